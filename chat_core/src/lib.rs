@@ -3,6 +3,7 @@ pub mod utils;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::fmt;
 use utoipa::ToSchema;
 
 #[derive(FromRow, Debug, Clone, ToSchema, Deserialize, Serialize, PartialEq)]
@@ -14,6 +15,10 @@ pub struct User {
     #[sqlx(default)]
     #[serde(skip)]
     pub password_hash: Option<String>,
+    /// bot accounts act through api keys and can't sign in
+    #[sqlx(default)]
+    #[serde(default)]
+    pub is_bot: bool,
     pub created_at: DateTime<Local>,
 }
 #[derive(FromRow, Debug, ToSchema, Clone, Deserialize, Serialize, PartialEq)]
@@ -22,12 +27,47 @@ pub struct WorkSpace {
     pub name: String,
     pub owner_id: i64,
     pub created_at: DateTime<Local>,
+    /// set while the workspace waits to be purged
+    #[sqlx(default)]
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Local>>,
+    /// iana name of the default timezone of the members
+    #[sqlx(default)]
+    #[serde(default)]
+    pub timezone: String,
 }
 #[derive(FromRow, Debug, ToSchema, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub is_bot: bool,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+/// profile of a user as seen by the members of their workspaces
+#[derive(FromRow, Debug, ToSchema, Clone, Deserialize, Serialize, PartialEq)]
+pub struct UserProfile {
+    pub id: i64,
+    pub fullname: String,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub email: String,
+    pub display_name: Option<String>,
+    pub title: Option<String>,
+    /// iana name, e.g. Europe/Berlin
+    pub timezone: Option<String>,
+    /// url of the avatar file, see `/api/upload`
+    pub avatar: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expires_at: Option<DateTime<Local>>,
 }
 
 // fn b64_decode<'de, S>(deserializer: S) -> Result< DateTime<FixedOffset>, S::Error>
@@ -67,6 +107,54 @@ pub enum ChatType {
     PrivateChannel,
     PublicChannel,
 }
+/// role of a user in their workspace, see `workspace_role` in the migrations
+#[derive(Debug, Clone, Copy, ToSchema, Deserialize, Serialize, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "workspace_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    Owner,
+    Admin,
+    Member,
+    /// only sees the chats they were added to
+    Guest,
+}
+/// role of a member in a chat, see `chat_role` in the migrations
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Deserialize, Serialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+    Owner,
+    /// removes and mutes members, deletes messages and locks the chat
+    Moderator,
+    #[default]
+    Member,
+}
+/// who may post in a chat, see `chat_posting_policy` in the migrations
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Deserialize, Serialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "chat_posting_policy", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum PostingPolicy {
+    #[default]
+    Everyone,
+    /// announcement channels, only owners and admins post
+    Admins,
+}
+/// what members of a chat are notified about by default
+#[derive(
+    Debug, Clone, Copy, Default, ToSchema, Deserialize, Serialize, PartialEq, Eq, sqlx::Type,
+)]
+#[sqlx(type_name = "notification_level", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    Mentions,
+    None,
+}
 #[derive(FromRow, Debug, Clone, ToSchema, Deserialize, Serialize, PartialEq)]
 pub struct Chat {
     pub id: i64,
@@ -75,6 +163,39 @@ pub struct Chat {
     pub r#type: ChatType,
     pub members: Vec<i64>,
     pub created_at: DateTime<Local>,
+    /// archived chats are read-only
+    #[sqlx(default)]
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Local>>,
+    /// deleted chats can be restored until they are purged
+    #[sqlx(default)]
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Local>>,
+    /// locked chats only take messages from moderators and owners
+    #[sqlx(default)]
+    #[serde(default)]
+    pub locked_at: Option<DateTime<Local>>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// purpose of the chat
+    #[sqlx(default)]
+    #[serde(default)]
+    pub description: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub posting_policy: PostingPolicy,
+    /// whether members other than owners and admins add people
+    #[sqlx(default)]
+    #[serde(default = "default_true")]
+    pub members_can_invite: bool,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub notification_level: NotificationLevel,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 /*
@@ -97,6 +218,10 @@ pub struct Message {
     pub sender_id: i64,
     pub content: String,
     pub files: Vec<String>,
+    /// sent by a bot account
+    #[sqlx(default)]
+    #[serde(default)]
+    pub is_bot: bool,
     pub created_at: DateTime<Local>,
 }
 
@@ -109,7 +234,50 @@ impl User {
             fullname: fullname.to_string(),
             email: email.to_string(),
             password_hash: None,
+            is_bot: false,
             created_at: DateTime::from(Utc::now()),
         }
     }
 }
+
+impl WorkspaceRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Admin => "admin",
+            Self::Member => "member",
+            Self::Guest => "guest",
+        }
+    }
+}
+
+impl fmt::Display for WorkspaceRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Moderator => "moderator",
+            Self::Member => "member",
+        }
+    }
+
+    /// owners rank highest
+    pub fn rank(&self) -> u8 {
+        match self {
+            Self::Owner => 2,
+            Self::Moderator => 1,
+            Self::Member => 0,
+        }
+    }
+}
+
+impl fmt::Display for ChatRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -41,10 +41,11 @@ where
             }
         };
     info!("{:?}", parts);
-    let req = match state.verify(&token) {
-        Ok(user) => {
+    let req = match state.verify(&token).await {
+        Ok(claims) => {
             let mut req = Request::from_parts(parts, body);
-            req.extensions_mut().insert(user);
+            req.extensions_mut().insert(claims.user.clone());
+            req.extensions_mut().insert(claims);
             req
         }
         Err(e) => {
@@ -60,7 +61,7 @@ mod tests {
     use super::*;
     use std::sync::Arc;
 
-    use crate::utils::{DecodingKey, EncodingKey};
+    use crate::utils::{DecodingKey, EncodingKey, UserClaims};
     use crate::User;
     use anyhow::Result;
     use axum::body::Body;
@@ -78,7 +79,7 @@ mod tests {
     }
     impl TokenVerify for AppState {
         type Error = ();
-        fn verify(&self, token: &str) -> std::result::Result<User, Self::Error> {
+        async fn verify(&self, token: &str) -> std::result::Result<UserClaims, Self::Error> {
             self.0.dk.verify(token).map_err(|_| ())
         }
     }
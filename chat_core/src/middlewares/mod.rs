@@ -1,6 +1,7 @@
 use axum::middleware::from_fn;
 use axum::Router;
 use std::fmt;
+use std::future::Future;
 use tower::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer};
@@ -9,17 +10,19 @@ use tracing::Level;
 
 mod auth;
 mod request_id;
+mod scope;
 mod server_time;
 
 use request_id::set_request;
 use server_time::ServerTimeLayer;
 
-use crate::User;
+use crate::utils::UserClaims;
 pub use auth::verify_token;
+pub use scope::{RequireScope, RequireScopeLayer};
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<UserClaims, Self::Error>> + Send;
 }
 pub const REQUEST_ID_HEADER: &str = "x-request-id";
 pub const SERVER_TIME_HEADER: &str = "x-server-time";
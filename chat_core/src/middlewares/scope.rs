@@ -0,0 +1,183 @@
+use crate::utils::{Scope, UserClaims};
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// reject requests whose token lacks the scope, must run after `verify_token`
+#[derive(Debug, Clone, Copy)]
+pub struct RequireScopeLayer {
+    read: Scope,
+    write: Scope,
+}
+
+impl RequireScopeLayer {
+    pub fn new(scope: Scope) -> Self {
+        Self {
+            read: scope,
+            write: scope,
+        }
+    }
+
+    /// `read` is required for GET and HEAD requests, `write` for all the others
+    pub fn read_write(read: Scope, write: Scope) -> Self {
+        Self { read, write }
+    }
+}
+
+impl<S> Layer<S> for RequireScopeLayer {
+    type Service = RequireScope<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireScope {
+            inner,
+            layer: *self,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireScope<S> {
+    inner: S,
+    layer: RequireScopeLayer,
+}
+
+impl<S> Service<Request> for RequireScope<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let scope = match *request.method() {
+            Method::GET | Method::HEAD => self.layer.read,
+            _ => self.layer.write,
+        };
+        let granted = request
+            .extensions()
+            .get::<UserClaims>()
+            .is_some_and(|claims| claims.has_scope(scope));
+        if !granted {
+            let msg = format!("token lacks the {} scope", scope);
+            warn!(msg);
+            return Box::pin(async move { Ok((StatusCode::FORBIDDEN, msg).into_response()) });
+        }
+        let future = self.inner.call(request);
+        Box::pin(future)
+    }
+}
+
+/// the claims of the verified token, available behind `verify_token`
+#[async_trait]
+impl<S> FromRequestParts<S> for UserClaims
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserClaims>()
+            .cloned()
+            .ok_or((StatusCode::UNAUTHORIZED, "token claims are missing"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::User;
+    use anyhow::Result;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    async fn handler() -> impl IntoResponse {
+        (StatusCode::OK, "OK")
+    }
+
+    async fn scopes_handler(claims: UserClaims) -> impl IntoResponse {
+        let scopes: Vec<_> = claims.scopes.unwrap_or_default();
+        format!("{scopes:?}")
+    }
+
+    fn request(method: Method, claims: Option<UserClaims>) -> Result<Request> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri("/chats")
+            .body(Body::empty())?;
+        if let Some(claims) = claims {
+            req.extensions_mut().insert(claims);
+        }
+        Ok(req)
+    }
+
+    #[tokio::test]
+    async fn require_scope_layer_should_work() -> Result<()> {
+        let app = Router::new()
+            .route("/chats", get(handler).post(handler))
+            .layer(RequireScopeLayer::read_write(
+                Scope::ChatsRead,
+                Scope::ChatsWrite,
+            ));
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let read_only = UserClaims::from(user.clone()).with_scopes(vec![Scope::ChatsRead]);
+
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, Some(read_only.clone()))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, Some(read_only))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // user sessions are unrestricted
+        let res = app
+            .clone()
+            .oneshot(request(Method::POST, Some(user.into()))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.oneshot(request(Method::GET, None)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_claims_extractor_should_work() -> Result<()> {
+        let app = Router::new().route("/chats", get(scopes_handler));
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let claims = UserClaims::from(user).with_scopes(vec![Scope::EventsRead]);
+
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, Some(claims))?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body.as_ref(), b"[EventsRead]");
+
+        let res = app.oneshot(request(Method::GET, None)?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
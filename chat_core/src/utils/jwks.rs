@@ -0,0 +1,106 @@
+use jwt_simple::prelude::*;
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use utoipa::ToSchema;
+
+use super::DecodingKey;
+
+/// JSON Web Key Set (RFC 7517) published at `/.well-known/jwks.json`
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// an Ed25519 public key in the OKP format of RFC 8037
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub kid: Option<String>,
+    pub alg: Option<String>,
+    #[serde(rename = "use")]
+    pub usage: Option<String>,
+}
+
+impl DecodingKey {
+    pub fn jwks(&self) -> Jwks {
+        let keys = self
+            .keys()
+            .iter()
+            .map(|key| Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                x: Base64UrlSafeNoPadding::encode_to_string(key.to_bytes())
+                    .expect("encode public key should work"),
+                kid: key.key_id().clone(),
+                alg: Some("EdDSA".to_string()),
+                usage: Some("sig".to_string()),
+            })
+            .collect();
+        Jwks { keys }
+    }
+
+    /// build a keyring from a JWKS document, keys other than Ed25519 are ignored
+    pub fn from_jwks(jwks: &Jwks) -> Result<Self, jwt_simple::Error> {
+        let mut keys = vec![];
+        for jwk in &jwks.keys {
+            if jwk.kty != "OKP" || jwk.crv != "Ed25519" {
+                continue;
+            }
+            if jwk.usage.as_deref().is_some_and(|v| v != "sig") {
+                continue;
+            }
+            let raw = Base64UrlSafeNoPadding::decode_to_vec(&jwk.x, None)?;
+            let mut key = Ed25519PublicKey::from_bytes(&raw)?;
+            match &jwk.kid {
+                Some(kid) => key = key.with_key_id(kid),
+                None => {
+                    key.create_key_id();
+                }
+            }
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            jwt_simple::reexports::anyhow::bail!("jwks contains no Ed25519 signing key");
+        }
+        Ok(Self::from_keys(keys))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::EncodingKey;
+    use crate::User;
+    use jwt_simple::reexports::serde_json;
+
+    #[test]
+    fn jwks_roundtrip_should_work() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let ek = EncodingKey::load(encoding_pem)?.with_kid("current");
+        let mut dk = DecodingKey::load_with_kid(decoding_pem, "current")?;
+        dk.add_key(
+            &Ed25519KeyPair::generate().public_key().to_pem(),
+            "previous",
+        )?;
+
+        let jwks = dk.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.keys[0].kid.as_deref(), Some("current"));
+        let json = serde_json::to_string(&jwks)?;
+        assert!(json.contains(r#""use":"sig""#));
+
+        let dk = DecodingKey::from_jwks(&serde_json::from_str(&json)?)?;
+        assert_eq!(dk.kids(), vec!["current", "previous"]);
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let claims = dk.verify(&ek.sign(user.clone())?)?;
+        assert_eq!(claims.user, user);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_jwks_should_fail() {
+        assert!(DecodingKey::from_jwks(&Jwks::default()).is_err());
+    }
+}
@@ -1,17 +1,70 @@
 use jwt_simple::prelude::*;
+use jwt_simple::JWTError;
 
+use super::Scope;
 use crate::User;
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+/// access tokens are short-lived, clients renew them with a refresh token
+pub const JWT_DURATION: u64 = 60 * 15;
 const JWT_ISSUER: &str = "chat_server";
 const JWT_AUDIENCE: &str = "chat_web";
+/// lifetime of the token handed out between the password and the second factor check
+pub const MFA_PENDING_DURATION: u64 = 60 * 5;
+const MFA_AUDIENCE: &str = "chat_mfa";
 
+/// custom claims of an access token
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserClaims {
+    #[serde(flatten)]
+    pub user: User,
+    /// session id, all access tokens minted from the same refresh token family share it
+    #[serde(default)]
+    pub sid: Option<String>,
+    /// token generation of the user, bumped when all sessions are logged out
+    #[serde(default)]
+    pub ver: i64,
+    /// restricted tokens like api keys only carry some scopes, `None` means unrestricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+    /// filled from the `jti` registered claim after verification
+    #[serde(skip)]
+    pub jti: String,
+    /// filled from the `exp` registered claim after verification
+    #[serde(skip)]
+    pub expires_at: u64,
+}
+
+/// claims of an "mfa pending" token, it is only accepted by the second factor exchange
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MfaClaims {
+    pub uid: i64,
+    /// filled from the `jti` registered claim after verification, the token is used only once
+    #[serde(skip)]
+    pub jti: String,
+    /// filled from the `exp` registered claim after verification
+    #[serde(skip)]
+    pub expires_at: u64,
+}
+
+/// the signing key, its `kid` is put into the header of every token
 pub struct EncodingKey(Ed25519KeyPair);
 
-#[allow(unused)]
-pub struct DecodingKey(Ed25519PublicKey);
+/// keyring of verification keys, several keys are accepted during a key rotation
+pub struct DecodingKey(Vec<Ed25519PublicKey>);
+
 impl EncodingKey {
+    /// load the key, the `kid` defaults to the thumbprint of the public key
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519KeyPair::from_pem(pem)?))
+        let key = Ed25519KeyPair::from_pem(pem)?;
+        let kid = key.public_key().create_key_id().to_string();
+        Ok(Self(key.with_key_id(&kid)))
+    }
+
+    pub fn with_kid(self, kid: &str) -> Self {
+        Self(self.0.with_key_id(kid))
+    }
+
+    pub fn kid(&self) -> &str {
+        self.0.key_id().as_deref().unwrap_or_default()
     }
 
     // pub fn sign(user: User, key: &EncodingKey) -> Result<String, AppError> {
@@ -21,27 +74,150 @@ impl EncodingKey {
     //     Ok(key.sign(claims)?)
     // }
 
-    pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
-        let user: User = user.into();
-        let claims = Claims::with_custom_claims(user, Duration::from_secs(JWT_DURATION));
-        let claims = claims.with_issuer(JWT_ISSUER).with_audience(JWT_AUDIENCE);
+    pub fn sign(&self, claims: impl Into<UserClaims>) -> Result<String, jwt_simple::Error> {
+        let claims: UserClaims = claims.into();
+        let claims = Claims::with_custom_claims(claims, Duration::from_secs(JWT_DURATION));
+        let claims = claims
+            .with_issuer(JWT_ISSUER)
+            .with_audience(JWT_AUDIENCE)
+            .with_jwt_id(uuid::Uuid::now_v7().to_string());
+        self.0.sign(claims)
+    }
+
+    /// sign a token proving the password check passed, it can't be used as an access token
+    pub fn sign_mfa_pending(&self, user_id: i64) -> Result<String, jwt_simple::Error> {
+        let claims = Claims::with_custom_claims(
+            MfaClaims {
+                uid: user_id,
+                jti: String::new(),
+                expires_at: 0,
+            },
+            Duration::from_secs(MFA_PENDING_DURATION),
+        );
+        let claims = claims
+            .with_issuer(JWT_ISSUER)
+            .with_audience(MFA_AUDIENCE)
+            .with_jwt_id(uuid::Uuid::now_v7().to_string());
         self.0.sign(claims)
     }
 }
 
 impl DecodingKey {
+    /// load a single key, the `kid` defaults to the thumbprint of the public key
     pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
-        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
-    }
-    #[allow(unused)]
-    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
-        let mut opts = VerificationOptions {
-            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
-            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUDIENCE])),
-            ..Default::default()
+        let mut key = Ed25519PublicKey::from_pem(pem)?;
+        key.create_key_id();
+        Ok(Self(vec![key]))
+    }
+
+    pub fn load_with_kid(pem: &str, kid: &str) -> Result<Self, jwt_simple::Error> {
+        let key = Ed25519PublicKey::from_pem(pem)?.with_key_id(kid);
+        Ok(Self(vec![key]))
+    }
+
+    /// add another accepted key to the keyring, e.g. the key of the previous rotation
+    pub fn add_key(&mut self, pem: &str, kid: &str) -> Result<(), jwt_simple::Error> {
+        let key = Ed25519PublicKey::from_pem(pem)?.with_key_id(kid);
+        self.0.retain(|v| v.key_id().as_deref() != Some(kid));
+        self.0.push(key);
+        Ok(())
+    }
+
+    /// merge the keys of another keyring, keys with the same `kid` are replaced
+    pub fn merge(&mut self, other: DecodingKey) {
+        for key in other.0 {
+            self.0.retain(|v| v.key_id() != key.key_id());
+            self.0.push(key);
+        }
+    }
+
+    pub fn kids(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter_map(|v| v.key_id().as_deref())
+            .collect()
+    }
+
+    pub fn verify(&self, token: &str) -> Result<UserClaims, jwt_simple::Error> {
+        let claims = self
+            .key_for(token)?
+            .verify_token::<UserClaims>(token, Some(verification_options(JWT_AUDIENCE)))?;
+        let mut user_claims = claims.custom;
+        user_claims.jti = claims.jwt_id.unwrap_or_default();
+        user_claims.expires_at = claims.expires_at.map(|v| v.as_secs()).unwrap_or_default();
+        Ok(user_claims)
+    }
+
+    /// verify an "mfa pending" token
+    pub fn verify_mfa_pending(&self, token: &str) -> Result<MfaClaims, jwt_simple::Error> {
+        let claims = self
+            .key_for(token)?
+            .verify_token::<MfaClaims>(token, Some(verification_options(MFA_AUDIENCE)))?;
+        let mut mfa_claims = claims.custom;
+        mfa_claims.jti = claims.jwt_id.unwrap_or_default();
+        mfa_claims.expires_at = claims.expires_at.map(|v| v.as_secs()).unwrap_or_default();
+        Ok(mfa_claims)
+    }
+
+    fn key_for(&self, token: &str) -> Result<&Ed25519PublicKey, jwt_simple::Error> {
+        let metadata = Token::decode_metadata(token)?;
+        let key = match metadata.key_id() {
+            Some(kid) => self
+                .0
+                .iter()
+                .find(|v| v.key_id().as_deref() == Some(kid))
+                .ok_or(JWTError::KeyIdentifierMismatch)?,
+            // tokens signed before key rotation was introduced carry no kid
+            None => self.0.first().ok_or(JWTError::MissingJWTKeyIdentifier)?,
         };
-        let claims = self.0.verify_token::<User>(token, Some(opts))?;
-        Ok(claims.custom)
+        Ok(key)
+    }
+
+    pub(crate) fn keys(&self) -> &[Ed25519PublicKey] {
+        &self.0
+    }
+
+    pub(crate) fn from_keys(keys: Vec<Ed25519PublicKey>) -> Self {
+        Self(keys)
+    }
+}
+
+fn verification_options(audience: &str) -> VerificationOptions {
+    VerificationOptions {
+        allowed_issuers: Some(HashSet::from_strings(&[JWT_ISSUER])),
+        allowed_audiences: Some(HashSet::from_strings(&[audience])),
+        ..Default::default()
+    }
+}
+
+impl UserClaims {
+    pub fn new(user: User, sid: Option<String>, ver: i64) -> Self {
+        Self {
+            user,
+            sid,
+            ver,
+            scopes: None,
+            jti: String::new(),
+            expires_at: 0,
+        }
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<Scope>) -> Self {
+        self.scopes = Some(scopes);
+        self
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scope.granted_by(scopes),
+            None => true,
+        }
+    }
+}
+
+impl From<User> for UserClaims {
+    fn from(user: User) -> Self {
+        Self::new(user, None, 0)
     }
 }
 // impl Deref for EncodingKey {
@@ -69,11 +245,87 @@ mod tests {
         let dk = DecodingKey::load(decoding_pem)?;
         let user = User::new(1, "zhang", "qazwsx2228@163.com");
         let token = ek.sign(user.clone())?;
-        let user2 = dk.verify(&token)?;
+        let claims = dk.verify(&token)?;
         println!("{token:?}");
         println!("user = {:?}", user);
-        println!("user2 = {:?}", user2);
-        assert_eq!(user, user2);
+        println!("user2 = {:?}", claims.user);
+        assert_eq!(user, claims.user);
+        assert!(!claims.jti.is_empty());
+        assert!(claims.expires_at > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jwt_key_rotation_should_work() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let old_ek = EncodingKey::load(encoding_pem)?.with_kid("old");
+        let new_key = Ed25519KeyPair::generate();
+        let new_ek = EncodingKey::load(&new_key.to_pem())?.with_kid("new");
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let old_token = old_ek.sign(user.clone())?;
+        let new_token = new_ek.sign(user.clone())?;
+        assert_eq!(
+            Token::decode_metadata(&new_token)?.key_id(),
+            Some(new_ek.kid())
+        );
+
+        // during the rotation window both keys are accepted
+        let mut dk = DecodingKey::load_with_kid(&new_key.public_key().to_pem(), "new")?;
+        dk.add_key(decoding_pem, "old")?;
+        assert_eq!(dk.verify(&old_token)?.user, user);
+        assert_eq!(dk.verify(&new_token)?.user, user);
+
+        // once the old key is retired its tokens are rejected
+        let dk = DecodingKey::load_with_kid(&new_key.public_key().to_pem(), "new")?;
+        assert!(dk.verify(&old_token).is_err());
+        assert_eq!(dk.verify(&new_token)?.user, user);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jwt_default_kid_should_match() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let ek = EncodingKey::load(encoding_pem)?;
+        let dk = DecodingKey::load(decoding_pem)?;
+        assert_eq!(dk.kids(), vec![ek.kid()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jwt_sign_with_session_should_work() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let ek = EncodingKey::load(encoding_pem)?;
+        let dk = DecodingKey::load(decoding_pem)?;
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let claims = UserClaims::new(user, Some("session".to_string()), 3);
+        let token1 = ek.sign(claims.clone())?;
+        let token2 = ek.sign(claims)?;
+        let claims1 = dk.verify(&token1)?;
+        let claims2 = dk.verify(&token2)?;
+        assert_eq!(claims1.sid.as_deref(), Some("session"));
+        assert_eq!(claims1.ver, 3);
+        assert_ne!(claims1.jti, claims2.jti);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mfa_pending_token_should_not_be_an_access_token() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let ek = EncodingKey::load(encoding_pem)?;
+        let dk = DecodingKey::load(decoding_pem)?;
+        let token = ek.sign_mfa_pending(1)?;
+        let claims = dk.verify_mfa_pending(&token)?;
+        assert_eq!(claims.uid, 1);
+        assert!(!claims.jti.is_empty());
+        assert!(dk.verify(&token).is_err());
+
+        let user = User::new(1, "zhang", "qazwsx2228@163.com");
+        let token = ek.sign(user)?;
+        assert!(dk.verify_mfa_pending(&token).is_err());
         Ok(())
     }
 }
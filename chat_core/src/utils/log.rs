@@ -14,9 +14,11 @@ pub type LoggingLayer = Filtered<
     LevelFilter,
     Registry,
 >;
+/// timestamps of the logs use the timezone of the host, e.g. from the `TZ` variable
 pub fn local_time() -> OffsetTime<&'static [BorrowedFormatItem<'static>]> {
+    let offset = chrono::Local::now().offset().local_minus_utc();
     OffsetTime::new(
-        time::UtcOffset::from_hms(8, 0, 0).unwrap(),
+        time::UtcOffset::from_whole_seconds(offset).unwrap_or(time::UtcOffset::UTC),
         format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
     )
 }
@@ -1,10 +1,18 @@
+mod jwks;
 mod jwt;
 pub mod log;
+mod revoke;
+mod scope;
 
 use std::env;
 use std::path::PathBuf;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwks::{Jwk, Jwks};
+pub use jwt::{
+    DecodingKey, EncodingKey, MfaClaims, UserClaims, JWT_DURATION, MFA_PENDING_DURATION,
+};
+pub use revoke::{is_token_revoked, TOKEN_REVOKED_CHANNEL};
+pub use scope::Scope;
 
 const CHAT: &str = "chat_server";
 
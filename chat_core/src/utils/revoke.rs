@@ -0,0 +1,36 @@
+use sqlx::PgPool;
+
+use super::UserClaims;
+
+/// postgres channel used to broadcast revoked sessions to every server
+pub const TOKEN_REVOKED_CHANNEL: &str = "token_revoked";
+
+/// check the revocation list: the token's jti or session may be revoked,
+/// or the user may have logged out all sessions by bumping the token generation.
+/// a token also stops working when the user leaves, or is deactivated in, the workspace it was issued for
+pub async fn is_token_revoked(pool: &PgPool, claims: &UserClaims) -> Result<bool, sqlx::Error> {
+    let revoked: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+                SELECT 1 FROM revoked_tokens
+                WHERE jti = $1 OR (sid IS NOT NULL AND sid = $2)
+            )
+            OR NOT EXISTS (
+                SELECT 1 FROM users
+                WHERE id = $3 AND token_version = $4
+            )
+            OR NOT EXISTS (
+                SELECT 1 FROM workspace_members
+                WHERE user_id = $3 AND ws_id = $5 AND deactivated_at IS NULL
+            )
+        "#,
+    )
+    .bind(&claims.jti)
+    .bind(&claims.sid)
+    .bind(claims.user.id)
+    .bind(claims.ver)
+    .bind(claims.user.ws_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(revoked)
+}
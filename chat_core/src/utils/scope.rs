@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// permission carried by a restricted token, tokens without scopes are unrestricted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "chats:read")]
+    ChatsRead,
+    #[serde(rename = "chats:write")]
+    ChatsWrite,
+    #[serde(rename = "messages:read")]
+    MessagesRead,
+    #[serde(rename = "messages:write")]
+    MessagesWrite,
+    #[serde(rename = "files:read")]
+    FilesRead,
+    #[serde(rename = "files:write")]
+    FilesWrite,
+    /// subscribe to the event stream of the notify server
+    #[serde(rename = "events:read")]
+    EventsRead,
+    /// grants every other scope
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+const SCOPES: [(Scope, &str); 8] = [
+    (Scope::ChatsRead, "chats:read"),
+    (Scope::ChatsWrite, "chats:write"),
+    (Scope::MessagesRead, "messages:read"),
+    (Scope::MessagesWrite, "messages:write"),
+    (Scope::FilesRead, "files:read"),
+    (Scope::FilesWrite, "files:write"),
+    (Scope::EventsRead, "events:read"),
+    (Scope::Admin, "admin"),
+];
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        SCOPES
+            .iter()
+            .find(|(scope, _)| scope == self)
+            .map(|(_, name)| *name)
+            .unwrap_or_default()
+    }
+
+    /// whether a token with `scopes` is allowed to act with this scope
+    pub fn granted_by(&self, scopes: &[Scope]) -> bool {
+        scopes.contains(self) || scopes.contains(&Scope::Admin)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SCOPES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(scope, _)| *scope)
+            .ok_or_else(|| format!("unknown scope {s}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jwt_simple::reexports::serde_json;
+
+    #[test]
+    fn scope_should_roundtrip() -> anyhow::Result<()> {
+        for (scope, name) in SCOPES {
+            assert_eq!(scope.to_string(), name);
+            assert_eq!(name.parse::<Scope>(), Ok(scope));
+            assert_eq!(serde_json::to_string(&scope)?, format!("\"{name}\""));
+        }
+        assert!("chats:delete".parse::<Scope>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn admin_should_grant_every_scope() {
+        assert!(Scope::FilesRead.granted_by(&[Scope::Admin]));
+        assert!(Scope::FilesRead.granted_by(&[Scope::FilesRead]));
+        assert!(!Scope::FilesWrite.granted_by(&[Scope::FilesRead]));
+    }
+}
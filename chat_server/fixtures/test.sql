@@ -14,13 +14,25 @@ VALUES (1,'tchen1@acme.org','Tyr chen','$argon2id$v=19$m=19456,t=2,p=1$jR8CP5LE/
        (1,'tchen7@acme.org','Tbgb chen','$argon2id$v=19$m=19456,t=2,p=1$jR8CP5LE/eIhzvSAWH2buw$2IQS47j1gRUnyd3BjfBO+hEs2H8korHnjzPKFJNfkAc'),
        (1,'tchen8@acme.org','Tmk chen','$argon2id$v=19$m=19456,t=2,p=1$jR8CP5LE/eIhzvSAWH2buw$2IQS47j1gRUnyd3BjfBO+hEs2H8korHnjzPKFJNfkAc');
 
- INSERT INTO chats (ws_id, name ,type,members)
- VALUES (1,'general','public_channel','{1,2,3,4,5}'),
-        (1,'private','private_channel','{1,2,3}');
+INSERT INTO workspace_members(ws_id,user_id)
+SELECT ws_id, id FROM users WHERE id > 0;
 
-INSERT INTO chats (ws_id,type, members)
-VALUES  (1,'single','{1,2}'),
-        (1, 'group', '{1,3,4}');
+ INSERT INTO chats (ws_id, name ,type)
+ VALUES (1,'general','public_channel'),
+        (1,'private','private_channel');
+
+INSERT INTO chats (ws_id,type,member_key)
+VALUES  (1,'single','1,2'),
+        (1, 'group','1,3,4');
+
+INSERT INTO chat_members (chat_id, user_id)
+VALUES  (1,1),(1,2),(1,3),(1,4),(1,5),
+        (2,1),(2,2),(2,3),
+        (3,1),(3,2),
+        (4,1),(4,3),(4,4);
+
+UPDATE chat_members SET role = 'owner' WHERE user_id = 1 AND chat_id IN (1, 2, 4);
+UPDATE chat_members SET role = 'moderator' WHERE chat_id = 1 AND user_id = 3;
 
 
 INSERT INTO messages (chat_id, sender_id, content)
@@ -1,5 +1,5 @@
-use anyhow::bail;
-use chat_core::utils::chat_server_path;
+use anyhow::{bail, Context};
+use chat_core::utils::{chat_server_path, DecodingKey, EncodingKey};
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs::File;
@@ -9,6 +9,8 @@ use std::path::PathBuf;
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
     // pub host: String,
     // pub port: u16,
     // pub user: String,
@@ -21,11 +23,166 @@ pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
     pub base_dir: PathBuf,
+    /// days a deleted workspace can be restored before it is purged
+    #[serde(default = "default_workspace_grace_days")]
+    pub workspace_grace_days: u32,
+    /// days a deleted chat can be restored before it is purged with its messages
+    #[serde(default = "default_chat_retention_days")]
+    pub chat_retention_days: u32,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
     pub pk: String,
+    /// key id of the signing key, defaults to the thumbprint of the public key
+    #[serde(default)]
+    pub kid: Option<String>,
+    /// retired public keys which are still accepted during a key rotation
+    #[serde(default)]
+    pub previous_keys: Vec<PublicKeyConfig>,
+    /// reject signin until the email address has been verified
+    #[serde(default)]
+    pub require_email_verification: bool,
+    /// let signup join an existing workspace by its name, otherwise an invite is required
+    #[serde(default)]
+    pub allow_join_by_name: bool,
+    #[serde(default)]
+    pub signin_throttle: SigninThrottleConfig,
+    /// single sign-on through an OpenID Connect provider, disabled when not set
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+}
+/// failed signins lock the email or ip for `base_lockout * 2^n` seconds once the limit is reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SigninThrottleConfig {
+    pub max_email_failures: i32,
+    pub max_ip_failures: i32,
+    /// lockout in seconds after the limit is reached, doubled with every further failure
+    pub base_lockout: i64,
+    pub max_lockout: i64,
+    /// failures older than this many seconds are forgotten
+    pub failure_window: i64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// the provider metadata is discovered from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// registered at the provider, points to `/api/oidc/callback`
+    pub redirect_uri: String,
+    /// new users are put into the workspace of the first rule matching their email domain,
+    /// users without a matching rule are rejected
+    #[serde(default)]
+    pub domains: Vec<OidcDomainRule>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcDomainRule {
+    /// email domain, `*` matches every domain
+    pub domain: String,
+    /// name of the workspace, created if it does not exist
+    pub workspace: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicKeyConfig {
+    pub kid: String,
+    pub pk: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailConfig {
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    /// base url of the web client, used to build the links sent by mail
+    #[serde(default = "default_mail_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub transport: MailTransport,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MailTransport {
+    Smtp {
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    },
+    /// keep mails in memory, and write them into `dir` if it is set
+    Outbox { dir: Option<PathBuf> },
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: default_mail_from(),
+            base_url: default_mail_base_url(),
+            transport: MailTransport::default(),
+        }
+    }
+}
+impl Default for SigninThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_email_failures: 5,
+            max_ip_failures: 20,
+            base_lockout: 30,
+            max_lockout: 60 * 60,
+            failure_window: 60 * 15,
+        }
+    }
+}
+impl Default for MailTransport {
+    fn default() -> Self {
+        Self::Outbox { dir: None }
+    }
+}
+fn default_workspace_grace_days() -> u32 {
+    30
+}
+fn default_chat_retention_days() -> u32 {
+    30
+}
+fn default_mail_from() -> String {
+    "chat <noreply@chat.local>".to_string()
+}
+fn default_mail_base_url() -> String {
+    "http://localhost:6688".to_string()
+}
+
+impl AuthConfig {
+    pub fn encoding_key(&self) -> anyhow::Result<EncodingKey> {
+        let ek = EncodingKey::load(&self.sk).context("load sk key failed")?;
+        Ok(match &self.kid {
+            Some(kid) => ek.with_kid(kid),
+            None => ek,
+        })
+    }
+    pub fn decoding_key(&self) -> anyhow::Result<DecodingKey> {
+        let mut dk = match &self.kid {
+            Some(kid) => DecodingKey::load_with_kid(&self.pk, kid),
+            None => DecodingKey::load(&self.pk),
+        }
+        .context("load pk key failed")?;
+        for key in &self.previous_keys {
+            dk.add_key(&key.pk, &key.kid)
+                .with_context(|| format!("load previous pk key {} failed", key.kid))?;
+        }
+        Ok(dk)
+    }
+}
+
+impl OidcConfig {
+    /// workspace for a new user with this email
+    pub fn workspace_for(&self, email: &str) -> Option<&str> {
+        let domain = email.rsplit_once('@')?.1;
+        self.domains
+            .iter()
+            .find(|rule| rule.domain == "*" || rule.domain.eq_ignore_ascii_case(domain))
+            .map(|rule| rule.workspace.as_str())
+    }
 }
 
 impl AppConfig {
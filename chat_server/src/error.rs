@@ -15,13 +15,23 @@ pub enum AppError {
     SqlxError(#[from] sqlx::Error),
     #[error("password error: {0}")]
     PassWordError(#[from] argon2::password_hash::Error),
+    #[error("invalid password")]
+    InvalidPassword,
     #[error("jwt error: {0}")]
     JWTError(#[from] jwt_simple::Error),
     #[error("Create chat error: {0}")]
     CreateChatError(String),
 
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
     #[error("Not found: {0}")]
     NotFound(String),
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("http header parse error:{0}")]
@@ -29,10 +39,48 @@ pub enum AppError {
 
     #[error("Email Already Exists :{0}")]
     EmailAlreadyExists(String),
+    #[error("workspace {0} already exists, joining it requires an invite")]
+    WorkspaceAlreadyExists(String),
+    #[error("workspace name {0} is already taken")]
+    WorkspaceNameTaken(String),
+    #[error("update workspace error: {0}")]
+    UpdateWorkspaceError(String),
+    #[error("update profile error: {0}")]
+    UpdateProfileError(String),
+    #[error("account is deactivated")]
+    UserDeactivated,
+    #[error("workspace {0} is scheduled for deletion")]
+    WorkspaceDeleted(i64),
+    #[error("create invite error: {0}")]
+    CreateInviteError(String),
+    #[error("create bot error: {0}")]
+    CreateBotError(String),
+    #[error("update chat error: {0}")]
+    UpdateChatError(String),
+    #[error("chat {0} is locked")]
+    ChatLocked(i64),
+    #[error("muted in this chat until {0}")]
+    ChatMuted(String),
+    #[error("chat {0} is archived")]
+    ChatArchived(i64),
+    #[error("chat member error: {0}")]
+    ChatMemberError(String),
     #[error("create message error :{0}")]
     CreateMessageError(String),
     #[error("{0}")]
     ChatFileError(String),
+    #[error("send mail error: {0}")]
+    MailError(String),
+    #[error("mfa error: {0}")]
+    MfaError(String),
+    #[error("invalid scope: {0}")]
+    InvalidScope(String),
+    #[error("scim error: {0}")]
+    ScimError(String),
+    #[error("oidc error: {0}")]
+    OidcError(String),
+    #[error("too many failed signin attempts, retry after {0} seconds")]
+    SigninLocked(i64),
 }
 impl ErrOutput {
     pub(crate) fn new(error: impl Into<String>) -> Self {
@@ -41,20 +89,55 @@ impl ErrOutput {
         }
     }
 }
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response<axum::body::Body> {
-        let state = match &self {
+impl AppError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
             Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::PassWordError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::InvalidPassword => StatusCode::FORBIDDEN,
             Self::JWTError(_) => StatusCode::FORBIDDEN,
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::WorkspaceAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::WorkspaceNameTaken(_) => StatusCode::CONFLICT,
+            Self::UpdateWorkspaceError(_) => StatusCode::BAD_REQUEST,
+            Self::WorkspaceDeleted(_) => StatusCode::GONE,
+            Self::UserDeactivated => StatusCode::FORBIDDEN,
+            Self::UpdateProfileError(_) => StatusCode::BAD_REQUEST,
             Self::CreateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::ChatMemberError(_) => StatusCode::BAD_REQUEST,
+            Self::ChatArchived(_) => StatusCode::FORBIDDEN,
+            Self::ChatLocked(_) => StatusCode::FORBIDDEN,
+            Self::ChatMuted(_) => StatusCode::FORBIDDEN,
+            Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::CreateBotError(_) => StatusCode::BAD_REQUEST,
+            Self::CreateInviteError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidToken(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidPath(_) => StatusCode::BAD_REQUEST,
             Self::IoError(_) => StatusCode::BAD_REQUEST,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
-        };
-        (state, Json(ErrOutput::new(self.to_string()))).into_response()
+            Self::MailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::MfaError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidScope(_) => StatusCode::BAD_REQUEST,
+            Self::OidcError(_) => StatusCode::UNAUTHORIZED,
+            Self::ScimError(_) => StatusCode::BAD_REQUEST,
+            Self::SigninLocked(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response<axum::body::Body> {
+        let state = self.status();
+        let mut response = (state, Json(ErrOutput::new(self.to_string()))).into_response();
+        if let Self::SigninLocked(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(http::header::RETRY_AFTER, retry_after.into());
+        }
+        response
     }
 }
@@ -0,0 +1,293 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+
+use crate::handlers::AuthOutput;
+use crate::models::{
+    ChangePassword, ForgotPassword, ResetPassword, UpdateProfile, UpdateUser, VerifyEmail,
+};
+use crate::{AppError, AppState, ErrOutput};
+use chat_core::{User, UserProfile};
+
+#[utoipa::path(
+    post,
+    path = "/api/password/forgot",
+    responses(
+        (status = 202, description = "Reset link sent if the email belongs to an account"),
+    )
+)]
+pub(crate) async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ForgotPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    // same answer for unknown addresses, so accounts can't be enumerated
+    state.request_password_reset(&input.email).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/password/reset",
+    responses(
+        (status = 204, description = "Password changed, all sessions logged out"),
+        (status = 400, description = "Invalid or expired token", body = ErrOutput),
+    )
+)]
+pub(crate) async fn reset_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_password(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/verify",
+    responses(
+        (status = 204, description = "Email address verified"),
+        (status = 400, description = "Invalid or expired token", body = ErrOutput),
+    )
+)]
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_email(&input.token).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/verify/resend",
+    responses(
+        (status = 202, description = "Verification mail sent"),
+        (status = 204, description = "Email address already verified"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn resend_verification_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    if state.is_email_verified(user.id).await? {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    state.send_email_verification(&user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/me",
+    responses(
+        (status = 200, description = "Account updated, a new email is pending until verified", body = User),
+        (status = 409, description = "Email already in use", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_me_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateUser>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.update_me(&user, &input).await?;
+    Ok(Json(user))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/me/profile",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Profile updated", body = UserProfile),
+        (status = 400, description = "Invalid profile field", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.update_profile(&user, &input).await?;
+    Ok(Json(profile))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/me/password",
+    responses(
+        (status = 200, description = "Password changed, other sessions logged out", body = AuthOutput),
+        (status = 403, description = "Current password is wrong", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn change_password_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.change_password(user.id, &input).await?;
+    // every token was revoked, hand the caller a fresh session
+    let output = state.issue_tokens(user).await?;
+    Ok(Json(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::signin_handler;
+    use crate::SigninUser;
+    use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn forgot_and_reset_password_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ForgotPassword {
+            email: "tchen1@acme.org".to_string(),
+        };
+        let ret = forgot_password_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let token = state
+            .outbox()
+            .and_then(|v| v.last_token_to("tchen1@acme.org"))
+            .expect("mail sent");
+
+        let input = ResetPassword {
+            token,
+            password: "Hunter42".to_string(),
+        };
+        let ret = reset_password_handler(State(state.clone()), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let ret = reset_password_handler(State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: ErrOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.error, "invalid token: token is invalid or expired");
+
+        let input = SigninUser::new("tchen1@acme.org", "Hunter42");
+        let ret = signin_handler(None, State(state), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgot_password_with_unknown_email_should_202() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ForgotPassword {
+            email: "alice@acme.org".to_string(),
+        };
+        let ret = forgot_password_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        assert!(state.outbox().expect("outbox").mails().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_email_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let ret = resend_verification_handler(Extension(user.clone()), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let token = state
+            .outbox()
+            .and_then(|v| v.last_token_to(&user.email))
+            .expect("mail sent");
+
+        let input = VerifyEmail { token };
+        let ret = verify_email_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let ret = resend_verification_handler(Extension(user), State(state))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_me_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateUser {
+            fullname: Some("Tyr".to_string()),
+            email: Some("tyr@acme.org".to_string()),
+        };
+        let ret = update_me_handler(Extension(user), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: User = serde_json::from_slice(&body)?;
+        assert_eq!(ret.fullname, "Tyr");
+        // the email only changes once the new address is verified
+        assert_eq!(ret.email, "tchen1@acme.org");
+        assert!(state
+            .outbox()
+            .and_then(|v| v.last_mail_to("tyr@acme.org"))
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_should_revoke_old_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let old = state.issue_tokens(user.clone()).await?;
+        let input = ChangePassword {
+            current_password: "123456".to_string(),
+            new_password: "Hunter42".to_string(),
+        };
+        let ret =
+            change_password_handler(Extension(user.clone()), State(state.clone()), Json(input))
+                .await?
+                .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: serde_json::Value = serde_json::from_slice(&body)?;
+        let token = auth["token"].as_str().expect("token");
+        assert!(state.verify(token).await.is_ok());
+        let old_token = serde_json::to_value(&old)?["token"]
+            .as_str()
+            .expect("token")
+            .to_string();
+        assert!(state.verify(&old_token).await.is_err());
+
+        let input = ChangePassword {
+            current_password: "123456".to_string(),
+            new_password: "Hunter43".to_string(),
+        };
+        let ret = change_password_handler(Extension(user), State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
@@ -1,17 +1,155 @@
-use axum::extract::State;
+use axum::extract::{ConnectInfo, State};
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
-use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
 
 use crate::models::{CreateUser, SigninUser};
 use crate::{AppError, AppState, ErrOutput};
+use chat_core::utils::{Jwks, Scope, UserClaims, JWT_DURATION, MFA_PENDING_DURATION};
+use chat_core::User;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use std::net::SocketAddr;
+use tracing::{info, warn};
 use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct AuthOutput {
+    /// short-lived access token
     token: String,
+    /// opaque token used to obtain a new pair from `/api/refresh`
+    refresh_token: String,
+    /// lifetime of the access token in seconds
+    expires_in: u64,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RefreshInput {
+    pub refresh_token: String,
+}
+/// returned by signin when the user has mfa enabled
+#[derive(Debug, Serialize, ToSchema, Deserialize)]
+pub struct MfaPendingOutput {
+    /// exchanged together with a code at `/api/signin/mfa`
+    mfa_token: String,
+    /// lifetime of the mfa token in seconds
+    expires_in: u64,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateScopedToken {
+    pub scopes: Vec<Scope>,
+}
+/// an access token restricted to some scopes, it belongs to the session it was minted from
+#[derive(Debug, Serialize, ToSchema, Deserialize)]
+pub struct ScopedTokenOutput {
+    token: String,
+    scopes: Vec<Scope>,
+    /// lifetime of the token in seconds
+    expires_in: u64,
+}
+/// an access token of the current session acting in another workspace
+#[derive(Debug, Serialize, ToSchema, Deserialize)]
+pub struct WorkspaceTokenOutput {
+    token: String,
+    ws_id: i64,
+    /// lifetime of the token in seconds
+    expires_in: u64,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MfaSigninInput {
+    pub mfa_token: String,
+    /// totp code or one of the recovery codes
+    pub code: String,
+}
+
+impl AppState {
+    /// start a new session for the user
+    pub(crate) async fn issue_tokens(&self, mut user: User) -> Result<AuthOutput, AppError> {
+        user.ws_id = self.active_workspace(&user).await?;
+        let sid = uuid::Uuid::now_v7().to_string();
+        let refresh_token = self.create_refresh_token(user.id, user.ws_id, &sid).await?;
+        let token = self.sign_access_token(user, &sid).await?;
+        Ok(AuthOutput {
+            token,
+            refresh_token,
+            expires_in: JWT_DURATION,
+        })
+    }
+    /// tokens for a user who has proven their identity, or the mfa challenge if mfa is enabled
+    pub(crate) async fn complete_signin(&self, user: User) -> Result<Response, AppError> {
+        // a deactivated user should not get as far as the second factor
+        self.active_workspace(&user).await?;
+        if self.is_mfa_enabled(user.id).await? {
+            let output = MfaPendingOutput {
+                mfa_token: self.ek.sign_mfa_pending(user.id)?,
+                expires_in: MFA_PENDING_DURATION,
+            };
+            return Ok((StatusCode::ACCEPTED, Json(output)).into_response());
+        }
+        // failures only reset once tokens are issued, the password alone does not lift a lockout
+        self.clear_signin_failures(&user.email).await?;
+        let output = self.issue_tokens(user).await?;
+        Ok((StatusCode::OK, Json(output)).into_response())
+    }
+    /// mint a token of the same session which is restricted to `scopes`
+    pub(crate) async fn sign_scoped_token(
+        &self,
+        claims: &UserClaims,
+        mut scopes: Vec<Scope>,
+    ) -> Result<ScopedTokenOutput, AppError> {
+        if claims.sid.is_none() {
+            return Err(AppError::PermissionDenied(
+                "scoped tokens can only be minted from a session".to_string(),
+            ));
+        }
+        if scopes.is_empty() {
+            return Err(AppError::InvalidScope(
+                "at least one scope is required".to_string(),
+            ));
+        }
+        if let Some(scope) = scopes.iter().find(|scope| !claims.has_scope(**scope)) {
+            return Err(AppError::InvalidScope(format!(
+                "token lacks the {scope} scope"
+            )));
+        }
+        scopes.sort_by_key(|scope| scope.as_str());
+        scopes.dedup();
+        let ver = self.token_version(claims.user.id).await?;
+        let claims = UserClaims::new(claims.user.clone(), claims.sid.clone(), ver)
+            .with_scopes(scopes.clone());
+        Ok(ScopedTokenOutput {
+            token: self.ek.sign(claims)?,
+            scopes,
+            expires_in: JWT_DURATION,
+        })
+    }
+    /// move the session to another workspace of the user, refreshing keeps the selection
+    pub(crate) async fn switch_workspace(
+        &self,
+        claims: &UserClaims,
+        ws_id: i64,
+    ) -> Result<WorkspaceTokenOutput, AppError> {
+        let Some(sid) = &claims.sid else {
+            return Err(AppError::PermissionDenied(
+                "only sessions can switch the workspace".to_string(),
+            ));
+        };
+        self.select_workspace(claims.user.id, sid, ws_id).await?;
+        let mut user = claims.user.clone();
+        user.ws_id = ws_id;
+        Ok(WorkspaceTokenOutput {
+            token: self.sign_access_token(user, sid).await?,
+            ws_id,
+            expires_in: JWT_DURATION,
+        })
+    }
+    pub(crate) async fn sign_access_token(
+        &self,
+        user: User,
+        sid: &str,
+    ) -> Result<String, AppError> {
+        let ver = self.token_version(user.id).await?;
+        let claims = UserClaims::new(user, Some(sid.to_string()), ver);
+        Ok(self.ek.sign(claims)?)
+    }
 }
 #[utoipa::path(
     post,
@@ -25,8 +163,11 @@ pub(crate) async fn signup_handler(
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
-    let body = Json(AuthOutput { token });
+    // the account is usable right away, a failed mail can be resent later
+    if let Err(e) = state.send_email_verification(&user).await {
+        warn!("send verification mail to {} failed: {}", user.email, e);
+    }
+    let body = Json(state.issue_tokens(user).await?);
     Ok((StatusCode::CREATED, body))
 }
 
@@ -34,42 +175,203 @@ pub(crate) async fn signup_handler(
     post,
     path = "/api/signin",
     responses(
-        (status = 201, description = "User signed in", body = AuthOutput),
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Password accepted, a second factor is required", body = MfaPendingOutput),
+        (status = 429, description = "Too many failed attempts, see the Retry-After header", body = ErrOutput),
     )
 )]
 pub(crate) async fn signin_handler(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     State(state): State<AppState>,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip().to_string());
+    // checked before the password, argon2 is too expensive to run for a locked client
+    if let Some(retry_after) = state.signin_lockout(&input.email, ip.as_deref()).await? {
+        return Err(AppError::SigninLocked(retry_after));
+    }
     let user = state.verify_user(&input).await?;
 
     match user {
         Some(user) => {
+            let user = match &input.invite {
+                Some(code) => state.accept_invite(user, code).await?,
+                None => user,
+            };
             info!("{:?}", user);
             let ss = &user.created_at;
             info!("{ss:?}");
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            if state.config.auth.require_email_verification
+                && !state.is_email_verified(user.id).await?
+            {
+                return Ok((
+                    StatusCode::FORBIDDEN,
+                    Json(ErrOutput::new("Email address is not verified")),
+                )
+                    .into_response());
+            }
+            state.complete_signin(user).await
+        }
+        None => {
+            state
+                .record_signin_failure(&input.email, ip.as_deref())
+                .await?;
+            Ok((
+                StatusCode::FORBIDDEN,
+                Json(ErrOutput::new("Invalid email or password")),
+            )
+                .into_response())
         }
-        None => Ok((
-            StatusCode::FORBIDDEN,
-            Json(ErrOutput::new("Invalid email or password")),
-        )
-            .into_response()),
     }
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/signin/mfa",
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 401, description = "Invalid or used mfa token, or invalid code", body = ErrOutput),
+        (status = 429, description = "Too many failed signins, retry later", body = ErrOutput),
+    )
+)]
+pub(crate) async fn signin_mfa_handler(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    Json(input): Json<MfaSigninInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip().to_string());
+    let claims = state
+        .dk
+        .verify_mfa_pending(&input.mfa_token)
+        .map_err(|_| AppError::Unauthorized("invalid mfa token".to_string()))?;
+    let Some(user) = state.find_user_by_id(claims.uid).await? else {
+        return Err(AppError::Unauthorized("user not found".to_string()));
+    };
+    // wrong codes count against the same lockout as wrong passwords
+    if let Some(retry_after) = state.signin_lockout(&user.email, ip.as_deref()).await? {
+        return Err(AppError::SigninLocked(retry_after));
+    }
+    // spend the token before the code, so a replayed token can't burn recovery codes
+    if !state.consume_mfa_token(&claims).await? {
+        return Err(AppError::Unauthorized("mfa token already used".to_string()));
+    }
+    if !state.verify_mfa_code(&user, &input.code).await? {
+        state
+            .record_signin_failure(&user.email, ip.as_deref())
+            .await?;
+        return Err(AppError::Unauthorized("invalid mfa code".to_string()));
+    }
+    state.clear_signin_failures(&user.email).await?;
+    Ok(Json(state.issue_tokens(user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    responses(
+        (status = 200, description = "Token refreshed", body = AuthOutput),
+        (status = 401, description = "Invalid, expired or reused refresh token", body = ErrOutput),
+    )
+)]
+pub(crate) async fn refresh_handler(
+    State(state): State<AppState>,
+    Json(input): Json<RefreshInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let rotated = state.rotate_refresh_token(&input.refresh_token).await?;
+    let Some(mut user) = state.find_user_by_id(rotated.user_id).await? else {
+        return Err(AppError::Unauthorized("user not found".to_string()));
+    };
+    // stay in the workspace of the session while the user is still an active member of it
+    user.ws_id = match rotated.ws_id {
+        Some(ws_id) if state.is_workspace_member(ws_id, user.id).await? => ws_id,
+        _ => state.active_workspace(&user).await?,
+    };
+    let token = state.sign_access_token(user, &rotated.family_id).await?;
+    Ok(Json(AuthOutput {
+        token,
+        refresh_token: rotated.token,
+        expires_in: JWT_DURATION,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    responses(
+        (status = 204, description = "Current session logged out"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn logout_handler(
+    Extension(claims): Extension<UserClaims>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_session(&claims).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/logout/all",
+    responses(
+        (status = 204, description = "All sessions of the user logged out"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn logout_all_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_all_sessions(user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    responses(
+        (status = 201, description = "Restricted token of the current session", body = ScopedTokenOutput),
+        (status = 400, description = "Scope is unknown or not held by the caller", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_scoped_token_handler(
+    claims: UserClaims,
+    State(state): State<AppState>,
+    Json(input): Json<CreateScopedToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let output = state.sign_scoped_token(&claims, input.scopes).await?;
+    Ok((StatusCode::CREATED, Json(output)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys accepted for access tokens", body = Jwks),
+    )
+)]
+pub(crate) async fn jwks_handler(State(state): State<AppState>) -> Json<Jwks> {
+    Json(state.dk.jwks())
+}
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ErrOutput;
     use anyhow::Result;
+    use chat_core::middlewares::TokenVerify;
     use http_body_util::BodyExt;
 
     #[tokio::test]
     async fn signup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("none", "qazwsx2228@163.com", "zhang", "Hunter42");
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state.clone()), Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::CREATED);
@@ -79,6 +381,10 @@ mod tests {
         let ret: AuthOutput = serde_json::from_slice(&bytes)?;
         println!("{:?}", ret);
         assert_ne!(ret.token, "");
+        let mail = state
+            .outbox()
+            .and_then(|v| v.last_mail_to("qazwsx2228@163.com"));
+        assert!(mail.is_some());
         Ok(())
     }
 
@@ -90,13 +396,280 @@ mod tests {
         // let user = CreateUser::new("none", "qazwsx2228@163.com", "zhang", "Hunter42");
         // User::create(&user, &state.pool).await?;
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(None, State(state), Json(input))
             .await?
             .into_response();
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         println!("{:?}", ret);
         assert_ne!(ret.token, "");
+        assert_ne!(ret.refresh_token, "");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_with_mfa_should_need_code() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        state.enroll_mfa(&user).await?;
+        let code = state.current_totp_code(&user).await?;
+        let recovery = state.confirm_mfa(&user, &code).await?;
+
+        let input = SigninUser::new("tchen1@acme.org", "123456");
+        let ret = signin_handler(None, State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let pending: MfaPendingOutput = serde_json::from_slice(&body)?;
+        // the pending token is no access token
+        assert!(state.verify(&pending.mfa_token).await.is_err());
+
+        let input = MfaSigninInput {
+            mfa_token: pending.mfa_token.clone(),
+            code: "000000".to_string(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+
+        // a wrong code spends the pending token as well
+        let input = MfaSigninInput {
+            mfa_token: pending.mfa_token.clone(),
+            code: recovery.codes[0].clone(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+
+        let mfa_token = state.ek.sign_mfa_pending(user.id)?;
+        let input = MfaSigninInput {
+            mfa_token: mfa_token.clone(),
+            code: recovery.codes[0].clone(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+        assert_eq!(state.verify(&auth.token).await?.user.id, 1);
+
+        // the pending token is only exchanged once, and a replay doesn't burn a recovery code
+        let input = MfaSigninInput {
+            mfa_token,
+            code: recovery.codes[1].clone(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        assert!(state.verify_mfa_code(&user, &recovery.codes[1]).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrong_mfa_codes_should_lock_signin() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        state.enroll_mfa(&user).await?;
+        let code = state.current_totp_code(&user).await?;
+        let recovery = state.confirm_mfa(&user, &code).await?;
+        let max = state.config.auth.signin_throttle.max_email_failures;
+        for _ in 1..max {
+            let input = MfaSigninInput {
+                mfa_token: state.ek.sign_mfa_pending(user.id)?,
+                code: "000000".to_string(),
+            };
+            let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+                .await
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        }
+        // the password alone does not reset the failed codes
+        let input = SigninUser::new("tchen1@acme.org", "123456");
+        let ret = signin_handler(None, State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let input = MfaSigninInput {
+            mfa_token: state.ek.sign_mfa_pending(user.id)?,
+            code: "000000".to_string(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+
+        // even the right code is rejected while locked
+        let input = MfaSigninInput {
+            mfa_token: state.ek.sign_mfa_pending(user.id)?,
+            code: recovery.codes[0].clone(),
+        };
+        let ret = signin_mfa_handler(None, State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn switched_workspace_should_survive_refresh() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let auth = state.issue_tokens(user).await?;
+        let claims = state.verify(&auth.token).await?;
+        let foo = state
+            .find_workspace_by_name("foo")
+            .await?
+            .expect("workspace should exist");
+        let ret = state.switch_workspace(&claims, foo.id).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES ($1, 1)")
+            .bind(foo.id)
+            .execute(&state.pool)
+            .await?;
+        let switched = state.switch_workspace(&claims, foo.id).await?;
+        let switched_claims = state.verify(&switched.token).await?;
+        assert_eq!(switched_claims.user.ws_id, foo.id);
+        assert_eq!(switched_claims.sid, claims.sid);
+
+        let input = RefreshInput {
+            refresh_token: auth.refresh_token,
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let refreshed: AuthOutput = serde_json::from_slice(&body)?;
+        assert_eq!(state.verify(&refreshed.token).await?.user.ws_id, foo.id);
+
+        // the token of the old workspace stops working once the user has left it
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = 1 AND user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        assert!(state.verify(&auth.token).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scoped_token_should_be_restricted() -> Result<()> {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let auth = state.issue_tokens(user).await?;
+        let claims = state.verify(&auth.token).await?;
+
+        let input = CreateScopedToken {
+            scopes: vec![Scope::MessagesRead, Scope::MessagesRead],
+        };
+        let ret = create_scoped_token_handler(claims.clone(), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let scoped: ScopedTokenOutput = serde_json::from_slice(&body)?;
+        assert_eq!(scoped.scopes, vec![Scope::MessagesRead]);
+        let scoped_claims = state.verify(&scoped.token).await?;
+        assert_eq!(scoped_claims.sid, claims.sid);
+
+        // a restricted token can not widen its scopes
+        let ret = state
+            .sign_scoped_token(&scoped_claims, vec![Scope::ChatsWrite])
+            .await;
+        assert!(matches!(ret, Err(AppError::InvalidScope(_))));
+
+        let app = crate::get_router(state.clone()).await?;
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", scoped.token))
+                .body(Body::empty())
+        };
+        let res = app
+            .clone()
+            .oneshot(get("/api/chats/1/messages?limit=6")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(get("/api/chats")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // it ends together with the session
+        state.revoke_session(&claims).await?;
+        assert!(state.verify(&scoped.token).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_should_rotate_token() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SigninUser::new("tchen1@acme.org", "123456");
+        let ret = signin_handler(None, State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+
+        let input = RefreshInput {
+            refresh_token: auth.refresh_token.clone(),
+        };
+        let ret = refresh_handler(State(state.clone()), Json(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let refreshed: AuthOutput = serde_json::from_slice(&body)?;
+        assert_ne!(refreshed.refresh_token, auth.refresh_token);
+        let claims = state.dk.verify(&refreshed.token)?;
+        assert_eq!(claims.user.email, "tchen1@acme.org");
+        assert_eq!(claims.sid, state.dk.verify(&auth.token)?.sid);
+
+        // the old refresh token can not be used twice
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn logout_should_revoke_session() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SigninUser::new("tchen1@acme.org", "123456");
+        let ret = signin_handler(None, State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        let body = ret.into_body().collect().await?.to_bytes();
+        let auth: AuthOutput = serde_json::from_slice(&body)?;
+        let claims = state.verify(&auth.token).await?;
+
+        let ret = logout_handler(Extension(claims), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        assert!(state.verify(&auth.token).await.is_err());
+        let input = RefreshInput {
+            refresh_token: auth.refresh_token,
+        };
+        let ret = refresh_handler(State(state), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn jwks_should_contain_signing_key() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let Json(jwks) = jwks_handler(State(state.clone())).await;
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].kid.as_deref(), Some(state.ek.kid()));
         Ok(())
     }
 
@@ -126,7 +699,7 @@ mod tests {
         let email = "alice@acme.org";
         let password = "Hunter42";
         let input = SigninUser::new(email, password);
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(None, State(state), Json(input))
             .await
             .into_response();
         println!("{:?}", ret);
@@ -139,4 +712,36 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signin_lockout_should_429() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let addr: SocketAddr = "10.0.0.1:4000".parse()?;
+        let max = state.config.auth.signin_throttle.max_email_failures;
+        for _ in 0..max {
+            let input = SigninUser::new("tchen1@acme.org", "wrong");
+            let ret = signin_handler(Some(ConnectInfo(addr)), State(state.clone()), Json(input))
+                .await?
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        }
+        // even the right password is rejected while locked
+        let input = SigninUser::new("tchen1@acme.org", "123456");
+        let ret = signin_handler(Some(ConnectInfo(addr)), State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: i64 = ret
+            .headers()
+            .get(axum::http::header::RETRY_AFTER)
+            .expect("retry-after header")
+            .to_str()?
+            .parse()?;
+        assert!(retry_after > 0);
+
+        let logs = state.list_audit_logs(crate::SIGNIN_LOCKOUT_ACTION).await?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].ip.as_deref(), Some("10.0.0.1"));
+        Ok(())
+    }
 }
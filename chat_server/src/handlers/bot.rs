@@ -0,0 +1,162 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+
+use crate::models::{ApiKey, ApiKeyCreated, CreateApiKey, CreateBot};
+use crate::{AppError, AppState, ErrOutput};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/bots",
+    responses(
+        (status = 200, description = "Bots of the workspace", body = Vec<User>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_bot_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_bots(user.ws_id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/bots",
+    responses(
+        (status = 201, description = "Bot created", body = User),
+        (status = 403, description = "Caller is not the workspace owner", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_bot_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBot>,
+) -> Result<impl IntoResponse, AppError> {
+    let bot = state.create_bot(&user, &input).await?;
+    Ok((StatusCode::CREATED, Json(bot)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/bots/{id}/keys",
+    params(
+        ("id" = u64, Path, description = "Bot id")
+    ),
+    responses(
+        (status = 200, description = "Api keys of the bot", body = Vec<ApiKey>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_api_key_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_api_keys(&user, id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/bots/{id}/keys",
+    params(
+        ("id" = u64, Path, description = "Bot id")
+    ),
+    responses(
+        (status = 201, description = "Api key created, the key is only shown once", body = ApiKeyCreated),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_api_key_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(input): Json<CreateApiKey>,
+) -> Result<impl IntoResponse, AppError> {
+    let key = state.create_api_key(&user, id, &input).await?;
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/bots/{id}/keys/{key_id}",
+    params(
+        ("id" = u64, Path, description = "Bot id"),
+        ("key_id" = u64, Path, description = "Api key id")
+    ),
+    responses(
+        (status = 204, description = "Api key revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_api_key_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, key_id)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_api_key(&user, id, key_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_router;
+    use axum::body::Body;
+    use axum::http::Request;
+    use chat_core::utils::Scope;
+    use tower::ServiceExt;
+
+    fn get(uri: &str, key: &str) -> anyhow::Result<Request<Body>> {
+        let req = Request::builder()
+            .uri(uri)
+            .header("Authorization", format!("Bearer {key}"))
+            .body(Body::empty())?;
+        Ok(req)
+    }
+
+    #[tokio::test]
+    async fn api_key_should_be_scoped() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let bot = state
+            .create_bot(
+                &owner,
+                &CreateBot {
+                    name: "bot".to_string(),
+                },
+            )
+            .await?;
+        let input = CreateApiKey {
+            name: "ci".to_string(),
+            scopes: vec![Scope::ChatsRead],
+            expires_in_days: None,
+        };
+        let created = state.create_api_key(&owner, bot.id, &input).await?;
+        let app = get_router(state).await?;
+
+        let res = app
+            .clone()
+            .oneshot(get("/api/users", &created.key)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(get("/api/bots", &created.key)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.oneshot(get("/api/users", "chat_invalid")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+}
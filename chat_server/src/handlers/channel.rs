@@ -0,0 +1,101 @@
+use crate::models::{ChannelPage, ListChannels, PreviewChannel};
+use crate::{AppError, AppState, ErrOutput};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chat_core::{Chat, Message, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    params(
+        ListChannels
+    ),
+    responses(
+        (status = 200, description = "A page of the public channels of the workspace", body = ChannelPage),
+        (status = 403, description = "Guests can not browse channels", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_channels_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListChannels>,
+) -> Result<impl IntoResponse, AppError> {
+    let page = state.search_channels(&user, &input).await?;
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/channels/{id}/join",
+    params(
+        ("id" = u64, Path, description = "Channel id")
+    ),
+    responses(
+        (status = 200, description = "Channel joined", body = Chat),
+        (status = 404, description = "Public channel not found", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn join_channel_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_channel(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/channels/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Channel id")
+    ),
+    responses(
+        (status = 204, description = "Channel left"),
+        (status = 404, description = "Public channel not found or user is not a member of it", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn leave_channel_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.leave_channel(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/channels/{id}/messages",
+    params(
+        ("id" = u64, Path, description = "Channel id"),
+        PreviewChannel
+    ),
+    responses(
+        (status = 200, description = "Recent messages of the channel, newest first", body = Vec<Message>),
+        (status = 404, description = "Public channel not found", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn preview_channel_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Query(input): Query<PreviewChannel>,
+) -> Result<impl IntoResponse, AppError> {
+    let messages = state.preview_channel(&user, id, &input).await?;
+    Ok(Json(messages))
+}
@@ -1,13 +1,20 @@
-use crate::{AppError, AppState, CreateChat, ErrOutput};
-use axum::extract::{Path, State};
+use crate::policy::Action;
+use crate::{
+    AddChatMembers, AppError, AppState, ChatMember, CreateChat, ErrOutput, ListChats, MuteMember,
+    UpdateChat, UpdateChatRole, UpdateChatSettings,
+};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
-use chat_core::{Chat, User};
+use chat_core::{Chat, ChatType, User};
 use tracing::info;
 #[utoipa::path(
     get,
     path = "/api/chats",
+    params(
+        ListChats
+    ),
     responses(
         (status = 200, description = "List of chats", body = Vec<Chat>),
     ),
@@ -18,8 +25,12 @@ use tracing::info;
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListChats>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chats = state.fetch_chat_all(user.id as _).await?;
+    // everyone only sees the chats they were added to
+    let chats = state
+        .fetch_member_chats(user.ws_id as _, user.id as _, input.archived)
+        .await?;
     info!("chats {chats:?}");
     Ok((StatusCode::OK, Json(chats)))
 }
@@ -28,6 +39,8 @@ pub(crate) async fn list_chat_handler(
     path = "/api/chats",
     responses(
         (status = 201, description = "Chat created", body = Chat),
+        (status = 200, description = "A chat without a name of the same members exists already", body = Chat),
+        (status = 403, description = "Role may not create this chat", body = ErrOutput),
     ),
     security(
         ("token" = [])
@@ -38,8 +51,19 @@ pub(crate) async fn create_chat_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.create_chat(input, user.ws_id as _).await?;
-    Ok((StatusCode::CREATED, Json(chat)))
+    let role = state.authorize(&user, Action::CreateChat).await?;
+    if input.is_public() {
+        Action::CreatePublicChannel.check(role)?;
+    }
+    let (chat, created) = state
+        .create_or_get_chat(input, user.ws_id as _, Some(user.id))
+        .await?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(chat)))
 }
 
 #[utoipa::path(
@@ -76,17 +100,28 @@ pub(crate) async fn get_chat_handler(
     ),
     responses(
         (status = 200, description = "Chat update", body = Chat),
+        (status = 400, description = "Only channels are renamed or made public or private", body = ErrOutput),
+        (status = 403, description = "Role may not make this change", body = ErrOutput),
     ),
     security(
         ("token" = [])
     )
 )]
 pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
     Path(id): Path<u64>,
     State(state): State<AppState>,
-    Json(input): Json<CreateChat>,
+    Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.update_chat(input, id).await?;
+    let role = state.authorize(&user, Action::UpdateChat).await?;
+    let chat = state
+        .get_chat_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("chat id {id}")))?;
+    if input.public == Some(true) && chat.r#type != ChatType::PublicChannel {
+        Action::CreatePublicChannel.check(role)?;
+    }
+    let chat = state.update_chat(&input, id).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 #[utoipa::path(
@@ -96,7 +131,8 @@ pub(crate) async fn update_chat_handler(
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "Chat delete", body = Chat),
+        (status = 200, description = "Chat deleted, it can be restored until it is purged", body = Chat),
+        (status = 403, description = "Only owners of the chat and admins delete chats", body = ErrOutput),
     ),
     security(
         ("token" = [])
@@ -110,3 +146,363 @@ pub(crate) async fn delete_chat_handler(
     let chat = state.delete_chat(id).await?;
     Ok((StatusCode::OK, Json(chat)))
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Members added", body = Chat),
+        (status = 400, description = "Members can not be added to this chat", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn add_chat_members_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Json(input): Json<AddChatMembers>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.add_chat_members(&user, id, &input).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/members/{uid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("uid" = i64, Path, description = "User id of the member")
+    ),
+    responses(
+        (status = 200, description = "Member removed", body = Chat),
+        (status = 403, description = "Only moderators remove other members", body = ErrOutput),
+        (status = 404, description = "User is not a member of the chat", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn remove_chat_member_handler(
+    Extension(user): Extension<User>,
+    Path((id, uid)): Path<(u64, i64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.remove_chat_member(&user, id, uid).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 204, description = "Chat left"),
+        (status = 400, description = "Single chats can not be left", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.leave_chat(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/settings",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    request_body = UpdateChatSettings,
+    responses(
+        (status = 200, description = "Chat settings updated", body = Chat),
+        (status = 400, description = "Invalid input", body = ErrOutput),
+        (status = 403, description = "Posting policy and invites are left to owners and admins", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_settings_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateChatSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.update_chat_settings(&user, id, &input).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/archive",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat archived, it stays readable", body = Chat),
+        (status = 403, description = "Guests can not archive chats", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn archive_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.archive_chat(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/unarchive",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat unarchived", body = Chat),
+        (status = 403, description = "Guests can not unarchive chats", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unarchive_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.unarchive_chat(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/restore",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Deleted chat restored", body = Chat),
+        (status = 403, description = "Only owners and admins restore chats", body = ErrOutput),
+        (status = 404, description = "Deleted chat not found", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn restore_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.restore_chat(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Members of the chat with their roles", body = Vec<ChatMember>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_chat_members_handler(
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_chat_members(id).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/members/{uid}/role",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("uid" = i64, Path, description = "User id of the member")
+    ),
+    request_body = UpdateChatRole,
+    responses(
+        (status = 200, description = "Role changed", body = ChatMember),
+        (status = 400, description = "A chat keeps at least one owner", body = ErrOutput),
+        (status = 403, description = "Only owners of the chat change roles", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn set_chat_role_handler(
+    Extension(user): Extension<User>,
+    Path((id, uid)): Path<(u64, i64)>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateChatRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.set_chat_role(&user, id, uid, &input).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/members/{uid}/mute",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("uid" = i64, Path, description = "User id of the member")
+    ),
+    request_body = MuteMember,
+    responses(
+        (status = 200, description = "Member muted", body = ChatMember),
+        (status = 403, description = "Moderators only mute members", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn mute_chat_member_handler(
+    Extension(user): Extension<User>,
+    Path((id, uid)): Path<(u64, i64)>,
+    State(state): State<AppState>,
+    Json(input): Json<MuteMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.mute_chat_member(&user, id, uid, &input).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/members/{uid}/mute",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("uid" = i64, Path, description = "User id of the member")
+    ),
+    responses(
+        (status = 200, description = "Member unmuted", body = ChatMember),
+        (status = 403, description = "Moderators only unmute members", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unmute_chat_member_handler(
+    Extension(user): Extension<User>,
+    Path((id, uid)): Path<(u64, i64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.unmute_chat_member(&user, id, uid).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/lock",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat locked, only moderators post", body = Chat),
+        (status = 403, description = "Only moderators lock chats", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn lock_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.lock_chat(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/unlock",
+    params(
+        ("id" = u64, Path, description = "Chat id")
+    ),
+    responses(
+        (status = 200, description = "Chat unlocked", body = Chat),
+        (status = 403, description = "Only moderators unlock chats", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unlock_chat_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.unlock_chat(&user, id).await?;
+    Ok(Json(chat))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{get_router, AppState};
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn update_chat_should_refuse_members() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let moderator = state.find_user_by_id(2).await?.expect("user should exist");
+        sqlx::query(
+            "UPDATE chat_members SET role = 'moderator' WHERE chat_id = 1 AND user_id = $1",
+        )
+        .bind(moderator.id)
+        .execute(&state.pool)
+        .await?;
+        let members = state
+            .get_chat_by_id(1)
+            .await?
+            .expect("chat should exist")
+            .members;
+        let token = state.ek.sign(moderator)?;
+        let app = get_router(state.clone()).await?;
+        let req = Request::builder()
+            .method(Method::PATCH)
+            .uri("/api/chats/1")
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"name": "general", "members": [2, 3]}"#))?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.members, members);
+
+        // the name alone is fine
+        let req = Request::builder()
+            .method(Method::PATCH)
+            .uri("/api/chats/1")
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"name": "general"}"#))?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+
+use crate::models::{CreateInvite, Invite, InviteCreated};
+use crate::{AppError, AppState, ErrOutput};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/invites",
+    responses(
+        (status = 200, description = "Invites of the workspace", body = Vec<Invite>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_invites(&user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invites",
+    responses(
+        (status = 201, description = "Invite created, the code is only shown once", body = InviteCreated),
+        (status = 403, description = "Caller is not the workspace owner", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state.create_invite(&user, &input).await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/invites/{id}",
+    params(
+        ("id" = u64, Path, description = "Invite id")
+    ),
+    responses(
+        (status = 204, description = "Invite revoked"),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_invite(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -27,9 +27,34 @@ pub(crate) async fn send_message_handler(
     Path(id): Path<u64>,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
+    state.ensure_can_post(&user, id).await?;
     let message = state.create_message(input, id, user.id as _).await?;
     Ok((StatusCode::CREATED, Json(message)))
 }
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Message deleted", body = Message),
+        (status = 403, description = "Only moderators delete messages of others", body = ErrOutput),
+        (status = 404, description = "Message not found", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state.delete_message(&user, id, mid).await?;
+    Ok(Json(message))
+}
 #[utoipa::path(
     get,
     path = "/api/chats/{id}/messages",
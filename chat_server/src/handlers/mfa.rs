@@ -0,0 +1,139 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+
+use crate::models::{MfaCode, MfaEnrollment, RecoveryCodes};
+use crate::{AppError, AppState, ErrOutput};
+use chat_core::User;
+
+#[utoipa::path(
+    post,
+    path = "/api/me/mfa",
+    responses(
+        (status = 200, description = "Enrollment started, confirm it with a code", body = MfaEnrollment),
+        (status = 400, description = "Mfa already enabled", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn enroll_mfa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.enroll_mfa(&user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/me/mfa/confirm",
+    responses(
+        (status = 200, description = "Mfa enabled", body = RecoveryCodes),
+        (status = 400, description = "Invalid code", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn confirm_mfa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<MfaCode>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.confirm_mfa(&user, &input.code).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/me/mfa/disable",
+    responses(
+        (status = 204, description = "Mfa disabled"),
+        (status = 400, description = "Invalid code", body = ErrOutput),
+        (status = 429, description = "Too many invalid codes, retry later", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn disable_mfa_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<MfaCode>,
+) -> Result<impl IntoResponse, AppError> {
+    state.disable_mfa(&user, &input.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn mfa_handlers_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let ret = enroll_mfa_handler(Extension(user.clone()), State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        let input = MfaCode {
+            code: state.current_totp_code(&user).await?,
+        };
+        let ret = confirm_mfa_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let recovery: RecoveryCodes = serde_json::from_slice(&body)?;
+
+        let input = MfaCode {
+            code: "000000".to_string(),
+        };
+        let ret = disable_mfa_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        let input = MfaCode {
+            code: recovery.codes[0].clone(),
+        };
+        let ret = disable_mfa_handler(Extension(user), State(state), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrong_codes_should_lock_disable_mfa() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        state.enroll_mfa(&user).await?;
+        let code = state.current_totp_code(&user).await?;
+        let recovery = state.confirm_mfa(&user, &code).await?;
+        for _ in 0..state.config.auth.signin_throttle.max_email_failures {
+            let input = MfaCode {
+                code: "000000".to_string(),
+            };
+            let ret =
+                disable_mfa_handler(Extension(user.clone()), State(state.clone()), Json(input))
+                    .await
+                    .into_response();
+            assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let input = MfaCode {
+            code: recovery.codes[0].clone(),
+        };
+        let ret = disable_mfa_handler(Extension(user.clone()), State(state.clone()), Json(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(state.is_mfa_enabled(user.id).await?);
+        Ok(())
+    }
+}
@@ -1,13 +1,27 @@
+mod account;
 mod auth;
+mod bot;
+mod channel;
 mod chat;
+mod invite;
 mod message;
+mod mfa;
+mod oidc;
+mod scim;
 mod workspace;
 
+pub(crate) use account::*;
 pub(crate) use auth::*;
 use axum::response::IntoResponse;
+pub(crate) use bot::*;
 
+pub(crate) use channel::*;
 pub(crate) use chat::*;
+pub(crate) use invite::*;
 pub(crate) use message::*;
+pub(crate) use mfa::*;
+pub(crate) use oidc::*;
+pub(crate) use scim::*;
 pub(crate) use workspace::*;
 pub(crate) async fn index_handler() -> impl IntoResponse {
     "index_handler"
@@ -0,0 +1,135 @@
+use axum::extract::{Query, State};
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Redirect};
+
+use crate::handlers::{AuthOutput, MfaPendingOutput};
+use crate::models::{OidcCallback, OIDC_LOGIN_TTL};
+use crate::{AppError, AppState, ErrOutput};
+
+/// binds the callback to the browser which started the login, against login csrf
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+#[utoipa::path(
+    get,
+    path = "/api/oidc/login",
+    responses(
+        (status = 303, description = "Redirect to the identity provider, the state is set as a cookie"),
+        (status = 404, description = "Oidc login is not configured", body = ErrOutput),
+    )
+)]
+pub(crate) async fn oidc_login_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let (url, oidc_state) = state.oidc_login_url().await?;
+    let cookie = state_cookie(&state, &oidc_state, OIDC_LOGIN_TTL as i64);
+    Ok(([(SET_COOKIE, cookie)], Redirect::to(&url)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/oidc/callback",
+    params(
+        OidcCallback
+    ),
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "A second factor is required", body = MfaPendingOutput),
+        (status = 400, description = "The state does not match the cookie of the login", body = ErrOutput),
+        (status = 401, description = "The provider rejected the login", body = ErrOutput),
+    )
+)]
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(input): Query<OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    if state_from_cookie(&headers) != Some(input.state.as_str()) {
+        return Err(AppError::InvalidToken(
+            "oidc state does not belong to this browser".to_string(),
+        ));
+    }
+    let user = state.oidc_signin(&input).await?;
+    let cookie = state_cookie(&state, "", 0);
+    Ok(([(SET_COOKIE, cookie)], state.complete_signin(user).await?))
+}
+
+/// only sent back to the callback, and on the top level redirect from the provider
+fn state_cookie(state: &AppState, value: &str, max_age: i64) -> String {
+    let secure = match state.oidc.as_ref() {
+        Some(oidc) if oidc.config().redirect_uri.starts_with("https://") => "; Secure",
+        _ => "",
+    };
+    format!(
+        "{OIDC_STATE_COOKIE}={value}; Path=/api/oidc/callback; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}"
+    )
+}
+
+fn state_from_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|v| v.trim().split_once('='))
+        .find(|(name, _)| *name == OIDC_STATE_COOKIE)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::mock::MockIdp;
+    use anyhow::Result;
+    use axum::http::{header, StatusCode};
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn oidc_handlers_should_work() -> Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let state = state.with_oidc(idp.config())?;
+
+        let res = oidc_login_handler(State(state.clone()))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let cookie = res.headers()[header::SET_COOKIE].to_str()?;
+        assert!(cookie.contains("HttpOnly; SameSite=Lax"));
+        let cookie = cookie.split(';').next().expect("cookie").to_string();
+        let location = res.headers()[header::LOCATION].to_str()?;
+        let (code, oidc_state) = idp.authorize(location).await?;
+
+        let input = OidcCallback {
+            code: Some(code),
+            state: oidc_state,
+            error: None,
+            error_description: None,
+        };
+        // a callback without the cookie of the login is rejected, e.g. a link sent by an attacker
+        let ret =
+            oidc_callback_handler(State(state.clone()), HeaderMap::new(), Query(input.clone()))
+                .await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, format!("theme=dark; {cookie}").parse()?);
+        let res = oidc_callback_handler(State(state.clone()), headers, Query(input))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body)?;
+        let claims = state.dk.verify(output["token"].as_str().expect("token"))?;
+        assert_eq!(claims.user.email, "tchen1@acme.org");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_login_should_need_config() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ret = oidc_login_handler(State(state)).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Serialize;
+
+use crate::models::{
+    ScimGroupInput, ScimListQuery, ScimPatch, ScimToken, ScimTokenCreated, ScimUserInput,
+};
+use crate::{AppError, AppState, ErrOutput};
+use chat_core::{User, WorkSpace};
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// errors of the scim endpoints use the error body of rfc 7644
+pub(crate) struct ScimError(AppError);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimErrorOutput {
+    schemas: Vec<&'static str>,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scim_type: Option<&'static str>,
+    detail: String,
+}
+
+/// a json body with the scim media type
+pub(crate) struct ScimJson<T>(StatusCode, T);
+
+impl From<AppError> for ScimError {
+    fn from(e: AppError) -> Self {
+        Self(e)
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let status = self.0.status();
+        let scim_type = match self.0 {
+            AppError::EmailAlreadyExists(_) => Some("uniqueness"),
+            _ => None,
+        };
+        let output = ScimErrorOutput {
+            schemas: vec![crate::models::SCIM_ERROR_SCHEMA],
+            status: status.as_u16().to_string(),
+            scim_type,
+            detail: self.0.to_string(),
+        };
+        ScimJson(status, output).into_response()
+    }
+}
+
+impl<T: Serialize> IntoResponse for ScimJson<T> {
+    fn into_response(self) -> Response {
+        let mut response = (self.0, Json(self.1)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(SCIM_CONTENT_TYPE),
+        );
+        response
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/scim-tokens",
+    responses(
+        (status = 200, description = "Scim tokens of the workspace", body = Vec<ScimToken>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_scim_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_scim_tokens(&user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/scim-tokens",
+    responses(
+        (status = 201, description = "Scim token created, the token is only shown once", body = ScimTokenCreated),
+        (status = 403, description = "Caller may not manage the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_scim_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_scim_token(&user).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/scim-tokens/{id}",
+    params(
+        ("id" = i64, Path, description = "Scim token id")
+    ),
+    responses(
+        (status = 204, description = "Scim token revoked"),
+        (status = 404, description = "Scim token not found", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_scim_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_scim_token(&user, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// the endpoints below implement the scim protocol and are documented by rfc 7644
+
+pub(crate) async fn list_scim_users_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Query(query): Query<ScimListQuery>,
+) -> Result<impl IntoResponse, ScimError> {
+    let users = state.list_scim_users(ws.id, &query).await?;
+    Ok(ScimJson(StatusCode::OK, users))
+}
+
+pub(crate) async fn get_scim_user_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ScimError> {
+    let user = state.get_scim_user(ws.id, id).await?;
+    Ok(ScimJson(StatusCode::OK, user))
+}
+
+pub(crate) async fn create_scim_user_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Json(input): Json<ScimUserInput>,
+) -> Result<impl IntoResponse, ScimError> {
+    let user = state.create_scim_user(ws.id, &input).await?;
+    Ok(ScimJson(StatusCode::CREATED, user))
+}
+
+pub(crate) async fn patch_scim_user_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(patch): Json<ScimPatch>,
+) -> Result<impl IntoResponse, ScimError> {
+    let user = state.patch_scim_user(ws.id, id, &patch).await?;
+    Ok(ScimJson(StatusCode::OK, user))
+}
+
+pub(crate) async fn delete_scim_user_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ScimError> {
+    state.delete_scim_user(ws.id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_scim_groups_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Query(query): Query<ScimListQuery>,
+) -> Result<impl IntoResponse, ScimError> {
+    let groups = state.list_scim_groups(ws.id, &query).await?;
+    Ok(ScimJson(StatusCode::OK, groups))
+}
+
+pub(crate) async fn get_scim_group_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ScimError> {
+    let group = state.get_scim_group(ws.id, id).await?;
+    Ok(ScimJson(StatusCode::OK, group))
+}
+
+pub(crate) async fn create_scim_group_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Json(input): Json<ScimGroupInput>,
+) -> Result<impl IntoResponse, ScimError> {
+    let group = state.create_scim_group(ws.id, &input).await?;
+    Ok(ScimJson(StatusCode::CREATED, group))
+}
+
+pub(crate) async fn patch_scim_group_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(patch): Json<ScimPatch>,
+) -> Result<impl IntoResponse, ScimError> {
+    let group = state.patch_scim_group(ws.id, id, &patch).await?;
+    Ok(ScimJson(StatusCode::OK, group))
+}
+
+pub(crate) async fn delete_scim_group_handler(
+    Extension(ws): Extension<WorkSpace>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, ScimError> {
+    state.delete_scim_group(ws.id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,16 +1,251 @@
-use crate::{AppError, AppState};
-use axum::extract::State;
+use crate::handlers::WorkspaceTokenOutput;
+use crate::models::{
+    DeactivateUser, DirectoryPage, ListUsers, UpdateRole, UpdateWorkspace, UserWorkspace,
+    WorkspaceMember,
+};
+use crate::{AppError, AppState, ErrOutput};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
-use chat_core::User;
+use chat_core::utils::UserClaims;
+use chat_core::{User, UserProfile, WorkSpace};
 use tracing::info;
 
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    params(
+        ListUsers
+    ),
+    responses(
+        (status = 200, description = "A page of the users of the workspace", body = DirectoryPage),
+        (status = 403, description = "Guests can not read the directory", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
 pub(crate) async fn list_chat_users_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListUsers>,
 ) -> Result<impl IntoResponse, AppError> {
     info!("user {user:?}");
-    let ws_id = user.ws_id;
-    let users = state.fetch_chat_user_all(ws_id as _).await?;
-    Ok(Json(users))
+    let page = state.search_directory(&user, &input).await?;
+    Ok(Json(page))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description = "Profile of the user", body = UserProfile),
+        (status = 404, description = "User is not in the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_user_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.find_user_profile(&user, id).await?;
+    Ok(Json(profile))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/users/{id}/role",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    request_body = UpdateRole,
+    responses(
+        (status = 200, description = "Role changed", body = WorkspaceMember),
+        (status = 403, description = "Caller may not give this role", body = ErrOutput),
+        (status = 404, description = "User is not in the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_role_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.update_member_role(&user, id, input.role).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/deactivate",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    request_body = DeactivateUser,
+    responses(
+        (status = 200, description = "User deactivated", body = WorkspaceMember),
+        (status = 403, description = "Caller may not manage this user", body = ErrOutput),
+        (status = 404, description = "User is not in the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn deactivate_user_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    input: Option<Json<DeactivateUser>>,
+) -> Result<impl IntoResponse, AppError> {
+    let input = input.map(|Json(v)| v).unwrap_or_default();
+    let member = state.deactivate_member(&user, id, &input).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/{id}/reactivate",
+    params(
+        ("id" = i64, Path, description = "User id")
+    ),
+    responses(
+        (status = 200, description = "User reactivated", body = WorkspaceMember),
+        (status = 403, description = "Caller may not manage this user", body = ErrOutput),
+        (status = 404, description = "User is not in the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn reactivate_user_handler(
+    Extension(user): Extension<User>,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let member = state.reactivate_member(&user, id).await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces",
+    responses(
+        (status = 200, description = "Workspaces the user belongs to", body = Vec<UserWorkspace>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_workspaces_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_user_workspaces(user.id).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/switch",
+    params(
+        ("id" = i64, Path, description = "Workspace id")
+    ),
+    responses(
+        (status = 200, description = "Access token for the workspace", body = WorkspaceTokenOutput),
+        (status = 404, description = "User is not a member of the workspace", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn switch_workspace_handler(
+    claims: UserClaims,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.switch_workspace(&claims, id).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace",
+    responses(
+        (status = 200, description = "Current workspace", body = WorkSpace),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.current_workspace(&user).await?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/workspace",
+    request_body = UpdateWorkspace,
+    responses(
+        (status = 200, description = "Workspace updated", body = WorkSpace),
+        (status = 403, description = "Admins rename, only the owner transfers", body = ErrOutput),
+        (status = 409, description = "Name is already taken", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.update_workspace(&user, &input).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace",
+    responses(
+        (status = 202, description = "Workspace scheduled for deletion", body = WorkSpace),
+        (status = 403, description = "Caller is not the owner", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.delete_workspace(&user).await?;
+    Ok((StatusCode::ACCEPTED, Json(ws)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/restore",
+    responses(
+        (status = 200, description = "Deletion cancelled", body = WorkSpace),
+        (status = 403, description = "Caller is not the owner", body = ErrOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn restore_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.restore_workspace(&user).await?))
 }
@@ -1,23 +1,29 @@
 mod config;
 mod error;
 mod handlers;
+mod mail;
 mod middlewares;
 mod models;
+mod oidc;
 mod openapi;
+mod policy;
 
-use crate::middlewares::verify_chat;
+use crate::middlewares::{verify_chat, verify_scim_token, verify_workspace};
 use crate::openapi::OpenApiRouter;
 use anyhow::Context;
+use axum::handler::Handler;
 use axum::middleware::from_fn_with_state;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
-use chat_core::middlewares::{set_layers, verify_token, TokenVerify};
-use chat_core::utils::{DecodingKey, EncodingKey};
-use chat_core::User;
+use chat_core::middlewares::{set_layers, verify_token, RequireScopeLayer, TokenVerify};
+use chat_core::utils::{is_token_revoked, DecodingKey, EncodingKey, Scope, UserClaims};
 pub use config::AppConfig;
 pub use error::{AppError, ErrOutput};
 use handlers::*;
+use mail::Mailer;
+pub use mail::{Mail, OutboxMailer};
 pub use models::*;
+use oidc::OidcClient;
 use sqlx::PgPool;
 use std::fmt;
 use std::fmt::Formatter;
@@ -35,33 +41,217 @@ pub struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) mailer: Arc<dyn Mailer>,
+    pub(crate) oidc: Option<OidcClient>,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let chat = Router::new()
         .route(
             "/:id",
-            get(get_chat_handler)
-                .post(send_message_handler)
-                .patch(update_chat_handler)
-                .delete(delete_chat_handler),
+            get(get_chat_handler.layer(RequireScopeLayer::new(Scope::ChatsRead)))
+                .post(send_message_handler.layer(RequireScopeLayer::new(Scope::MessagesWrite)))
+                .patch(update_chat_handler.layer(RequireScopeLayer::new(Scope::ChatsWrite)))
+                .delete(delete_chat_handler.layer(RequireScopeLayer::new(Scope::ChatsWrite))),
+        )
+        .route(
+            "/:id/messages",
+            get(list_message_handler).route_layer(RequireScopeLayer::new(Scope::MessagesRead)),
+        )
+        .route(
+            "/:id/messages/:mid",
+            delete(delete_message_handler)
+                .route_layer(RequireScopeLayer::new(Scope::MessagesWrite)),
+        )
+        .route(
+            "/:id/members",
+            get(list_chat_members_handler)
+                .post(add_chat_members_handler)
+                .route_layer(RequireScopeLayer::read_write(
+                    Scope::ChatsRead,
+                    Scope::ChatsWrite,
+                )),
+        )
+        .route(
+            "/:id/members/:uid",
+            delete(remove_chat_member_handler)
+                .route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/members/:uid/role",
+            put(set_chat_role_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/members/:uid/mute",
+            post(mute_chat_member_handler)
+                .delete(unmute_chat_member_handler)
+                .route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/lock",
+            post(lock_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/unlock",
+            post(unlock_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/leave",
+            post(leave_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/settings",
+            patch(update_chat_settings_handler)
+                .route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/archive",
+            post(archive_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/:id/unarchive",
+            post(unarchive_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
         )
-        .route("/:id/messages", get(list_message_handler))
         .layer(from_fn_with_state(state.clone(), verify_chat))
-        .route("/", get(list_chat_handler).post(create_chat_handler));
+        // deleted chats have no members any more, admins restore them from outside
+        .route(
+            "/:id/restore",
+            post(restore_chat_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/",
+            get(list_chat_handler)
+                .post(create_chat_handler)
+                .route_layer(RequireScopeLayer::read_write(
+                    Scope::ChatsRead,
+                    Scope::ChatsWrite,
+                )),
+        );
+    // session and account management is not available to restricted tokens
+    let account = Router::new()
+        .route("/logout", post(logout_handler))
+        .route("/logout/all", post(logout_all_handler))
+        .route("/tokens", post(create_scoped_token_handler))
+        .route("/email/verify/resend", post(resend_verification_handler))
+        .route("/me", patch(update_me_handler))
+        .route("/me/profile", patch(update_profile_handler))
+        .route("/me/password", post(change_password_handler))
+        .route("/me/mfa", post(enroll_mfa_handler))
+        .route("/me/mfa/confirm", post(confirm_mfa_handler))
+        .route("/me/mfa/disable", post(disable_mfa_handler))
+        .route("/workspaces/:id/switch", post(switch_workspace_handler))
+        .route(
+            "/workspace",
+            get(get_workspace_handler)
+                .patch(update_workspace_handler)
+                .delete(delete_workspace_handler),
+        )
+        .route("/workspace/restore", post(restore_workspace_handler))
+        .route_layer(RequireScopeLayer::new(Scope::Admin));
+    let admin = Router::new()
+        .route("/bots", get(list_bot_handler).post(create_bot_handler))
+        .route(
+            "/bots/:id/keys",
+            get(list_api_key_handler).post(create_api_key_handler),
+        )
+        .route("/bots/:id/keys/:key_id", delete(revoke_api_key_handler))
+        .route(
+            "/invites",
+            get(list_invite_handler).post(create_invite_handler),
+        )
+        .route("/invites/:id", delete(revoke_invite_handler))
+        .route("/users/:id/role", patch(update_role_handler))
+        .route("/users/:id/deactivate", post(deactivate_user_handler))
+        .route("/users/:id/reactivate", post(reactivate_user_handler))
+        .route(
+            "/scim-tokens",
+            get(list_scim_token_handler).post(create_scim_token_handler),
+        )
+        .route("/scim-tokens/:id", delete(revoke_scim_token_handler))
+        .route_layer(RequireScopeLayer::new(Scope::Admin));
     let api = Router::new()
-        .route("/users", get(list_chat_users_handler))
+        .route(
+            "/users",
+            get(list_chat_users_handler).route_layer(RequireScopeLayer::new(Scope::ChatsRead)),
+        )
+        .route(
+            "/users/:id",
+            get(get_user_handler).route_layer(RequireScopeLayer::new(Scope::ChatsRead)),
+        )
         .nest("/chats", chat)
-        .route("/upload", post(upload_handler))
-        .route("/files/:ws_id/*path", get(file_handler))
+        .route(
+            "/channels",
+            get(list_channels_handler).route_layer(RequireScopeLayer::new(Scope::ChatsRead)),
+        )
+        .route(
+            "/channels/:id/join",
+            post(join_channel_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/channels/:id/leave",
+            post(leave_channel_handler).route_layer(RequireScopeLayer::new(Scope::ChatsWrite)),
+        )
+        .route(
+            "/channels/:id/messages",
+            get(preview_channel_handler).route_layer(RequireScopeLayer::new(Scope::MessagesRead)),
+        )
+        .route(
+            "/upload",
+            post(upload_handler).route_layer(RequireScopeLayer::new(Scope::FilesWrite)),
+        )
+        .route(
+            "/files/:ws_id/*path",
+            get(file_handler).route_layer(RequireScopeLayer::new(Scope::FilesRead)),
+        )
+        .merge(admin)
+        // everything above is unavailable while the workspace waits to be purged
+        .layer(from_fn_with_state(state.clone(), verify_workspace))
+        .route(
+            "/workspaces",
+            get(list_workspaces_handler).route_layer(RequireScopeLayer::new(Scope::ChatsRead)),
+        )
+        .merge(account)
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .route("/signin", post(signin_handler))
-        .route("/signup", post(signup_handler));
+        .route("/signin/mfa", post(signin_mfa_handler))
+        .route("/signup", post(signup_handler))
+        .route("/refresh", post(refresh_handler))
+        .route("/password/forgot", post(forgot_password_handler))
+        .route("/password/reset", post(reset_password_handler))
+        .route("/email/verify", post(verify_email_handler))
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler));
+
+    // provisioning by the identity provider, authenticated with a scim token of the workspace
+    let scim = Router::new()
+        .route(
+            "/Users",
+            get(list_scim_users_handler).post(create_scim_user_handler),
+        )
+        .route(
+            "/Users/:id",
+            get(get_scim_user_handler)
+                .patch(patch_scim_user_handler)
+                .delete(delete_scim_user_handler),
+        )
+        .route(
+            "/Groups",
+            get(list_scim_groups_handler).post(create_scim_group_handler),
+        )
+        .route(
+            "/Groups/:id",
+            get(get_scim_group_handler)
+                .patch(patch_scim_group_handler)
+                .delete(delete_scim_group_handler),
+        )
+        .layer(from_fn_with_state(state.clone(), verify_scim_token));
 
     let router = Router::new()
         .openapi()
         .route("/", get(index_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
+        .nest("/scim/v2", scim)
         .with_state(state);
     Ok(set_layers(router))
 }
@@ -74,14 +264,28 @@ impl Deref for AppState {
 }
 impl TokenVerify for AppState {
     type Error = AppError;
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    async fn verify(&self, token: &str) -> Result<UserClaims, Self::Error> {
+        if token.starts_with(API_KEY_PREFIX) {
+            return self.verify_api_key(token).await;
+        }
+        let claims = self.dk.verify(token)?;
+        if is_token_revoked(&self.pool, &claims).await? {
+            return Err(AppError::Unauthorized("token has been revoked".to_string()));
+        }
+        Ok(claims)
     }
 }
 impl AppState {
     pub async fn try_new(config: AppConfig) -> Result<Self, AppError> {
-        let dk = DecodingKey::load(&config.auth.pk).context("load pk key failed")?;
-        let ek = EncodingKey::load(&config.auth.sk).context("load sk key failed")?;
+        let dk = config.auth.decoding_key()?;
+        let ek = config.auth.encoding_key()?;
+        let mailer = config.mail.mailer()?;
+        let oidc = config
+            .auth
+            .oidc
+            .clone()
+            .map(OidcClient::try_new)
+            .transpose()?;
         // let pool =  PgPoolOptions::new()
         //     .max_connections(10)
         //     .after_connect(|conn, _meta|
@@ -101,6 +305,8 @@ impl AppState {
                 dk,
                 ek,
                 pool,
+                mailer,
+                oidc,
             }),
         })
     }
@@ -123,8 +329,8 @@ mod test_util {
             tokio::fs::create_dir_all(&config.server.base_dir)
                 .await
                 .context("create base_dir failed")?;
-            let dk = DecodingKey::load(&config.auth.pk).context("load pk key failed")?;
-            let ek = EncodingKey::load(&config.auth.sk).context("load sk key failed")?;
+            let dk = config.auth.decoding_key()?;
+            let ek = config.auth.encoding_key()?;
 
             let server_url = config.server.db_url.split("/chat").next().unwrap();
 
@@ -137,10 +343,40 @@ mod test_util {
                         dk,
                         ek,
                         pool,
+                        mailer: Arc::new(OutboxMailer::default()),
+                        oidc: None,
                     }),
                 },
             ))
         }
+
+        /// enable oidc login, e.g. against a mock provider
+        pub fn with_oidc(mut self, config: config::OidcConfig) -> anyhow::Result<Self> {
+            let inner = Arc::get_mut(&mut self.inner).context("state is shared")?;
+            inner.oidc = Some(OidcClient::try_new(config.clone())?);
+            inner.config.auth.oidc = Some(config);
+            Ok(self)
+        }
+
+        /// user 1 made the owner of its workspace, tests of admin features start from it
+        pub async fn workspace_owner_for_test(&self) -> Result<chat_core::User, AppError> {
+            let owner = self
+                .find_user_by_id(1)
+                .await?
+                .ok_or_else(|| AppError::NotFound("user id 1".to_string()))?;
+            let ws = self
+                .find_workspace_by_id(owner.ws_id as _)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("workspace id {}", owner.ws_id)))?;
+            self.update_workspace_owner(ws, owner.id as _).await?;
+            Ok(owner)
+        }
+
+        /// the in-memory outbox, when the state was built with one
+        pub fn outbox(&self) -> Option<&OutboxMailer> {
+            let mailer: &dyn std::any::Any = self.mailer.as_ref();
+            mailer.downcast_ref()
+        }
     }
 
     pub async fn get_test_pool(url: Option<&str>) -> (sqlx_db_tester::TestPg, PgPool) {
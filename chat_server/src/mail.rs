@@ -0,0 +1,180 @@
+use crate::config::{MailConfig, MailTransport};
+use crate::AppError;
+use anyhow::Context;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub type MailFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send + 'a>>;
+
+/// transport used to deliver the mails sent by the server
+pub trait Mailer: Any + Send + Sync {
+    fn send(&self, mail: Mail) -> MailFuture<'_>;
+}
+
+/// delivers mails through an SMTP relay
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+/// keeps every mail in memory and optionally writes it as json into a directory,
+/// used in development and by the tests
+#[derive(Debug, Clone, Default)]
+pub struct OutboxMailer {
+    dir: Option<PathBuf>,
+    mails: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl Mail {
+    pub fn new(to: impl Into<String>, subject: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            to: to.into(),
+            subject: subject.into(),
+            body: body.into(),
+        }
+    }
+}
+
+impl SmtpMailer {
+    pub fn try_new(
+        from: &str,
+        host: &str,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let from = from.parse().context("invalid mail from address")?;
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .context("create smtp transport failed")?
+            .port(port);
+        if let (Some(username), Some(password)) = (username, password) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self {
+            from,
+            transport: builder.build(),
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: Mail) -> MailFuture<'_> {
+        Box::pin(async move {
+            let to: Mailbox = mail
+                .to
+                .parse()
+                .map_err(|e| AppError::MailError(format!("invalid recipient {}: {e}", mail.to)))?;
+            let message = lettre::Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(mail.subject)
+                .body(mail.body)
+                .map_err(|e| AppError::MailError(e.to_string()))?;
+            self.transport
+                .send(message)
+                .await
+                .map_err(|e| AppError::MailError(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+impl OutboxMailer {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            mails: Arc::default(),
+        }
+    }
+
+    /// every mail sent so far
+    pub fn mails(&self) -> Vec<Mail> {
+        self.mails.lock().expect("outbox lock poisoned").clone()
+    }
+
+    /// the last mail sent to the address
+    pub fn last_mail_to(&self, to: &str) -> Option<Mail> {
+        self.mails().into_iter().rev().find(|v| v.to == to)
+    }
+
+    /// the token of the link in the last mail sent to the address
+    pub fn last_token_to(&self, to: &str) -> Option<String> {
+        let mail = self.last_mail_to(to)?;
+        let (_, token) = mail.body.split_once("token=")?;
+        token.split_whitespace().next().map(|v| v.to_string())
+    }
+}
+
+impl Mailer for OutboxMailer {
+    fn send(&self, mail: Mail) -> MailFuture<'_> {
+        Box::pin(async move {
+            info!("outbox mail to {}: {}", mail.to, mail.subject);
+            if let Some(dir) = &self.dir {
+                tokio::fs::create_dir_all(dir).await?;
+                let path = dir.join(format!("{}.json", uuid::Uuid::now_v7()));
+                let data = serde_json::to_vec_pretty(&mail)
+                    .map_err(|e| AppError::MailError(e.to_string()))?;
+                tokio::fs::write(path, data).await?;
+            }
+            self.mails.lock().expect("outbox lock poisoned").push(mail);
+            Ok(())
+        })
+    }
+}
+
+impl MailConfig {
+    pub fn mailer(&self) -> anyhow::Result<Arc<dyn Mailer>> {
+        let mailer: Arc<dyn Mailer> = match &self.transport {
+            MailTransport::Smtp {
+                host,
+                port,
+                username,
+                password,
+            } => Arc::new(SmtpMailer::try_new(
+                &self.from,
+                host,
+                *port,
+                username.clone(),
+                password.clone(),
+            )?),
+            MailTransport::Outbox { dir } => Arc::new(OutboxMailer::new(dir.clone())),
+        };
+        Ok(mailer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn outbox_mailer_should_work() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::now_v7()));
+        let mailer = OutboxMailer::new(Some(dir.clone()));
+        mailer
+            .send(Mail::new("tchen1@acme.org", "hello", "world"))
+            .await?;
+        assert_eq!(mailer.mails().len(), 1);
+        let mail = mailer.last_mail_to("tchen1@acme.org").expect("mail exists");
+        assert_eq!(mail.subject, "hello");
+        let files = std::fs::read_dir(&dir)?.count();
+        assert_eq!(files, 1);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -2,8 +2,13 @@ use anyhow::Result;
 use chat_server::{get_router, AppConfig, AppState};
 
 use chat_core::utils::log::init_logging;
+use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
+
+/// how often deleted workspaces and chats past their grace period are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -12,10 +17,27 @@ async fn main() -> Result<()> {
     info!("{config:?}");
     let addr = format!("0.0.0.0:{}", config.server.port);
     let state = AppState::try_new(config).await?;
+    let purge_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = purge_state.purge_deleted_workspaces().await {
+                warn!("purge deleted workspaces failed: {e}");
+            }
+            if let Err(e) = purge_state.purge_deleted_chats().await {
+                warn!("purge deleted chats failed: {e}");
+            }
+        }
+    });
     let app = get_router(state).await?;
     info!("Listener on:{}", addr);
     let listener = TcpListener::bind(&addr).await?;
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -1,34 +1,74 @@
+use crate::policy::ChatAction;
 use crate::{AppError, AppState};
-use axum::extract::{FromRequestParts, Path, Request, State};
+use axum::extract::{FromRequestParts, MatchedPath, Path, Request, State};
+use axum::http::Method;
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use chat_core::User;
+use std::collections::HashMap;
 
+/// reject users who are not members of the chat, or whose role in it does not allow the request
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
     println!("{parts:?}");
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-        .await
-        .expect("Path::from_request_parts");
+    // routes below a chat may have more parameters, e.g. /:id/members/:uid
+    let params = match Path::<HashMap<String, u64>>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(params)) => params,
+        Err(e) => return AppError::InvalidPath(e.body_text()).into_response(),
+    };
+    let Some(&chat_id) = params.get("id") else {
+        return AppError::NotFound("chat id".to_string()).into_response();
+    };
     let user = parts
         .extensions
         .get::<User>()
         .expect("parts.extensions.get::<User>()");
-
-    if !state
-        .is_chat_member(chat_id, user.id as u64)
-        .await
-        .expect("state.is_chat_member(chat_id, user.id as u64).await.expect")
-    {
-        let err = AppError::CreateMessageError(format!(
-            "user {} are not members of this chat {chat_id}",
-            user.id
-        ));
-        return err.into_response();
+    // the route below the chat, e.g. `/members/:uid` of `/api/chats/:id/members/:uid`
+    let route = parts
+        .extensions
+        .get::<MatchedPath>()
+        .and_then(|path| path.as_str().split_once("/:id"))
+        .map_or("", |(_, route)| route);
+    // routes without an action are refused, so a new route can not be reached unchecked
+    let Some(action) = chat_action(&parts.method, route, &params, user) else {
+        return AppError::NotFound(format!("{} {route} of chat {chat_id}", parts.method))
+            .into_response();
+    };
+    if let Err(e) = state.authorize_chat(user, chat_id, action).await {
+        return e.into_response();
     }
     let req = Request::from_parts(parts, body);
     next.run(req).await
 }
+
+fn chat_action(
+    method: &Method,
+    route: &str,
+    params: &HashMap<String, u64>,
+    user: &User,
+) -> Option<ChatAction> {
+    let action = match (method.as_str(), route) {
+        ("GET", "" | "/messages" | "/members") => ChatAction::Read,
+        // messages of others are checked against the sender
+        ("DELETE", "/messages/:mid") => ChatAction::Read,
+        ("POST", "") => ChatAction::SendMessage,
+        ("PATCH", "" | "/settings") | ("POST", "/archive" | "/unarchive") => ChatAction::UpdateChat,
+        ("DELETE", "") => ChatAction::DeleteChat,
+        ("POST", "/members") => ChatAction::AddMembers,
+        // leaving is always allowed
+        ("POST", "/leave") => ChatAction::Read,
+        ("DELETE", "/members/:uid") if params.get("uid") == Some(&(user.id as u64)) => {
+            ChatAction::Read
+        }
+        ("DELETE", "/members/:uid") | ("POST" | "DELETE", "/members/:uid/mute") => {
+            ChatAction::ModerateMembers
+        }
+        ("PUT", "/members/:uid/role") => ChatAction::ManageRoles,
+        ("POST", "/lock" | "/unlock") => ChatAction::LockChat,
+        _ => return None,
+    };
+    Some(action)
+}
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -37,7 +77,7 @@ mod tests {
     use axum::body::Body;
     use axum::http::StatusCode;
     use axum::middleware::from_fn_with_state;
-    use axum::routing::get;
+    use axum::routing::{get, patch, post};
     use axum::Router;
     use chat_core::middlewares::verify_token;
     use tower::ServiceExt;
@@ -55,6 +95,7 @@ mod tests {
         let token = state.ek.sign(user)?;
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
+            .route("/chat/:id/export", post(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
             .with_state(state);
@@ -76,6 +117,47 @@ mod tests {
         println!("{:?}", res);
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
+        // routes without a chat action are refused
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/chat/1/export")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // chat id is not a number
+        let req = Request::builder()
+            .uri("/chat/general/messages")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn verify_chat_middleware_should_check_chat_role() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = Router::new()
+            .route("/chat/:id", patch(handler))
+            .layer(from_fn_with_state(state.clone(), verify_chat))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+        for (user_id, status) in [(1, StatusCode::OK), (2, StatusCode::FORBIDDEN)] {
+            let user = state
+                .find_user_by_id(user_id)
+                .await?
+                .expect("user should exist");
+            let token = state.ek.sign(user)?;
+            let req = Request::builder()
+                .method(Method::PATCH)
+                .uri("/chat/1")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), status);
+        }
         Ok(())
     }
 }
@@ -1,3 +1,7 @@
 mod chat;
+mod scim;
+mod workspace;
 
 pub use chat::verify_chat;
+pub use scim::verify_scim_token;
+pub use workspace::verify_workspace;
@@ -0,0 +1,33 @@
+use crate::handlers::ScimError;
+use crate::{AppError, AppState};
+use axum::extract::{FromRequestParts, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::Authorization;
+use axum_extra::TypedHeader;
+
+/// authenticate the identity provider by its scim token, the workspace of the token is added to the request
+pub async fn verify_scim_token(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let token =
+        match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+            Err(e) => {
+                let e = AppError::Unauthorized(format!("parse Authorization header failed: {e}"));
+                return ScimError::from(e).into_response();
+            }
+        };
+    match state.verify_scim_token(&token).await {
+        Ok(ws) => {
+            let mut req = Request::from_parts(parts, body);
+            req.extensions_mut().insert(ws);
+            next.run(req).await
+        }
+        Err(e) => ScimError::from(e).into_response(),
+    }
+}
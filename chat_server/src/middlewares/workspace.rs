@@ -0,0 +1,17 @@
+use crate::{AppError, AppState};
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chat_core::User;
+
+/// reject requests into a workspace which is scheduled for deletion
+pub async fn verify_workspace(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(ws_id) = req.extensions().get::<User>().map(|user| user.ws_id) else {
+        return AppError::Unauthorized("user is missing".to_string()).into_response();
+    };
+    match state.is_workspace_deleted(ws_id).await {
+        Ok(false) => next.run(req).await,
+        Ok(true) => AppError::WorkspaceDeleted(ws_id).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
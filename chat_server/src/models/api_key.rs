@@ -0,0 +1,330 @@
+use super::token::{generate_token, hash_token};
+use super::workspace::add_workspace_member;
+use crate::policy::Action;
+use crate::{AppError, AppState};
+use chat_core::utils::{Scope, UserClaims};
+use chat_core::{User, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// api keys are told apart from jwt by this prefix
+pub const API_KEY_PREFIX: &str = "chat_";
+const API_KEY_DISPLAY_LEN: usize = 12;
+/// `last_used_at` is only written once a minute, not on every request of a busy key
+const LAST_USED_PRECISION: i64 = 60;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateBot {
+    pub name: String,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// the key never expires when it is not set
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Local>>,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub revoked_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+/// returned once on creation, the key itself can't be read again
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ApiKeyCreated {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[derive(Debug, FromRow)]
+struct ApiKeyRow {
+    id: i64,
+    user_id: i64,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Local>>,
+    last_used_at: Option<DateTime<Local>>,
+    revoked_at: Option<DateTime<Local>>,
+    created_at: DateTime<Local>,
+}
+
+impl AppState {
+    /// create a bot account in the workspace of the owner
+    pub async fn create_bot(&self, owner: &User, input: &CreateBot) -> Result<User, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        if input.name.trim().is_empty() {
+            return Err(AppError::CreateBotError(
+                "bot name cannot be empty".to_string(),
+            ));
+        }
+        let email = format!("bot-{}@bots.chat.local", uuid::Uuid::now_v7());
+        let mut tx = self.pool.begin().await?;
+        let bot: User = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, email, fullname, password_hash, is_bot, email_verified_at)
+            VALUES ($1, $2, $3, '', TRUE, NOW())
+            RETURNING id, ws_id, fullname, email, is_bot, created_at
+            "#,
+        )
+        .bind(owner.ws_id)
+        .bind(email)
+        .bind(input.name.trim())
+        .fetch_one(&mut *tx)
+        .await?;
+        add_workspace_member(&mut *tx, bot.ws_id, bot.id, WorkspaceRole::Member).await?;
+        tx.commit().await?;
+        Ok(bot)
+    }
+
+    pub async fn list_bots(&self, ws_id: i64) -> Result<Vec<User>, AppError> {
+        let bots = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, is_bot, created_at
+            FROM users
+            WHERE ws_id = $1 AND is_bot
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(bots)
+    }
+
+    pub async fn create_api_key(
+        &self,
+        owner: &User,
+        bot_id: i64,
+        input: &CreateApiKey,
+    ) -> Result<ApiKeyCreated, AppError> {
+        let bot = self.find_workspace_bot(owner, bot_id).await?;
+        if input.scopes.is_empty() {
+            return Err(AppError::CreateBotError(
+                "api key needs at least one scope".to_string(),
+            ));
+        }
+        let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+        let scopes: Vec<String> = input.scopes.iter().map(|v| v.to_string()).collect();
+        let row: ApiKeyRow = sqlx::query_as(
+            r#"
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7 * INTERVAL '1 day')
+            RETURNING id, user_id, name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at
+            "#,
+        )
+        .bind(bot.id)
+        .bind(&input.name)
+        .bind(&key[..API_KEY_DISPLAY_LEN])
+        .bind(hash_token(&key))
+        .bind(&scopes)
+        .bind(owner.id)
+        .bind(input.expires_in_days.map(|v| v as f64))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ApiKeyCreated {
+            api_key: row.into(),
+            key,
+        })
+    }
+
+    pub async fn list_api_keys(&self, owner: &User, bot_id: i64) -> Result<Vec<ApiKey>, AppError> {
+        let bot = self.find_workspace_bot(owner, bot_id).await?;
+        let rows: Vec<ApiKeyRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(bot.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        owner: &User,
+        bot_id: i64,
+        key_id: i64,
+    ) -> Result<(), AppError> {
+        let bot = self.find_workspace_bot(owner, bot_id).await?;
+        let ret = sqlx::query(
+            r#"
+            UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(key_id)
+        .bind(bot.id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("api key id {key_id}")));
+        }
+        Ok(())
+    }
+
+    /// resolve an api key to the claims of its bot
+    pub async fn verify_api_key(&self, key: &str) -> Result<UserClaims, AppError> {
+        let row: Option<(i64, i64, Vec<String>)> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, scopes
+            FROM api_keys
+            WHERE key_hash = $1
+              AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+        )
+        .bind(hash_token(key))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((id, user_id, scopes)) = row else {
+            return Err(AppError::Unauthorized("invalid api key".to_string()));
+        };
+        sqlx::query(
+            r#"
+            UPDATE api_keys SET last_used_at = NOW()
+            WHERE id = $1
+              AND (last_used_at IS NULL OR last_used_at < NOW() - $2 * INTERVAL '1 second')
+            "#,
+        )
+        .bind(id)
+        .bind(LAST_USED_PRECISION as f64)
+        .execute(&self.pool)
+        .await?;
+        let Some(user) = self.find_user_by_id(user_id).await? else {
+            return Err(AppError::Unauthorized("invalid api key".to_string()));
+        };
+        if !self.is_workspace_member(user.ws_id, user.id).await? {
+            return Err(AppError::UserDeactivated);
+        }
+        let mut claims = UserClaims::from(user).with_scopes(parse_scopes(&scopes));
+        claims.jti = format!("api_key:{id}");
+        Ok(claims)
+    }
+
+    async fn find_workspace_bot(&self, owner: &User, bot_id: i64) -> Result<User, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        match self.find_user_by_id(bot_id).await? {
+            Some(bot) if bot.is_bot && bot.ws_id == owner.ws_id => Ok(bot),
+            _ => Err(AppError::NotFound(format!("bot id {bot_id}"))),
+        }
+    }
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        Self {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            prefix: row.prefix,
+            scopes: parse_scopes(&row.scopes),
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// unknown scopes, e.g. of a removed feature, are dropped
+fn parse_scopes(scopes: &[String]) -> Vec<Scope> {
+    scopes.iter().filter_map(|v| v.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn create_bot_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let input = CreateBot {
+            name: "deploy bot".to_string(),
+        };
+        let bot = state.create_bot(&owner, &input).await?;
+        assert!(bot.is_bot);
+        assert_eq!(bot.ws_id, owner.ws_id);
+        assert_eq!(state.list_bots(owner.ws_id).await?.len(), 1);
+
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.create_bot(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_key_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let bot = state
+            .create_bot(
+                &owner,
+                &CreateBot {
+                    name: "bot".to_string(),
+                },
+            )
+            .await?;
+        let input = CreateApiKey {
+            name: "ci".to_string(),
+            scopes: vec![Scope::MessagesWrite],
+            expires_in_days: Some(30),
+        };
+        let created = state.create_api_key(&owner, bot.id, &input).await?;
+        assert!(created.key.starts_with(API_KEY_PREFIX));
+        assert!(created.key.starts_with(&created.api_key.prefix));
+        assert!(created.api_key.expires_at.is_some());
+
+        let claims = state.verify_api_key(&created.key).await?;
+        assert_eq!(claims.user.id, bot.id);
+        assert!(claims.has_scope(Scope::MessagesWrite));
+        assert!(!claims.has_scope(Scope::MessagesRead));
+
+        let keys = state.list_api_keys(&owner, bot.id).await?;
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].last_used_at.is_some());
+
+        state
+            .revoke_api_key(&owner, bot.id, created.api_key.id)
+            .await?;
+        assert!(state.verify_api_key(&created.key).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bot_message_should_be_marked() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let bot = state
+            .create_bot(
+                &owner,
+                &CreateBot {
+                    name: "bot".to_string(),
+                },
+            )
+            .await?;
+        let input = crate::CreateMessage {
+            content: "beep".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, 1, bot.id as _).await?;
+        assert!(message.is_bot);
+        Ok(())
+    }
+}
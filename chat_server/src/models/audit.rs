@@ -0,0 +1,54 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct AuditLog {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub action: String,
+    pub ip: Option<String>,
+    pub detail: serde_json::Value,
+    pub created_at: DateTime<Local>,
+}
+
+impl AppState {
+    /// record a security relevant event
+    pub async fn write_audit_log(
+        &self,
+        user_id: Option<i64>,
+        action: &str,
+        ip: Option<&str>,
+        detail: serde_json::Value,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_logs (user_id, action, ip, detail)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(user_id)
+        .bind(action)
+        .bind(ip)
+        .bind(detail)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_audit_logs(&self, action: &str) -> Result<Vec<AuditLog>, AppError> {
+        let logs = sqlx::query_as(
+            r#"
+            SELECT id, user_id, action, ip, detail, created_at
+            FROM audit_logs
+            WHERE action = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(action)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(logs)
+    }
+}
@@ -0,0 +1,232 @@
+use super::chat::fetch_chat;
+use super::directory::escape_like;
+use crate::policy::Action;
+use crate::{AppError, AppState};
+use chat_core::{Chat, ChatType, Message, User};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const DEFAULT_PREVIEW_SIZE: i64 = 20;
+const MAX_PREVIEW_SIZE: i64 = 50;
+
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct ListChannels {
+    /// prefix or fuzzy match on the name
+    pub q: Option<String>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct PreviewChannel {
+    /// number of recent messages, at most 50
+    pub limit: Option<i64>,
+}
+
+/// a public channel as listed to the members of the workspace
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+pub struct Channel {
+    pub id: i64,
+    pub name: String,
+    pub member_count: i64,
+    /// whether the user is a member
+    pub joined: bool,
+    pub created_at: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ChannelPage {
+    pub channels: Vec<Channel>,
+    /// not set on the last page
+    pub next_cursor: Option<i64>,
+}
+
+impl AppState {
+    /// a page of the public channels of the workspace of the user, ordered by id
+    pub async fn search_channels(
+        &self,
+        user: &User,
+        input: &ListChannels,
+    ) -> Result<ChannelPage, AppError> {
+        self.authorize(user, Action::BrowseChannels).await?;
+        let q = input
+            .q
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_lowercase());
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        // one more row tells whether there is a next page
+        let mut channels: Vec<Channel> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.name, c.created_at,
+                   (SELECT COUNT(*) FROM chat_members m WHERE m.chat_id = c.id) AS member_count,
+                   EXISTS(SELECT 1 FROM chat_members m WHERE m.chat_id = c.id AND m.user_id = $2) AS joined
+            FROM chats c
+            WHERE c.ws_id = $1
+              AND c.type = 'public_channel'
+              AND c.archived_at IS NULL
+              AND c.deleted_at IS NULL
+              AND c.id > $3
+              AND ($4::TEXT IS NULL OR c.name ILIKE $5 || '%' OR $4 <% c.name)
+            ORDER BY c.id
+            LIMIT $6
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(user.id)
+        .bind(input.cursor.unwrap_or(0))
+        .bind(&q)
+        .bind(q.as_deref().map(escape_like))
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let next_cursor = if channels.len() as i64 > limit {
+            channels.truncate(limit as usize);
+            channels.last().map(|v| v.id)
+        } else {
+            None
+        };
+        Ok(ChannelPage {
+            channels,
+            next_cursor,
+        })
+    }
+
+    /// join a public channel, joining twice is fine
+    pub async fn join_channel(&self, user: &User, id: u64) -> Result<Chat, AppError> {
+        let chat = self.public_channel(user, id).await?;
+        if chat.archived_at.is_some() {
+            return Err(AppError::ChatArchived(chat.id));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id) VALUES ($1, $2)
+            ON CONFLICT (chat_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(id as i64)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+        let chat = fetch_chat(&mut *tx, id as _).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    /// other chats are left through `/api/chats`, here they are reported as missing
+    pub async fn leave_channel(&self, user: &User, id: u64) -> Result<Chat, AppError> {
+        self.get_chat_by_id(id)
+            .await?
+            .filter(|chat| chat.ws_id == user.ws_id && chat.r#type == ChatType::PublicChannel)
+            .ok_or_else(|| AppError::NotFound(format!("channel id {id}")))?;
+        self.leave_chat(user, id).await
+    }
+
+    /// recent messages of a public channel, newest first, the user does not have to be a member
+    pub async fn preview_channel(
+        &self,
+        user: &User,
+        id: u64,
+        input: &PreviewChannel,
+    ) -> Result<Vec<Message>, AppError> {
+        self.public_channel(user, id).await?;
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PREVIEW_SIZE)
+            .clamp(1, MAX_PREVIEW_SIZE);
+        let messages = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, is_bot, created_at
+            FROM messages
+            WHERE chat_id = $1
+            ORDER BY id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(id as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    /// other chats are not visible to non-members, they are reported as missing
+    async fn public_channel(&self, user: &User, id: u64) -> Result<Chat, AppError> {
+        self.authorize(user, Action::BrowseChannels).await?;
+        self.get_chat_by_id(id)
+            .await?
+            .filter(|chat| chat.ws_id == user.ws_id && chat.r#type == ChatType::PublicChannel)
+            .ok_or_else(|| AppError::NotFound(format!("channel id {id}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn search_channels_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(6).await?.expect("user should exist");
+        let input = ListChannels {
+            q: Some("GEN".to_string()),
+            ..Default::default()
+        };
+        let page = state.search_channels(&user, &input).await?;
+        assert_eq!(page.channels.len(), 1);
+        assert_eq!(page.channels[0].name, "general");
+        assert_eq!(page.channels[0].member_count, 5);
+        assert!(!page.channels[0].joined);
+        assert_eq!(page.next_cursor, None);
+
+        // private channels are not listed
+        let input = ListChannels {
+            q: Some("private".to_string()),
+            ..Default::default()
+        };
+        assert!(state
+            .search_channels(&user, &input)
+            .await?
+            .channels
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_and_preview_channel_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(6).await?.expect("user should exist");
+        let input = PreviewChannel { limit: Some(3) };
+        let messages = state.preview_channel(&user, 1, &input).await?;
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].id > messages[1].id);
+        let ret = state.preview_channel(&user, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let chat = state.join_channel(&user, 1).await?;
+        assert!(chat.members.contains(&user.id));
+        assert!(state.is_chat_member(1, user.id as _).await?);
+        state.join_channel(&user, 1).await?;
+        let ret = state.join_channel(&user, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        state.leave_channel(&user, 1).await?;
+        assert!(!state.is_chat_member(1, user.id as _).await?);
+
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.leave_channel(&member, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        assert!(state.is_chat_member(2, member.id as _).await?);
+        Ok(())
+    }
+}
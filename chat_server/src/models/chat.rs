@@ -1,7 +1,12 @@
-use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use crate::policy::{Action, ChatAction};
+use crate::{AppError, AppState, ChatFile};
+use chat_core::{Chat, ChatType, NotificationLevel, PostingPolicy, User};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use sqlx::{PgConnection, PgExecutor};
+use std::io::ErrorKind;
+use std::str::FromStr;
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
 
 // use chat_core::
 #[derive(Debug, Clone, Default, Serialize, ToSchema, Deserialize)]
@@ -10,7 +15,54 @@ pub struct CreateChat {
     pub members: Vec<i64>,
     pub public: bool,
 }
-async fn get_type(input: &CreateChat, app_state: &AppState) -> Result<ChatType, AppError> {
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct ListChats {
+    /// list archived chats as well
+    #[serde(default)]
+    pub archived: bool,
+}
+/// users of the workspace to add to a chat
+#[derive(Debug, Clone, Default, Serialize, ToSchema, Deserialize)]
+pub struct AddChatMembers {
+    pub members: Vec<i64>,
+}
+/// rename a channel or switch it between private and public, fields left out are kept.
+/// members change through their own routes
+#[derive(Debug, Clone, Default, Serialize, ToSchema, Deserialize)]
+// a member list is refused, it would skip the checks of adding and removing members
+#[serde(deny_unknown_fields)]
+pub struct UpdateChat {
+    pub name: Option<String>,
+    pub public: Option<bool>,
+}
+/// fields left out are kept, an empty topic or description clears it
+#[derive(Debug, Clone, Default, Serialize, ToSchema, Deserialize)]
+pub struct UpdateChatSettings {
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub posting_policy: Option<PostingPolicy>,
+    pub members_can_invite: Option<bool>,
+    pub notification_level: Option<NotificationLevel>,
+}
+
+impl CreateChat {
+    /// only named chats can be channels
+    pub(crate) fn is_public(&self) -> bool {
+        self.public && self.name.as_deref().is_some_and(|v| !v.trim().is_empty())
+    }
+
+    /// a blank name is no name, such chats are told apart by their members
+    fn normalize(&mut self) {
+        if self.name.as_deref().is_some_and(|v| v.trim().is_empty()) {
+            self.name = None;
+        }
+    }
+}
+async fn get_type(
+    input: &CreateChat,
+    ws_id: u64,
+    app_state: &AppState,
+) -> Result<ChatType, AppError> {
     let len = input.members.len();
     if len < 2 {
         return Err(AppError::CreateChatError(
@@ -24,12 +76,12 @@ async fn get_type(input: &CreateChat, app_state: &AppState) -> Result<ChatType,
         ));
     }
     let users = &app_state
-        .fetch_chat_user_by_ids(&input.members)
+        .fetch_chat_user_by_ids(ws_id, &input.members)
         .await
         .expect("555555");
     if users.len() != len {
         return Err(AppError::CreateChatError(
-            "Some members do not exist".to_string(),
+            "Some members do not exist in the workspace".to_string(),
         ));
     }
     let chat_type = match (&input.name, len) {
@@ -45,88 +97,179 @@ async fn get_type(input: &CreateChat, app_state: &AppState) -> Result<ChatType,
     };
     Ok(chat_type)
 }
+const MAX_TOPIC_LEN: usize = 250;
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+// members are read from chat_members, ordered by user id
+pub(crate) const CHAT_COLUMNS: &str = r#"
+    c.id, c.ws_id, c.name, c.type, c.created_at, c.archived_at, c.deleted_at, c.locked_at,
+    c.topic, c.description, c.posting_policy, c.members_can_invite, c.notification_level,
+    ARRAY(SELECT m.user_id FROM chat_members m WHERE m.chat_id = c.id ORDER BY m.user_id) AS members
+"#;
+
 impl AppState {
     #[allow(unused)]
     pub async fn create_chat(&self, input: CreateChat, ws_id: u64) -> Result<Chat, AppError> {
-        let chat_type = get_type(&input, self).await?;
-        // match
-        let chat: Chat = sqlx::query_as(
+        let (chat, _) = self.create_or_get_chat(input, ws_id, None).await?;
+        Ok(chat)
+    }
+    /// chats without a name are unique per member set, the existing chat is returned with `false`.
+    /// `owner_id` owns a new chat unless it is a single chat
+    pub async fn create_or_get_chat(
+        &self,
+        mut input: CreateChat,
+        ws_id: u64,
+        owner_id: Option<i64>,
+    ) -> Result<(Chat, bool), AppError> {
+        input.normalize();
+        // otherwise any chat could be looked up by its members
+        if owner_id.is_some_and(|v| !input.members.contains(&v)) {
+            return Err(AppError::CreateChatError(
+                "the creator must be a member of the chat".to_string(),
+            ));
+        }
+        let chat_type = get_type(&input, ws_id, self).await?;
+        let key = member_key(input.name.as_deref(), &input.members);
+        let owner_id = owner_id.filter(|_| chat_type != ChatType::Single);
+        let mut tx = self.pool.begin().await?;
+        let id: Option<i64> = sqlx::query_scalar(
             r#"
-            INSERT INTO chats (ws_id, name, type, members)
+            INSERT INTO chats (ws_id, name, type, member_key)
             VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, created_at
+            ON CONFLICT (ws_id, member_key) WHERE member_key IS NOT NULL AND deleted_at IS NULL
+            DO NOTHING
+            RETURNING id
             "#,
         )
         .bind(ws_id as i64)
         .bind(input.name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(&self.pool)
+        .bind(&key)
+        .fetch_optional(&mut *tx)
         .await?;
-        Ok(chat)
+        let Some(id) = id else {
+            let id = member_key_owner(&mut *tx, ws_id as _, key.as_deref(), 0)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("chat of members {key:?}")))?;
+            return Ok((fetch_chat(&mut *tx, id).await?, false));
+        };
+        set_chat_members(&mut tx, id, &input.members).await?;
+        if let Some(owner_id) = owner_id {
+            sqlx::query(
+                "UPDATE chat_members SET role = 'owner' WHERE chat_id = $1 AND user_id = $2",
+            )
+            .bind(id)
+            .bind(owner_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        let chat = fetch_chat(&mut *tx, id).await?;
+        tx.commit().await?;
+        Ok((chat, true))
     }
     #[allow(unused)]
-    pub async fn update_chat(&self, input: CreateChat, id: u64) -> Result<Chat, AppError> {
-        let chat_type = get_type(&input, self).await?;
-        // match
-        let chat: Chat = sqlx::query_as(
+    pub async fn update_chat(&self, input: &UpdateChat, id: u64) -> Result<Chat, AppError> {
+        let chat = self
+            .get_chat_by_id(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {id}")))?;
+        ensure_writable(&chat)?;
+        // single and group chats are told apart by their members, they have no name to change
+        let is_channel = matches!(
+            chat.r#type,
+            ChatType::PublicChannel | ChatType::PrivateChannel
+        );
+        if !is_channel && (input.name.is_some() || input.public.is_some()) {
+            return Err(AppError::UpdateChatError(
+                "only channels can be renamed or made public or private".to_string(),
+            ));
+        }
+        let name = input.name.as_deref().map(str::trim);
+        if name.is_some_and(str::is_empty) {
+            return Err(AppError::UpdateChatError(
+                "a channel must have a name".to_string(),
+            ));
+        }
+        let chat_type = input.public.map(|public| {
+            if public {
+                ChatType::PublicChannel
+            } else {
+                ChatType::PrivateChannel
+            }
+        });
+        sqlx::query(
             r#"
                UPDATE chats
                SET
-                   name = $2,
-                   type = $3,
-                   members = $4
+                   name = COALESCE($2, name),
+                   type = COALESCE($3, type)
                WHERE
                    id = $1
-               RETURNING id, ws_id, name, type, members, created_at
                "#,
         )
         .bind(id as i64)
-        .bind(input.name)
+        .bind(name)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(&self.pool)
+        .execute(&self.pool)
         .await?;
-        Ok(chat)
+        fetch_chat(&self.pool, id as _).await
     }
+    /// soft delete, the chat can be restored until the retention window is over
     #[allow(unused)]
     pub async fn delete_chat(&self, ws_id: u64) -> Result<Chat, AppError> {
-        // match
-        let chat = sqlx::query_as(
+        self.set_chat_state(ws_id, "deleted_at = COALESCE(deleted_at, NOW())")
+            .await
+    }
+    #[allow(unused)]
+    pub async fn fetch_chat_all(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
+        let chats = sqlx::query_as(&format!(
             r#"
-               DELETE FROM chats
-               WHERE id = $1
-               RETURNING id, ws_id, name, type, members, created_at
-               "#,
-        )
+            SELECT {CHAT_COLUMNS}
+            FROM chats c
+            WHERE c.ws_id = $1 AND c.deleted_at IS NULL
+            ORDER BY c.id
+            "#
+        ))
         .bind(ws_id as i64)
-        .fetch_one(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
-        Ok(chat)
+        Ok(chats)
     }
-    #[allow(unused)]
-    pub async fn fetch_chat_all(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-        let chats = sqlx::query_as(
+    /// chats of the workspace the user was added to, archived chats only when asked for
+    pub async fn fetch_member_chats(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        archived: bool,
+    ) -> Result<Vec<Chat>, AppError> {
+        let chats = sqlx::query_as(&format!(
             r#"
-            SELECT id ,ws_id, name, type,members,created_at
-            FROM chats
-            WHERE ws_id  = $1
-            "#,
-        )
+            SELECT {CHAT_COLUMNS}
+            FROM chat_members cm
+            JOIN chats c ON c.id = cm.chat_id
+            WHERE cm.user_id = $2 AND c.ws_id = $1
+              AND c.deleted_at IS NULL
+              AND ($3 OR c.archived_at IS NULL)
+            ORDER BY c.id
+            "#
+        ))
         .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(archived)
         .fetch_all(&self.pool)
         .await?;
         Ok(chats)
     }
+    /// deleted chats are not found
     #[allow(unused)]
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
-        let chat = sqlx::query_as(
+        let chat = sqlx::query_as(&format!(
             r#"
-            SELECT id,ws_id,name,type,members,created_at
-            FROM chats
-            WHERE id =$1
-            "#,
-        )
+            SELECT {CHAT_COLUMNS}
+            FROM chats c
+            WHERE c.id = $1 AND c.deleted_at IS NULL
+            "#
+        ))
         .bind(id as i64)
         .fetch_optional(&self.pool)
         .await?;
@@ -136,8 +279,9 @@ impl AppState {
         let is_members = sqlx::query(
             r#"
             SELECT 1
-            FROM chats
-            WHERE id = $1 AND $2 =ANY(members)
+            FROM chat_members m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.chat_id = $1 AND m.user_id = $2 AND c.deleted_at IS NULL
             "#,
         )
         .bind(chat_id as i64)
@@ -147,6 +291,418 @@ impl AppState {
         Ok(is_members.is_some())
     }
 }
+
+impl AppState {
+    /// add users of the workspace to a chat, users who are members already are skipped
+    pub async fn add_chat_members(
+        &self,
+        user: &User,
+        chat_id: u64,
+        input: &AddChatMembers,
+    ) -> Result<Chat, AppError> {
+        self.authorize(user, Action::UpdateChat).await?;
+        let chat = self.chat_of_workspace(user, chat_id).await?;
+        ensure_writable(&chat)?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::ChatMemberError(
+                "a single chat can not get more members".to_string(),
+            ));
+        }
+        self.authorize_chat(user, chat_id, ChatAction::AddMembers)
+            .await?;
+        let mut members = input.members.clone();
+        members.sort_unstable();
+        members.dedup();
+        if members.is_empty() {
+            return Err(AppError::ChatMemberError("no members to add".to_string()));
+        }
+        let users = self
+            .fetch_chat_user_by_ids(user.ws_id as _, &members)
+            .await?;
+        if users.len() != members.len() {
+            return Err(AppError::ChatMemberError(
+                "some members do not exist in the workspace".to_string(),
+            ));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id)
+            SELECT $1, unnest($2::BIGINT[])
+            ON CONFLICT (chat_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(chat.id)
+        .bind(&members)
+        .execute(&mut *tx)
+        .await?;
+        let chat = fetch_chat(&mut *tx, chat.id).await?;
+        if chat.name.is_none() && chat.members.len() > 8 {
+            return Err(AppError::ChatMemberError(
+                "Group chat with more 8 members  must have a name".to_string(),
+            ));
+        }
+        let key = member_key(chat.name.as_deref(), &chat.members);
+        set_member_key(&mut tx, chat.ws_id, chat.id, key.as_deref()).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    /// removing somebody else is up to the moderators, leaving is always allowed
+    pub async fn remove_chat_member(
+        &self,
+        user: &User,
+        chat_id: u64,
+        member_id: i64,
+    ) -> Result<Chat, AppError> {
+        if member_id != user.id {
+            self.moderate_member(user, chat_id, member_id).await?;
+        }
+        let chat = self.chat_of_workspace(user, chat_id).await?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::ChatMemberError(
+                "a single chat keeps both members".to_string(),
+            ));
+        }
+        let mut tx = self.pool.begin().await?;
+        if !delete_chat_member(&mut tx, chat.id, member_id).await? {
+            return Err(AppError::NotFound(format!(
+                "user id {member_id} in chat {chat_id}"
+            )));
+        }
+        let chat = fetch_chat(&mut *tx, chat.id).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    pub async fn leave_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.remove_chat_member(user, chat_id, user.id).await
+    }
+
+    /// archived chats stay readable, but nobody can post or change members
+    pub async fn archive_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.authorize(user, Action::UpdateChat).await?;
+        self.chat_of_workspace(user, chat_id).await?;
+        self.set_chat_state(chat_id, "archived_at = COALESCE(archived_at, NOW())")
+            .await
+    }
+
+    /// members of a locked chat read it, only moderators and owners post
+    pub async fn lock_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.authorize_chat(user, chat_id, ChatAction::LockChat)
+            .await?;
+        self.set_chat_state(chat_id, "locked_at = COALESCE(locked_at, NOW())")
+            .await
+    }
+
+    pub async fn unlock_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.authorize_chat(user, chat_id, ChatAction::LockChat)
+            .await?;
+        self.set_chat_state(chat_id, "locked_at = NULL").await
+    }
+
+    pub async fn unarchive_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.authorize(user, Action::UpdateChat).await?;
+        self.chat_of_workspace(user, chat_id).await?;
+        self.set_chat_state(chat_id, "archived_at = NULL").await
+    }
+
+    /// undo a soft delete, the chat comes back in the state it was deleted in
+    pub async fn restore_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.authorize(user, Action::DeleteChat).await?;
+        let chat = fetch_chat(&self.pool, chat_id as _).await?;
+        if chat.ws_id != user.ws_id || chat.deleted_at.is_none() {
+            return Err(AppError::NotFound(format!("deleted chat id {chat_id}")));
+        }
+        // a newer chat of the same members keeps the key
+        self.set_chat_state(
+            chat_id,
+            r#"
+            deleted_at = NULL,
+            member_key = CASE WHEN EXISTS(
+                SELECT 1 FROM chats o
+                WHERE o.ws_id = chats.ws_id AND o.member_key = chats.member_key
+                  AND o.deleted_at IS NULL AND o.id <> chats.id
+            ) THEN NULL ELSE member_key END
+            "#,
+        )
+        .await
+    }
+
+    /// who may post or invite is up to the moderators of the chat
+    pub async fn update_chat_settings(
+        &self,
+        user: &User,
+        chat_id: u64,
+        input: &UpdateChatSettings,
+    ) -> Result<Chat, AppError> {
+        self.authorize(user, Action::UpdateChat).await?;
+        if input.posting_policy.is_some() || input.members_can_invite.is_some() {
+            self.authorize_chat(user, chat_id, ChatAction::UpdateChat)
+                .await?;
+        }
+        let chat = self.chat_of_workspace(user, chat_id).await?;
+        ensure_writable(&chat)?;
+        let topic = settings_text(input.topic.as_deref(), "topic", MAX_TOPIC_LEN)?;
+        let description = settings_text(
+            input.description.as_deref(),
+            "description",
+            MAX_DESCRIPTION_LEN,
+        )?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE chats
+            SET topic = CASE WHEN $2 THEN NULLIF($3, '') ELSE topic END,
+                description = CASE WHEN $4 THEN NULLIF($5, '') ELSE description END,
+                posting_policy = COALESCE($6, posting_policy),
+                members_can_invite = COALESCE($7, members_can_invite),
+                notification_level = COALESCE($8, notification_level)
+            WHERE id = $1
+            "#,
+        )
+        .bind(chat.id)
+        .bind(topic.is_some())
+        .bind(topic)
+        .bind(description.is_some())
+        .bind(description)
+        .bind(input.posting_policy)
+        .bind(input.members_can_invite)
+        .bind(input.notification_level)
+        .execute(&mut *tx)
+        .await?;
+        let chat = fetch_chat(&mut *tx, chat.id).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    /// muted members do not post, locked chats and announcement channels need a moderator
+    pub async fn ensure_can_post(&self, user: &User, chat_id: u64) -> Result<(), AppError> {
+        let chat = fetch_chat(&self.pool, chat_id as _).await?;
+        ensure_writable(&chat)?;
+        self.authorize_chat(user, chat_id, ChatAction::SendMessage)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn ensure_chat_writable(&self, chat_id: u64) -> Result<(), AppError> {
+        let chat = fetch_chat(&self.pool, chat_id as _).await?;
+        ensure_writable(&chat)
+    }
+
+    /// purge the chats deleted before the retention window, returns their ids
+    pub async fn purge_deleted_chats(&self) -> Result<Vec<i64>, AppError> {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM chats
+            WHERE deleted_at < NOW() - $1 * INTERVAL '1 day'
+            "#,
+        )
+        .bind(self.config.server.chat_retention_days as f64)
+        .fetch_all(&self.pool)
+        .await?;
+        for id in &ids {
+            self.purge_chat(*id).await?;
+        }
+        Ok(ids)
+    }
+
+    /// remove the chat with its members and messages, and the files no other message or profile uses
+    async fn purge_chat(&self, id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let files: Vec<String> =
+            sqlx::query_scalar("SELECT DISTINCT unnest(files) FROM messages WHERE chat_id = $1")
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let unused: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT f FROM unnest($1::TEXT[]) AS f
+            WHERE NOT EXISTS(SELECT 1 FROM messages WHERE f = ANY(files))
+              AND NOT EXISTS(SELECT 1 FROM users WHERE avatar = f)
+            "#,
+        )
+        .bind(&files)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let base_dir = &self.config.server.base_dir;
+        for file in unused.iter().filter_map(|v| ChatFile::from_str(v).ok()) {
+            match tokio::fs::remove_file(file.path(base_dir)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        info!("purged chat {id}");
+        Ok(())
+    }
+
+    async fn set_chat_state(&self, chat_id: u64, change: &'static str) -> Result<Chat, AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!("UPDATE chats SET {change} WHERE id = $1"))
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let chat = fetch_chat(&mut *tx, chat_id as _).await?;
+        tx.commit().await?;
+        Ok(chat)
+    }
+
+    async fn chat_of_workspace(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        self.get_chat_by_id(chat_id)
+            .await?
+            .filter(|chat| chat.ws_id == user.ws_id)
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))
+    }
+}
+
+fn settings_text<'a>(
+    value: Option<&'a str>,
+    field: &str,
+    max_len: usize,
+) -> Result<Option<&'a str>, AppError> {
+    let value = value.map(str::trim);
+    if let Some(v) = value {
+        if v.chars().count() > max_len {
+            return Err(AppError::UpdateChatError(format!(
+                "{field} must be at most {max_len} characters"
+            )));
+        }
+    }
+    Ok(value)
+}
+
+fn ensure_writable(chat: &Chat) -> Result<(), AppError> {
+    match chat.archived_at {
+        Some(_) => Err(AppError::ChatArchived(chat.id)),
+        None => Ok(()),
+    }
+}
+
+/// sorted members of a chat without a name, e.g. `1,2`
+fn member_key(name: Option<&str>, members: &[i64]) -> Option<String> {
+    if name.is_some() {
+        return None;
+    }
+    let mut members = members.to_vec();
+    members.sort_unstable();
+    members.dedup();
+    let members: Vec<String> = members.iter().map(i64::to_string).collect();
+    Some(members.join(","))
+}
+
+/// the chat which stands for the member set, other than `except`
+async fn member_key_owner(
+    executor: impl PgExecutor<'_>,
+    ws_id: i64,
+    key: Option<&str>,
+    except: i64,
+) -> Result<Option<i64>, AppError> {
+    let id = sqlx::query_scalar(
+        r#"
+        SELECT id FROM chats
+        WHERE ws_id = $1 AND member_key = $2 AND deleted_at IS NULL AND id <> $3
+        "#,
+    )
+    .bind(ws_id)
+    .bind(key)
+    .bind(except)
+    .fetch_optional(executor)
+    .await?;
+    Ok(id)
+}
+
+async fn set_member_key(
+    conn: &mut PgConnection,
+    ws_id: i64,
+    chat_id: i64,
+    key: Option<&str>,
+) -> Result<(), AppError> {
+    if let Some(other) = member_key_owner(&mut *conn, ws_id, key, chat_id).await? {
+        return Err(AppError::CreateChatError(format!(
+            "chat {other} already has these members"
+        )));
+    }
+    sqlx::query("UPDATE chats SET member_key = $2 WHERE id = $1")
+        .bind(chat_id)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// make `members` the members of the chat, members who stay keep their role and read state
+pub(crate) async fn set_chat_members(
+    conn: &mut PgConnection,
+    chat_id: i64,
+    members: &[i64],
+) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id <> ALL($2)")
+        .bind(chat_id)
+        .bind(members)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO chat_members (chat_id, user_id)
+        SELECT $1, unnest($2::BIGINT[])
+        ON CONFLICT (chat_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(chat_id)
+    .bind(members)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// drop the member from the chat, the longest standing member takes over when the last owner left
+pub(crate) async fn delete_chat_member(
+    conn: &mut PgConnection,
+    chat_id: i64,
+    member_id: i64,
+) -> Result<bool, AppError> {
+    let ret = sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+        .bind(chat_id)
+        .bind(member_id)
+        .execute(&mut *conn)
+        .await?;
+    if ret.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query(
+        r#"
+        UPDATE chat_members SET role = 'owner'
+        WHERE chat_id = $1
+          AND NOT EXISTS(SELECT 1 FROM chat_members WHERE chat_id = $1 AND role = 'owner')
+          AND user_id = (SELECT user_id FROM chat_members WHERE chat_id = $1
+                         ORDER BY role, joined_at, user_id LIMIT 1)
+        "#,
+    )
+    .bind(chat_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+pub(crate) async fn fetch_chat(executor: impl PgExecutor<'_>, id: i64) -> Result<Chat, AppError> {
+    let chat: Option<Chat> = sqlx::query_as(&format!(
+        r#"
+        SELECT {CHAT_COLUMNS}
+        FROM chats c
+        WHERE c.id = $1
+        "#
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await?;
+    chat.ok_or_else(|| AppError::NotFound(format!("chat id {id}")))
+}
 #[cfg(test)]
 impl CreateChat {
     // pub name: Option<String>,
@@ -168,6 +724,7 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chat_core::ChatRole;
     #[tokio::test]
     async fn create_single_chat_should_work() {
         let (_tdb, state) = AppState::new_for_test().await.unwrap();
@@ -182,7 +739,7 @@ mod tests {
         assert_eq!(chat.r#type, ChatType::Single);
     }
     #[tokio::test]
-    async fn update_single_chat_should_work() {
+    async fn update_single_chat_should_fail() {
         let (_tdb, state) = AppState::new_for_test().await.unwrap();
 
         let input = CreateChat::new("", &[1, 2], false);
@@ -190,16 +747,46 @@ mod tests {
             .create_chat(input, 1)
             .await
             .expect("create chat failed");
-        println!("{chat:?}");
-        let input = CreateChat::new("123", &[1, 2, 3], true);
+        let input = UpdateChat {
+            name: Some("123".to_string()),
+            public: Some(true),
+        };
+        let ret = state.update_chat(&input, chat.id as _).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
         let chat = state
-            .update_chat(input, chat.id as _)
+            .get_chat_by_id(chat.id as _)
             .await
-            .expect("create chat failed");
-        println!("{chat:?}");
-        assert_eq!(chat.ws_id, 1);
-        assert_eq!(chat.members.len(), 3);
+            .unwrap()
+            .expect("chat should exist");
+        assert_eq!(chat.members, [1, 2]);
+        assert_eq!(chat.r#type, ChatType::Single);
+    }
+
+    #[tokio::test]
+    async fn update_channel_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = UpdateChat {
+            name: Some(" acme ".to_string()),
+            ..Default::default()
+        };
+        let chat = state.update_chat(&input, 2).await?;
+        assert_eq!(chat.name.as_deref(), Some("acme"));
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+        let input = UpdateChat {
+            public: Some(true),
+            ..Default::default()
+        };
+        let chat = state.update_chat(&input, 2).await?;
+        assert_eq!(chat.name.as_deref(), Some("acme"));
         assert_eq!(chat.r#type, ChatType::PublicChannel);
+        // a channel keeps its name
+        let input = UpdateChat {
+            name: Some(" ".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_chat(&input, 2).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+        Ok(())
     }
 
     #[tokio::test]
@@ -277,6 +864,16 @@ mod tests {
         assert_eq!(chats.len(), 4);
     }
 
+    #[tokio::test]
+    async fn fetch_member_chats_should_only_return_joined_chats() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chats = state.fetch_member_chats(1, 4, false).await?;
+        assert_eq!(chats.len(), 2);
+        assert!(chats.iter().all(|chat| chat.members.contains(&4)));
+        assert!(state.fetch_member_chats(1, 6, false).await?.is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_member_should_work() {
         let (_tdb, state) = AppState::new_for_test().await.unwrap();
@@ -292,4 +889,216 @@ mod tests {
         let is_member = state.is_chat_member(2, 4).await.expect("is member failed");
         assert!(!is_member);
     }
+
+    #[tokio::test]
+    async fn add_and_remove_chat_members_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let input = AddChatMembers {
+            members: vec![4, 5, 3],
+        };
+        let chat = state.add_chat_members(&user, 2, &input).await?;
+        assert_eq!(chat.members, vec![1, 2, 3, 4, 5]);
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+
+        // members remove themselves, removing others is up to moderators
+        let ret = state.remove_chat_member(&user, 2, 4).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let chat = state.leave_chat(&user, 2).await?;
+        assert_eq!(chat.members, vec![1, 3, 4, 5]);
+        let ret = state.leave_chat(&user, 2).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let ret = state.leave_chat(&user, 3).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        let input = AddChatMembers { members: vec![99] };
+        let ret = state.add_chat_members(&user, 4, &input).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn locked_chat_should_only_take_moderators() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let moderator = state.find_user_by_id(3).await?.expect("user should exist");
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.lock_chat(&member, 1).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let chat = state.lock_chat(&moderator, 1).await?;
+        assert!(chat.locked_at.is_some());
+        let ret = state
+            .authorize_chat(&member, 1, ChatAction::SendMessage)
+            .await;
+        assert!(matches!(ret, Err(AppError::ChatLocked(1))));
+        state
+            .authorize_chat(&moderator, 1, ChatAction::SendMessage)
+            .await?;
+        let chat = state.unlock_chat(&moderator, 1).await?;
+        assert!(chat.locked_at.is_none());
+
+        // the moderator takes over once the owner left
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        state.leave_chat(&owner, 1).await?;
+        let members = state.list_chat_members(1).await?;
+        assert!(members
+            .iter()
+            .any(|m| m.user_id == 3 && m.role == ChatRole::Owner));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archived_chat_should_be_read_only() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let chat = state.archive_chat(&user, 2).await?;
+        assert!(chat.archived_at.is_some());
+        let ret = state.ensure_chat_writable(2).await;
+        assert!(matches!(ret, Err(AppError::ChatArchived(2))));
+        let input = AddChatMembers { members: vec![4] };
+        let ret = state.add_chat_members(&user, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::ChatArchived(2))));
+
+        // hidden from the default list
+        let chats = state.fetch_member_chats(1, 1, false).await?;
+        assert!(chats.iter().all(|chat| chat.id != 2));
+        let chats = state.fetch_member_chats(1, 1, true).await?;
+        assert!(chats.iter().any(|chat| chat.id == 2));
+
+        let chat = state.unarchive_chat(&user, 2).await?;
+        assert!(chat.archived_at.is_none());
+        state.ensure_chat_writable(2).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_chat_should_be_restored_or_purged() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let chat = state.delete_chat(1).await?;
+        assert!(chat.deleted_at.is_some());
+        assert_eq!(state.get_chat_by_id(1).await?, None);
+        assert!(!state.is_chat_member(1, 1).await?);
+        assert!(state.purge_deleted_chats().await?.is_empty());
+
+        let chat = state.restore_chat(&user, 1).await?;
+        assert!(chat.deleted_at.is_none());
+        assert!(state.is_chat_member(1, 1).await?);
+        let ret = state.restore_chat(&user, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        // messages go together with the chat
+        state.delete_chat(1).await?;
+        sqlx::query("UPDATE chats SET deleted_at = NOW() - INTERVAL '31 days' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.purge_deleted_chats().await?, vec![1]);
+        let messages: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE chat_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(messages, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_of_same_members_should_be_reused() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the fixtures have a single chat of 1 and 2, and a group of 1, 3 and 4
+        let (chat, created) = state
+            .create_or_get_chat(CreateChat::new("", &[2, 1], false), 1, None)
+            .await?;
+        assert!(!created);
+        assert_eq!(chat.id, 3);
+        let (chat, created) = state
+            .create_or_get_chat(CreateChat::new("", &[4, 3, 1], false), 1, None)
+            .await?;
+        assert!(!created);
+        assert_eq!(chat.id, 4);
+        let input = CreateChat {
+            name: Some("  ".to_string()),
+            members: vec![1, 2],
+            public: false,
+        };
+        let (chat, created) = state.create_or_get_chat(input, 1, Some(1)).await?;
+        assert!(!created);
+        assert_eq!(chat.id, 3);
+        // outsiders do not find the chats of others
+        let ret = state
+            .create_or_get_chat(CreateChat::new("", &[2, 1], false), 1, Some(3))
+            .await;
+        assert!(matches!(ret, Err(AppError::CreateChatError(_))));
+        let (_, created) = state
+            .create_or_get_chat(CreateChat::new("", &[1, 5], false), 1, None)
+            .await?;
+        assert!(created);
+        // named chats are never merged
+        let (chat, created) = state
+            .create_or_get_chat(CreateChat::new("team", &[1, 2], false), 1, Some(2))
+            .await?;
+        assert!(created);
+        let members = state.list_chat_members(chat.id as _).await?;
+        assert_eq!(members[1].role, ChatRole::Owner);
+
+        // a chat which lost members is no longer reused
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        state.leave_chat(&user, 4).await?;
+        let (chat, created) = state
+            .create_or_get_chat(CreateChat::new("", &[3, 4], false), 1, None)
+            .await?;
+        assert!(created);
+        assert_ne!(chat.id, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_settings_should_limit_posting() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let input = UpdateChatSettings {
+            topic: Some(" release ".to_string()),
+            notification_level: Some(NotificationLevel::Mentions),
+            ..Default::default()
+        };
+        let chat = state.update_chat_settings(&member, 2, &input).await?;
+        assert_eq!(chat.topic.as_deref(), Some("release"));
+        assert_eq!(chat.notification_level, NotificationLevel::Mentions);
+        let input = UpdateChatSettings {
+            topic: Some("x".repeat(251)),
+            ..Default::default()
+        };
+        let ret = state.update_chat_settings(&member, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::UpdateChatError(_))));
+
+        let input = UpdateChatSettings {
+            posting_policy: Some(PostingPolicy::Admins),
+            members_can_invite: Some(false),
+            ..Default::default()
+        };
+        let ret = state.update_chat_settings(&member, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let chat = state.update_chat_settings(&user, 2, &input).await?;
+        assert_eq!(chat.posting_policy, PostingPolicy::Admins);
+        assert_eq!(chat.topic.as_deref(), Some("release"));
+        state.ensure_can_post(&user, 2).await?;
+        let ret = state.ensure_can_post(&member, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let input = AddChatMembers { members: vec![5] };
+        let ret = state.add_chat_members(&member, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        // an empty topic clears it
+        let input = UpdateChatSettings {
+            topic: Some(String::new()),
+            ..Default::default()
+        };
+        let chat = state.update_chat_settings(&member, 2, &input).await?;
+        assert_eq!(chat.topic, None);
+        Ok(())
+    }
 }
@@ -0,0 +1,333 @@
+use crate::policy::{can_moderate, Action, ChatAction};
+use crate::{AppError, AppState};
+use chat_core::{ChatRole, PostingPolicy, User};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// longest mute, 30 days
+const MAX_MUTE_MINUTES: i64 = 30 * 24 * 60;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatMember {
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub role: ChatRole,
+    /// the member does not post until then
+    pub muted_until: Option<DateTime<Local>>,
+    pub joined_at: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+pub struct UpdateChatRole {
+    pub role: ChatRole,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, Deserialize)]
+pub struct MuteMember {
+    /// at most 30 days
+    pub minutes: i64,
+}
+
+/// a membership together with the state of the chat the checks depend on
+#[derive(Debug, FromRow)]
+struct Membership {
+    #[sqlx(flatten)]
+    member: ChatMember,
+    locked: bool,
+    posting_policy: PostingPolicy,
+    members_can_invite: bool,
+}
+
+impl AppState {
+    /// check the chat policy for a member, returns the role they act with.
+    /// owners and admins of the workspace act as owners of every chat
+    pub async fn authorize_chat(
+        &self,
+        user: &User,
+        chat_id: u64,
+        action: ChatAction,
+    ) -> Result<ChatRole, AppError> {
+        let membership: Option<Membership> = sqlx::query_as(
+            r#"
+            SELECT m.chat_id, m.user_id, m.role, m.muted_until, m.joined_at,
+                   c.locked_at IS NOT NULL AS locked, c.posting_policy, c.members_can_invite
+            FROM chat_members m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.chat_id = $1 AND m.user_id = $2 AND c.deleted_at IS NULL
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(membership) = membership else {
+            return Err(AppError::ChatMemberError(format!(
+                "user {} is not a member of chat {chat_id}",
+                user.id
+            )));
+        };
+        let member = &membership.member;
+        let required = match action {
+            ChatAction::SendMessage => {
+                if let Some(until) = member.muted_until.filter(|v| *v > Local::now()) {
+                    return Err(AppError::ChatMuted(until.to_rfc3339()));
+                }
+                if membership.locked || membership.posting_policy == PostingPolicy::Admins {
+                    ChatRole::Moderator
+                } else {
+                    ChatRole::Member
+                }
+            }
+            ChatAction::AddMembers if !membership.members_can_invite => ChatRole::Moderator,
+            _ => action.min_role(),
+        };
+        if member.role.rank() >= required.rank() {
+            return Ok(member.role);
+        }
+        if Action::ModerateChats.allowed_for(self.workspace_role(user).await?) {
+            return Ok(ChatRole::Owner);
+        }
+        if action == ChatAction::SendMessage && membership.locked {
+            return Err(AppError::ChatLocked(member.chat_id));
+        }
+        Err(AppError::PermissionDenied(format!(
+            "a chat {} can not {action}",
+            member.role
+        )))
+    }
+
+    pub async fn list_chat_members(&self, chat_id: u64) -> Result<Vec<ChatMember>, AppError> {
+        let members = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, role, muted_until, joined_at
+            FROM chat_members
+            WHERE chat_id = $1
+            ORDER BY user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(members)
+    }
+
+    /// only owners give roles, the last owner of a chat can not step down
+    pub async fn set_chat_role(
+        &self,
+        user: &User,
+        chat_id: u64,
+        member_id: i64,
+        input: &UpdateChatRole,
+    ) -> Result<ChatMember, AppError> {
+        self.authorize_chat(user, chat_id, ChatAction::ManageRoles)
+            .await?;
+        let member = self.chat_member(chat_id, member_id).await?;
+        if member.role == ChatRole::Owner && input.role != ChatRole::Owner {
+            let owners: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM chat_members WHERE chat_id = $1 AND role = 'owner'",
+            )
+            .bind(chat_id as i64)
+            .fetch_one(&self.pool)
+            .await?;
+            if owners <= 1 {
+                return Err(AppError::ChatMemberError(
+                    "a chat keeps at least one owner".to_string(),
+                ));
+            }
+        }
+        let member = sqlx::query_as(
+            r#"
+            UPDATE chat_members SET role = $3
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING chat_id, user_id, role, muted_until, joined_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(member_id)
+        .bind(input.role)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// a muted member reads the chat but does not post until the mute ends
+    pub async fn mute_chat_member(
+        &self,
+        user: &User,
+        chat_id: u64,
+        member_id: i64,
+        input: &MuteMember,
+    ) -> Result<ChatMember, AppError> {
+        if !(1..=MAX_MUTE_MINUTES).contains(&input.minutes) {
+            return Err(AppError::ChatMemberError(format!(
+                "a mute lasts between 1 and {MAX_MUTE_MINUTES} minutes"
+            )));
+        }
+        self.moderate_member(user, chat_id, member_id).await?;
+        let member = sqlx::query_as(
+            r#"
+            UPDATE chat_members SET muted_until = NOW() + make_interval(mins => $3)
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING chat_id, user_id, role, muted_until, joined_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(member_id)
+        .bind(input.minutes as i32)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    pub async fn unmute_chat_member(
+        &self,
+        user: &User,
+        chat_id: u64,
+        member_id: i64,
+    ) -> Result<ChatMember, AppError> {
+        self.moderate_member(user, chat_id, member_id).await?;
+        let member = sqlx::query_as(
+            r#"
+            UPDATE chat_members SET muted_until = NULL
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING chat_id, user_id, role, muted_until, joined_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(member_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// moderators act on members, owners on everybody but themselves
+    pub(crate) async fn moderate_member(
+        &self,
+        user: &User,
+        chat_id: u64,
+        member_id: i64,
+    ) -> Result<ChatMember, AppError> {
+        let role = self
+            .authorize_chat(user, chat_id, ChatAction::ModerateMembers)
+            .await?;
+        if member_id == user.id {
+            return Err(AppError::ChatMemberError(
+                "members do not moderate themselves".to_string(),
+            ));
+        }
+        let member = self.chat_member(chat_id, member_id).await?;
+        if !can_moderate(role, member.role) {
+            return Err(AppError::PermissionDenied(format!(
+                "a chat {role} can not moderate a chat {}",
+                member.role
+            )));
+        }
+        Ok(member)
+    }
+
+    async fn chat_member(&self, chat_id: u64, member_id: i64) -> Result<ChatMember, AppError> {
+        sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, role, muted_until, joined_at
+            FROM chat_members
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(member_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user id {member_id} in chat {chat_id}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn authorize_chat_should_follow_chat_roles() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let role = state
+            .authorize_chat(&owner, 1, ChatAction::DeleteChat)
+            .await?;
+        assert_eq!(role, ChatRole::Owner);
+        state
+            .authorize_chat(&member, 1, ChatAction::SendMessage)
+            .await?;
+        let ret = state
+            .authorize_chat(&member, 1, ChatAction::UpdateChat)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.authorize_chat(&member, 4, ChatAction::Read).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        // admins of the workspace act as owners
+        sqlx::query("UPDATE workspace_members SET role = 'admin' WHERE user_id = 2")
+            .execute(&state.pool)
+            .await?;
+        let role = state
+            .authorize_chat(&member, 1, ChatAction::DeleteChat)
+            .await?;
+        assert_eq!(role, ChatRole::Owner);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn muted_member_should_not_post() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let moderator = state.find_user_by_id(3).await?.expect("user should exist");
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let input = MuteMember { minutes: 10 };
+        let muted = state.mute_chat_member(&moderator, 1, 2, &input).await?;
+        assert!(muted.muted_until.is_some());
+        let ret = state
+            .authorize_chat(&member, 1, ChatAction::SendMessage)
+            .await;
+        assert!(matches!(ret, Err(AppError::ChatMuted(_))));
+
+        // moderators act on members only
+        let ret = state.mute_chat_member(&moderator, 1, 1, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state.mute_chat_member(&member, 1, 3, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let input = MuteMember { minutes: 0 };
+        let ret = state.mute_chat_member(&moderator, 1, 2, &input).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        state.unmute_chat_member(&moderator, 1, 2).await?;
+        state
+            .authorize_chat(&member, 1, ChatAction::SendMessage)
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_chat_role_should_keep_an_owner() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateChatRole {
+            role: ChatRole::Member,
+        };
+        let ret = state.set_chat_role(&owner, 2, 1, &input).await;
+        assert!(matches!(ret, Err(AppError::ChatMemberError(_))));
+
+        let input = UpdateChatRole {
+            role: ChatRole::Owner,
+        };
+        let member = state.set_chat_role(&owner, 2, 2, &input).await?;
+        assert_eq!(member.role, ChatRole::Owner);
+        let input = UpdateChatRole {
+            role: ChatRole::Moderator,
+        };
+        let member = state.set_chat_role(&owner, 2, 1, &input).await?;
+        assert_eq!(member.role, ChatRole::Moderator);
+        let ret = state.set_chat_role(&owner, 2, 3, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+}
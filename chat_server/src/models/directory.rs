@@ -0,0 +1,172 @@
+use crate::policy::Action;
+use crate::{AppError, AppState};
+use chat_core::{User, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Clone, Default, Serialize, IntoParams, ToSchema, Deserialize)]
+pub struct ListUsers {
+    /// prefix or fuzzy match on the name, display name and email
+    pub q: Option<String>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+    pub role: Option<WorkspaceRole>,
+    /// list deactivated instead of active members, for owners and admins
+    #[serde(default)]
+    pub deactivated: bool,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize)]
+pub struct DirectoryUser {
+    pub id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub is_bot: bool,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub role: WorkspaceRole,
+    pub deactivated_at: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct DirectoryPage {
+    pub users: Vec<DirectoryUser>,
+    /// not set on the last page
+    pub next_cursor: Option<i64>,
+}
+
+impl AppState {
+    /// a page of the members of the workspace of the user, ordered by id
+    pub async fn search_directory(
+        &self,
+        user: &User,
+        input: &ListUsers,
+    ) -> Result<DirectoryPage, AppError> {
+        self.authorize(user, Action::ReadDirectory).await?;
+        if input.deactivated {
+            self.authorize(user, Action::ManageMembers).await?;
+        }
+        let q = input
+            .q
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_lowercase());
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        // one more row tells whether there is a next page
+        let mut users: Vec<DirectoryUser> = sqlx::query_as(
+            r#"
+            SELECT u.id, u.fullname, u.email, u.is_bot, u.display_name, u.avatar,
+                   m.role, m.deactivated_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.ws_id = $1
+              AND u.id > $2
+              AND ($3::TEXT IS NULL
+                OR u.fullname ILIKE $4 || '%'
+                OR u.display_name ILIKE $4 || '%'
+                OR u.email ILIKE $4 || '%'
+                OR $3 <% u.fullname
+                OR $3 <% u.display_name)
+              AND ($5::workspace_role IS NULL OR m.role = $5)
+              AND (m.deactivated_at IS NOT NULL) = $6
+            ORDER BY u.id
+            LIMIT $7
+            "#,
+        )
+        .bind(user.ws_id)
+        .bind(input.cursor.unwrap_or(0))
+        .bind(&q)
+        .bind(q.as_deref().map(escape_like))
+        .bind(input.role)
+        .bind(input.deactivated)
+        .bind(limit + 1)
+        .fetch_all(&self.pool)
+        .await?;
+        let next_cursor = if users.len() as i64 > limit {
+            users.truncate(limit as usize);
+            users.last().map(|v| v.id)
+        } else {
+            None
+        };
+        Ok(DirectoryPage { users, next_cursor })
+    }
+}
+
+pub(crate) fn escape_like(q: &str) -> String {
+    q.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn directory_should_page_with_cursor() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let mut input = ListUsers {
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = state.search_directory(&user, &input).await?;
+        assert_eq!(page.users.len(), 3);
+        assert_eq!(page.next_cursor, Some(page.users[2].id));
+
+        let mut ids: Vec<i64> = page.users.iter().map(|v| v.id).collect();
+        input.cursor = page.next_cursor;
+        while let Some(cursor) = input.cursor {
+            let page = state.search_directory(&user, &input).await?;
+            assert!(page.users.iter().all(|v| v.id > cursor));
+            ids.extend(page.users.iter().map(|v| v.id));
+            input.cursor = page.next_cursor;
+        }
+        let all = state.fetch_chat_user_all(user.ws_id as _).await?;
+        assert_eq!(ids.len(), all.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn directory_should_match_names() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let other = state.find_user_by_id(2).await?.expect("user should exist");
+        let prefix: String = other.fullname.chars().take(3).collect();
+        let input = ListUsers {
+            q: Some(prefix.to_uppercase()),
+            ..Default::default()
+        };
+        let page = state.search_directory(&user, &input).await?;
+        assert!(page.users.iter().any(|v| v.id == other.id));
+
+        let input = ListUsers {
+            q: Some("%".to_string()),
+            ..Default::default()
+        };
+        assert!(state
+            .search_directory(&user, &input)
+            .await?
+            .users
+            .is_empty());
+
+        // only owners and admins see deactivated members
+        let input = ListUsers {
+            deactivated: true,
+            ..Default::default()
+        };
+        let ret = state.search_directory(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+}
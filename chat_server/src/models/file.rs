@@ -53,6 +53,16 @@ impl FromStr for ChatFile {
                 parts[1]
             )));
         };
+        // the hash is split after 3 and 6 hex digits, see `hash_to_url`
+        let is_hex = |v: &str| !v.is_empty() && v.chars().all(|c| c.is_ascii_hexdigit());
+        if parts[1].len() != 3
+            || parts[2].len() != 3
+            || ![parts[1], parts[2], part3].into_iter().all(is_hex)
+        {
+            return Err(AppError::ChatFileError(format!(
+                "Invalid file hash in path {s:?}"
+            )));
+        }
         let hash = format!("{}{}{}", parts[1], parts[2], part3);
         Ok(Self {
             ws_id,
@@ -73,4 +83,14 @@ mod tests {
         assert_eq!(file.ws_id, 1);
         assert_eq!(file.hash, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
     }
+
+    #[test]
+    fn chat_file_from_str_should_check_hash() -> anyhow::Result<()> {
+        let file = ChatFile::new(1, "test.txt", b"hello");
+        assert_eq!(ChatFile::from_str(&file.url())?.hash, file.hash);
+        assert!(ChatFile::from_str("/files/1/a/b/c.png").is_err());
+        assert!(ChatFile::from_str("/files/1/../../etc.png").is_err());
+        assert!(ChatFile::from_str("/files/1/aaf/4c6/.png").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,315 @@
+use super::token::{generate_token, hash_token};
+use super::workspace::add_workspace_member;
+use crate::policy::Action;
+use crate::{AppError, AppState, Mail};
+use chat_core::{User, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use utoipa::ToSchema;
+
+const DEFAULT_INVITE_DAYS: u32 = 7;
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateInvite {
+    /// send the invite to this address, only it may redeem the invite
+    pub email: Option<String>,
+    /// unlimited when not set, email invites default to a single use
+    pub max_uses: Option<i32>,
+    /// defaults to 7 days
+    pub expires_in_days: Option<u32>,
+    /// member or guest, defaults to member
+    pub role: Option<WorkspaceRole>,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct Invite {
+    pub id: i64,
+    pub ws_id: i64,
+    pub email: Option<String>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub created_by: i64,
+    pub role: WorkspaceRole,
+    pub expires_at: DateTime<Local>,
+    pub revoked_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+/// returned once on creation, the code itself can't be read again
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct InviteCreated {
+    #[serde(flatten)]
+    pub invite: Invite,
+    pub code: String,
+    /// signup link carrying the code
+    pub url: String,
+}
+
+impl AppState {
+    pub async fn create_invite(
+        &self,
+        owner: &User,
+        input: &CreateInvite,
+    ) -> Result<InviteCreated, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let max_uses = match (&input.email, input.max_uses) {
+            (_, Some(max_uses)) if max_uses < 1 => {
+                return Err(AppError::CreateInviteError(
+                    "max_uses must be at least 1".to_string(),
+                ))
+            }
+            (Some(_), None) => Some(1),
+            (_, max_uses) => max_uses,
+        };
+        let days = input.expires_in_days.unwrap_or(DEFAULT_INVITE_DAYS);
+        if days == 0 {
+            return Err(AppError::CreateInviteError(
+                "expires_in_days must be at least 1".to_string(),
+            ));
+        }
+        let role = input.role.unwrap_or(WorkspaceRole::Member);
+        if !matches!(role, WorkspaceRole::Member | WorkspaceRole::Guest) {
+            return Err(AppError::CreateInviteError(format!(
+                "invites can not grant the {role} role"
+            )));
+        }
+        let code = generate_token();
+        let invite: Invite = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_invites (ws_id, code_hash, email, max_uses, created_by, role, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7 * INTERVAL '1 day')
+            RETURNING id, ws_id, email, max_uses, uses, created_by, role, expires_at, revoked_at, created_at
+            "#,
+        )
+        .bind(owner.ws_id)
+        .bind(hash_token(&code))
+        .bind(&input.email)
+        .bind(max_uses)
+        .bind(owner.id)
+        .bind(role)
+        .bind(days as f64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let url = format!("{}/signup?invite={}", self.config.mail.base_url, code);
+        if let Some(email) = &input.email {
+            let mail = Mail::new(
+                email,
+                format!("{} invited you to chat", owner.fullname),
+                format!(
+                    "Hi,\n\n{} invited you to join their workspace, sign up or sign in through the link below:\n\n{}\n",
+                    owner.fullname, url
+                ),
+            );
+            self.mailer.send(mail).await?;
+        }
+        Ok(InviteCreated { invite, code, url })
+    }
+
+    pub async fn list_invites(&self, owner: &User) -> Result<Vec<Invite>, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, email, max_uses, uses, created_by, role, expires_at, revoked_at, created_at
+            FROM workspace_invites
+            WHERE ws_id = $1
+            ORDER BY id DESC
+            "#,
+        )
+        .bind(owner.ws_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(invites)
+    }
+
+    pub async fn revoke_invite(&self, owner: &User, id: i64) -> Result<(), AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let ret = sqlx::query(
+            r#"
+            UPDATE workspace_invites SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND ws_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(owner.ws_id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("invite id {id}")));
+        }
+        Ok(())
+    }
+
+    /// use up one redemption of the invite, returns the id of its workspace and the role it grants
+    pub async fn redeem_invite(
+        &self,
+        code: &str,
+        email: &str,
+    ) -> Result<(i64, WorkspaceRole), AppError> {
+        redeem(&self.pool, code, email).await
+    }
+
+    /// add a signed in user to the workspace of the invite and make it their current one,
+    /// members keep the role they already have
+    pub async fn accept_invite(&self, user: User, code: &str) -> Result<User, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let (ws_id, role) = redeem(&mut *tx, code, &user.email).await?;
+        add_workspace_member(&mut *tx, ws_id, user.id, role).await?;
+        let user = sqlx::query_as(
+            r#"
+            UPDATE users SET ws_id = $2 WHERE id = $1
+            RETURNING id, ws_id, fullname, email, is_bot, created_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(ws_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+}
+
+pub(super) async fn redeem(
+    executor: impl PgExecutor<'_>,
+    code: &str,
+    email: &str,
+) -> Result<(i64, WorkspaceRole), AppError> {
+    let invite: Option<(i64, WorkspaceRole)> = sqlx::query_as(
+        r#"
+        UPDATE workspace_invites SET uses = uses + 1
+        WHERE code_hash = $1
+          AND revoked_at IS NULL
+          AND expires_at > NOW()
+          AND (max_uses IS NULL OR uses < max_uses)
+          AND (email IS NULL OR LOWER(email) = LOWER($2))
+        RETURNING ws_id, role
+        "#,
+    )
+    .bind(hash_token(code))
+    .bind(email.trim())
+    .fetch_optional(executor)
+    .await?;
+    invite.ok_or_else(|| AppError::InvalidToken("invite is invalid or expired".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+
+    #[tokio::test]
+    async fn invite_link_should_respect_max_uses() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let input = CreateInvite {
+            max_uses: Some(1),
+            ..Default::default()
+        };
+        let created = state.create_invite(&owner, &input).await?;
+        assert!(created.url.ends_with(&created.code));
+
+        // a signup which fails does not use up the invite
+        let mut input = CreateUser::new("", "new@acme.org", &"x".repeat(100), "Hunter42");
+        input.invite = Some(created.code.clone());
+        assert!(state.create_user(&input).await.is_err());
+
+        let mut input = CreateUser::new("", "new@acme.org", "new", "Hunter42");
+        input.invite = Some(created.code.clone());
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, owner.ws_id);
+
+        let mut input = CreateUser::new("", "other@acme.org", "other", "Hunter42");
+        input.invite = Some(created.code);
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+
+        let invites = state.list_invites(&owner).await?;
+        assert_eq!(invites[0].uses, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_invite_should_be_bound_to_the_address() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let input = CreateInvite {
+            email: Some("new@acme.org".to_string()),
+            ..Default::default()
+        };
+        let created = state.create_invite(&owner, &input).await?;
+        assert_eq!(created.invite.max_uses, Some(1));
+        let mail = state
+            .outbox()
+            .and_then(|v| v.last_mail_to("new@acme.org"))
+            .expect("mail sent");
+        assert!(mail.body.contains(&created.url));
+
+        assert!(state
+            .redeem_invite(&created.code, "other@acme.org")
+            .await
+            .is_err());
+        assert_eq!(
+            state.redeem_invite(&created.code, "New@acme.org").await?,
+            (owner.ws_id, WorkspaceRole::Member)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoked_invite_should_not_be_redeemed() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let created = state
+            .create_invite(&owner, &CreateInvite::default())
+            .await?;
+        state.revoke_invite(&owner, created.invite.id).await?;
+        assert!(state
+            .redeem_invite(&created.code, "new@acme.org")
+            .await
+            .is_err());
+
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.create_invite(&member, &CreateInvite::default()).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signup_should_not_join_workspace_by_name() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "new@acme.org", "new", "Hunter42");
+        let ret = state.create_user(&input).await;
+        assert!(matches!(ret, Err(AppError::WorkspaceAlreadyExists(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn accept_invite_should_add_membership() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let acme_owner = state.workspace_owner_for_test().await?;
+        let input = CreateUser::new("foo2", "owner@foo.org", "owner", "Hunter42");
+        let foo_owner = state.create_user(&input).await?;
+        let input = CreateInvite {
+            role: Some(WorkspaceRole::Guest),
+            ..Default::default()
+        };
+        let created = state.create_invite(&foo_owner, &input).await?;
+
+        // the owner of acme keeps their workspace and joins foo as a guest
+        let user = state.accept_invite(acme_owner, &created.code).await?;
+        assert_eq!(user.ws_id, foo_owner.ws_id);
+        assert_eq!(state.workspace_role(&user).await?, WorkspaceRole::Guest);
+        let workspaces = state.list_user_workspaces(user.id).await?;
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(workspaces[0].role, WorkspaceRole::Owner);
+
+        let input = CreateInvite {
+            role: Some(WorkspaceRole::Admin),
+            ..Default::default()
+        };
+        let ret = state.create_invite(&foo_owner, &input).await;
+        assert!(matches!(ret, Err(AppError::CreateInviteError(_))));
+        Ok(())
+    }
+}
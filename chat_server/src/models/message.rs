@@ -1,5 +1,6 @@
+use crate::policy::ChatAction;
 use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
+use chat_core::{Message, User};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use utoipa::{IntoParams, ToSchema};
@@ -24,6 +25,7 @@ impl AppState {
         user_id: u64,
     ) -> Result<Message, AppError> {
         let base_dir = &self.config.server.base_dir;
+        self.ensure_chat_writable(chat_id).await?;
         if input.content.is_empty() {
             return Err(AppError::CreateMessageError(
                 "Content cannot be empty".to_string(),
@@ -46,7 +48,7 @@ impl AppState {
         let message: Message = sqlx::query_as(
             r#"
         INSERT INTO messages (chat_id,sender_id,content,files) VALUES ($1,$2,$3,$4)
-        RETURNING id ,chat_id,sender_id,content,files,created_at
+        RETURNING id ,chat_id,sender_id,content,files,is_bot,created_at
         "#,
         )
         .bind(chat_id as i64)
@@ -58,6 +60,42 @@ impl AppState {
         .expect("6666");
         Ok(message)
     }
+    /// senders delete their own messages, moderators those of anybody
+    pub async fn delete_message(
+        &self,
+        user: &User,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Message, AppError> {
+        let sender_id: Option<i64> =
+            sqlx::query_scalar("SELECT sender_id FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(message_id as i64)
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(sender_id) = sender_id else {
+            return Err(AppError::NotFound(format!(
+                "message id {message_id} in chat {chat_id}"
+            )));
+        };
+        let action = if sender_id == user.id {
+            ChatAction::Read
+        } else {
+            ChatAction::DeleteMessages
+        };
+        self.authorize_chat(user, chat_id, action).await?;
+        self.ensure_chat_writable(chat_id).await?;
+        let message = sqlx::query_as(
+            r#"
+            DELETE FROM messages WHERE id = $1
+            RETURNING id, chat_id, sender_id, content, files, is_bot, created_at
+            "#,
+        )
+        .bind(message_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(message)
+    }
     #[allow(unused)]
     pub async fn list_messages(
         &self,
@@ -68,7 +106,7 @@ impl AppState {
 
         let messages: Vec<Message> = sqlx::query_as(
             r#"
-            SELECT id, chat_id,sender_id, content, files ,created_at
+            SELECT id, chat_id,sender_id, content, files, is_bot, created_at
             FROM messages
             WHERE chat_id = $1
             AND id < $2
@@ -145,6 +183,22 @@ mod tests {
         assert_eq!(messages.len(), 4);
     }
 
+    #[tokio::test]
+    async fn delete_message_should_need_sender_or_moderator() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let moderator = state.find_user_by_id(3).await?.expect("user should exist");
+        let message = state.delete_message(&member, 1, 1).await?;
+        assert_eq!(message.sender_id, 2);
+        let ret = state.delete_message(&member, 1, 2).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let message = state.delete_message(&moderator, 1, 3).await?;
+        assert_eq!(message.sender_id, 4);
+        let ret = state.delete_message(&moderator, 1, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> anyhow::Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello word");
         let path = file.path(&state.config.server.base_dir);
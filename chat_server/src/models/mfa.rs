@@ -0,0 +1,322 @@
+use super::token::hash_token;
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::utils::MfaClaims;
+use chat_core::User;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+use utoipa::ToSchema;
+
+const MFA_ISSUER: &str = "chat";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP: u64 = 30;
+/// number of steps before and after the current one which are still accepted
+const TOTP_SKEW: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MfaEnrollment {
+    /// base32 secret, for authenticators which can't scan the QR code
+    pub secret: String,
+    /// `otpauth://` URI, rendered as QR code by the client
+    pub otpauth_uri: String,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct MfaCode {
+    pub code: String,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    /// shown only once, every code can be used a single time instead of a totp code
+    pub codes: Vec<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct MfaRow {
+    secret: String,
+    last_used_step: i64,
+    enabled: bool,
+}
+
+impl AppState {
+    /// start the enrollment with a new secret, a previous unconfirmed enrollment is replaced
+    pub async fn enroll_mfa(&self, user: &User) -> Result<MfaEnrollment, AppError> {
+        if self.is_mfa_enabled(user.id).await? {
+            return Err(AppError::MfaError("mfa is already enabled".to_string()));
+        }
+        let mut bytes = [0u8; 20];
+        OsRng.fill_bytes(&mut bytes);
+        let Secret::Encoded(secret) = Secret::Raw(bytes.to_vec()).to_encoded() else {
+            unreachable!("to_encoded always returns an encoded secret");
+        };
+        let totp = build_totp(&secret, &user.email)?;
+        sqlx::query(
+            r#"
+            INSERT INTO user_mfa (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET secret = EXCLUDED.secret, last_used_step = 0, created_at = NOW()
+            "#,
+        )
+        .bind(user.id)
+        .bind(&secret)
+        .execute(&self.pool)
+        .await?;
+        Ok(MfaEnrollment {
+            secret,
+            otpauth_uri: totp.get_url(),
+        })
+    }
+
+    /// finish the enrollment with a code from the authenticator, returns the recovery codes
+    pub async fn confirm_mfa(&self, user: &User, code: &str) -> Result<RecoveryCodes, AppError> {
+        let Some(row) = self.fetch_mfa(user.id).await? else {
+            return Err(AppError::MfaError("mfa enrollment not started".to_string()));
+        };
+        if row.enabled {
+            return Err(AppError::MfaError("mfa is already enabled".to_string()));
+        }
+        if !self.check_totp(user, &row, code).await? {
+            return Err(AppError::MfaError("invalid mfa code".to_string()));
+        }
+        sqlx::query("UPDATE user_mfa SET enabled_at = NOW() WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&self.pool)
+            .await?;
+        self.regenerate_recovery_codes(user.id).await
+    }
+
+    /// turn mfa off, requires a valid totp or recovery code
+    pub async fn disable_mfa(&self, user: &User, code: &str) -> Result<(), AppError> {
+        // wrong codes count against the signin lockout, so a stolen token can't brute-force them
+        if let Some(retry_after) = self.signin_lockout(&user.email, None).await? {
+            return Err(AppError::SigninLocked(retry_after));
+        }
+        if !self.verify_mfa_code(user, code).await? {
+            self.record_signin_failure(&user.email, None).await?;
+            return Err(AppError::MfaError("invalid mfa code".to_string()));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM user_mfa WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn is_mfa_enabled(&self, user_id: i64) -> Result<bool, AppError> {
+        Ok(self
+            .fetch_mfa(user_id)
+            .await?
+            .is_some_and(|row| row.enabled))
+    }
+
+    /// check a totp code, or consume a recovery code, of a user with mfa enabled
+    pub async fn verify_mfa_code(&self, user: &User, code: &str) -> Result<bool, AppError> {
+        let Some(row) = self.fetch_mfa(user.id).await?.filter(|row| row.enabled) else {
+            return Ok(false);
+        };
+        if self.check_totp(user, &row, code).await? {
+            return Ok(true);
+        }
+        self.consume_recovery_code(user.id, code).await
+    }
+
+    /// mark the "mfa pending" token as used, returns `false` if it was used before
+    pub async fn consume_mfa_token(&self, claims: &MfaClaims) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO revoked_tokens (user_id, jti, expires_at)
+            SELECT $1, $2, to_timestamp($3)
+            WHERE NOT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $2)
+            "#,
+        )
+        .bind(claims.uid)
+        .bind(&claims.jti)
+        .bind(claims.expires_at as f64)
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() == 1)
+    }
+
+    async fn fetch_mfa(&self, user_id: i64) -> Result<Option<MfaRow>, AppError> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT secret, last_used_step, enabled_at IS NOT NULL AS enabled
+            FROM user_mfa
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// accept the code once per time step, the step is recorded so it can't be replayed
+    async fn check_totp(&self, user: &User, row: &MfaRow, code: &str) -> Result<bool, AppError> {
+        let totp = build_totp(&row.secret, &user.email)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| AppError::MfaError(e.to_string()))?
+            .as_secs();
+        let current = now / TOTP_STEP;
+        let step = (current - TOTP_SKEW..=current + TOTP_SKEW)
+            .filter(|step| *step as i64 > row.last_used_step)
+            .find(|step| totp.check(code, step * TOTP_STEP));
+        let Some(step) = step else {
+            return Ok(false);
+        };
+        let ret = sqlx::query(
+            r#"
+            UPDATE user_mfa SET last_used_step = $2
+            WHERE user_id = $1 AND last_used_step < $2
+            "#,
+        )
+        .bind(user.id)
+        .bind(step as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() == 1)
+    }
+
+    async fn regenerate_recovery_codes(&self, user_id: i64) -> Result<RecoveryCodes, AppError> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let hashes: Vec<String> = codes
+            .iter()
+            .map(|v| hash_token(&normalize_recovery_code(v)))
+            .collect();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO mfa_recovery_codes (user_id, code_hash)
+            SELECT $1, UNNEST($2::CHAR(64)[])
+            "#,
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(RecoveryCodes { codes })
+    }
+
+    async fn consume_recovery_code(&self, user_id: i64, code: &str) -> Result<bool, AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE mfa_recovery_codes SET used_at = NOW()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(hash_token(&normalize_recovery_code(code)))
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+}
+
+fn build_totp(secret: &str, email: &str) -> Result<TOTP, AppError> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::MfaError(format!("{e:?}")))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP,
+        secret,
+        Some(MFA_ISSUER.to_string()),
+        email.to_string(),
+    )
+    .map_err(|e| AppError::MfaError(e.to_string()))
+}
+
+/// 10 hex digits grouped as `xxxxx-xxxxx`
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+impl AppState {
+    /// the code the authenticator of the user currently shows
+    pub(crate) async fn current_totp_code(&self, user: &User) -> anyhow::Result<String> {
+        let row = self.fetch_mfa(user.id).await?.expect("mfa enrolled");
+        Ok(build_totp(&row.secret, &user.email)?.generate_current()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_code_should_be_normalized() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            normalize_recovery_code(&code.to_uppercase()),
+            code.replace('-', "")
+        );
+    }
+
+    #[tokio::test]
+    async fn mfa_enrollment_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let enrollment = state.enroll_mfa(&user).await?;
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/chat:"));
+        assert!(enrollment.otpauth_uri.contains(&enrollment.secret));
+        assert!(!state.is_mfa_enabled(1).await?);
+
+        assert!(state.confirm_mfa(&user, "000000").await.is_err());
+        let code = state.current_totp_code(&user).await?;
+        let recovery = state.confirm_mfa(&user, &code).await?;
+        assert_eq!(recovery.codes.len(), RECOVERY_CODE_COUNT);
+        assert!(state.is_mfa_enabled(1).await?);
+        // a second enrollment is rejected while mfa is on
+        assert!(state.enroll_mfa(&user).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mfa_code_should_not_be_replayed() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        state.enroll_mfa(&user).await?;
+        let code = state.current_totp_code(&user).await?;
+        let recovery = state.confirm_mfa(&user, &code).await?;
+        assert!(!state.verify_mfa_code(&user, &code).await?);
+
+        let recovery_code = &recovery.codes[0];
+        assert!(state.verify_mfa_code(&user, recovery_code).await?);
+        assert!(!state.verify_mfa_code(&user, recovery_code).await?);
+
+        state.disable_mfa(&user, &recovery.codes[1]).await?;
+        assert!(!state.is_mfa_enabled(1).await?);
+        Ok(())
+    }
+}
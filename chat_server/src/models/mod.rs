@@ -1,13 +1,47 @@
+mod api_key;
+mod audit;
+mod channel;
 mod chat;
+mod chat_member;
+mod directory;
 mod file;
+mod invite;
 mod message;
+mod mfa;
+mod oidc;
+mod profile;
+mod role;
+mod scim;
+mod signin_attempt;
+mod token;
 mod user;
+mod user_token;
 mod workspace;
 
-pub use chat::CreateChat;
+pub use api_key::{ApiKey, ApiKeyCreated, CreateApiKey, CreateBot, API_KEY_PREFIX};
+pub use audit::AuditLog;
+pub use channel::{Channel, ChannelPage, ListChannels, PreviewChannel};
+pub use chat::{AddChatMembers, CreateChat, ListChats, UpdateChat, UpdateChatSettings};
+pub use chat_member::{ChatMember, MuteMember, UpdateChatRole};
+pub use directory::{DirectoryPage, DirectoryUser, ListUsers};
+pub use invite::{CreateInvite, Invite, InviteCreated};
 pub use message::{CreateMessage, ListMessages};
+pub use mfa::{MfaCode, MfaEnrollment, RecoveryCodes};
+pub use oidc::OidcCallback;
+pub(crate) use oidc::OIDC_LOGIN_TTL;
+pub use profile::UpdateProfile;
+pub use role::{DeactivateUser, UpdateRole, WorkspaceMember};
+pub(crate) use scim::SCIM_ERROR_SCHEMA;
+pub use scim::{
+    ScimGroup, ScimGroupInput, ScimListQuery, ScimListResponse, ScimPatch, ScimToken,
+    ScimTokenCreated, ScimUser, ScimUserInput, SCIM_TOKEN_PREFIX,
+};
 use serde::{Deserialize, Serialize};
-pub use user::{CreateUser, SigninUser};
+pub use signin_attempt::SIGNIN_LOCKOUT_ACTION;
+pub use token::RotatedToken;
+pub use user::{ChangePassword, CreateUser, SigninUser, UpdateUser};
+pub use user_token::{ForgotPassword, ResetPassword, UserTokenKind, VerifyEmail};
+pub use workspace::{UpdateWorkspace, UserWorkspace};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatFile {
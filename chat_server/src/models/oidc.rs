@@ -0,0 +1,275 @@
+use super::token::{generate_token, hash_token};
+use super::workspace::add_workspace_member;
+use crate::oidc::{Identity, OidcClient};
+use crate::{AppError, AppState};
+use chat_core::{User, WorkspaceRole};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+/// seconds the user has to finish the login at the provider
+pub(crate) const OIDC_LOGIN_TTL: f64 = 600.0;
+
+/// query of the redirect back from the provider
+#[derive(Debug, Clone, ToSchema, IntoParams, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: String,
+    /// set by the provider when the login failed or was cancelled
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+impl AppState {
+    fn oidc_client(&self) -> Result<&OidcClient, AppError> {
+        self.oidc
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("oidc login is not configured".to_string()))
+    }
+
+    /// start an authorization code flow, returns the provider url to send the browser to
+    /// and the state the callback has to come back with
+    pub async fn oidc_login_url(&self) -> Result<(String, String), AppError> {
+        let client = self.oidc_client()?;
+        let state = generate_token();
+        let nonce = generate_token();
+        let code_verifier = generate_token();
+        let url = client
+            .authorization_url(&state, &nonce, &code_verifier)
+            .await?;
+
+        sqlx::query("DELETE FROM oidc_logins WHERE created_at < NOW() - $1 * INTERVAL '1 second'")
+            .bind(OIDC_LOGIN_TTL)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_logins (state_hash, nonce, code_verifier)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(hash_token(&state))
+        .bind(&nonce)
+        .bind(&code_verifier)
+        .execute(&self.pool)
+        .await?;
+        Ok((url, state))
+    }
+
+    /// finish the flow started by `oidc_login_url`, returns the local user of the identity
+    pub async fn oidc_signin(&self, input: &OidcCallback) -> Result<User, AppError> {
+        let client = self.oidc_client()?;
+        // the state is consumed even when the login failed, it is single use
+        let login: Option<(String, String)> = sqlx::query_as(
+            r#"
+            DELETE FROM oidc_logins
+            WHERE state_hash = $1 AND created_at > NOW() - $2 * INTERVAL '1 second'
+            RETURNING nonce, code_verifier
+            "#,
+        )
+        .bind(hash_token(&input.state))
+        .bind(OIDC_LOGIN_TTL)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((nonce, code_verifier)) = login else {
+            return Err(AppError::InvalidToken(
+                "oidc state is invalid or expired".to_string(),
+            ));
+        };
+        if let Some(error) = &input.error {
+            let description = input.error_description.as_deref().unwrap_or_default();
+            return Err(AppError::OidcError(format!("{error} {description}")));
+        }
+        let code = input
+            .code
+            .as_deref()
+            .ok_or_else(|| AppError::OidcError("callback has no code".to_string()))?;
+        let identity = client.exchange_code(code, &code_verifier, &nonce).await?;
+        if !identity.email_verified {
+            return Err(AppError::OidcError(
+                "email address is not verified by the provider".to_string(),
+            ));
+        }
+        self.find_or_create_oidc_user(&identity).await
+    }
+
+    /// users are matched by identity first, then linked by email, or created by domain rule
+    async fn find_or_create_oidc_user(&self, identity: &Identity) -> Result<User, AppError> {
+        let user_id: Option<i64> = sqlx::query_scalar(
+            "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2",
+        )
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(user) = match user_id {
+            Some(id) => self.find_user_by_id(id).await?,
+            None => None,
+        } {
+            return Ok(user);
+        }
+
+        let user: Option<User> = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, fullname, email, is_bot, created_at
+            FROM users WHERE LOWER(email) = LOWER($1)
+            "#,
+        )
+        .bind(&identity.email)
+        .fetch_optional(&self.pool)
+        .await?;
+        let user = match user {
+            Some(user) if user.is_bot => {
+                return Err(AppError::PermissionDenied(
+                    "bot accounts cannot sign in".to_string(),
+                ))
+            }
+            Some(user) => {
+                // the provider vouched for the address
+                sqlx::query(
+                    "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()) WHERE id = $1",
+                )
+                .bind(user.id)
+                .execute(&self.pool)
+                .await?;
+                user
+            }
+            None => self.create_oidc_user(identity).await?,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO user_identities (user_id, issuer, subject, email)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(user.id)
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .bind(&identity.email)
+        .execute(&self.pool)
+        .await?;
+        info!(
+            "linked {} of {} to user {}",
+            identity.subject, identity.issuer, user.id
+        );
+        Ok(user)
+    }
+
+    async fn create_oidc_user(&self, identity: &Identity) -> Result<User, AppError> {
+        let config = self.oidc_client()?.config();
+        let Some(workspace) = config.workspace_for(&identity.email) else {
+            return Err(AppError::PermissionDenied(format!(
+                "no workspace for {}",
+                identity.email
+            )));
+        };
+        let ws = match self.find_workspace_by_name(workspace).await? {
+            None => self.create_workspace(workspace, 0).await?,
+            Some(ws) => ws,
+        };
+        let fullname = identity.name.as_deref().unwrap_or(&identity.email);
+        // an empty hash never matches, the user can only sign in through the provider
+        let mut tx = self.pool.begin().await?;
+        let user: User = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, email, fullname, password_hash, email_verified_at)
+            VALUES ($1, $2, $3, '', NOW())
+            RETURNING id, ws_id, fullname, email, is_bot, created_at
+            "#,
+        )
+        .bind(ws.id)
+        .bind(&identity.email)
+        .bind(fullname)
+        .fetch_one(&mut *tx)
+        .await?;
+        add_workspace_member(&mut *tx, ws.id, user.id, WorkspaceRole::Member).await?;
+        tx.commit().await?;
+        if ws.owner_id == 0 {
+            self.update_workspace_owner(ws, user.id as _).await?;
+        }
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::mock::MockIdp;
+    use crate::SigninUser;
+
+    async fn signin(state: &AppState, idp: &MockIdp) -> anyhow::Result<User> {
+        let (url, _) = state.oidc_login_url().await?;
+        let (code, oidc_state) = idp.authorize(&url).await?;
+        let input = OidcCallback {
+            code: Some(code),
+            state: oidc_state,
+            error: None,
+            error_description: None,
+        };
+        Ok(state.oidc_signin(&input).await?)
+    }
+
+    #[tokio::test]
+    async fn oidc_signin_should_link_existing_user() -> anyhow::Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let state = state.with_oidc(idp.config())?;
+
+        idp.sign_in_as("TChen1@acme.org");
+        let user = signin(&state, &idp).await?;
+        assert_eq!(user.id, 1);
+        assert!(state.is_email_verified(user.id).await?);
+
+        // the second login finds the user by the identity
+        let user = signin(&state, &idp).await?;
+        assert_eq!(user.id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_signin_should_create_user_by_domain() -> anyhow::Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let state = state.with_oidc(idp.config())?;
+
+        idp.sign_in_as("alice@example.com");
+        let user = signin(&state, &idp).await?;
+        assert_eq!(user.fullname, "Mock User");
+        let ws = state
+            .find_workspace_by_name("example")
+            .await?
+            .expect("workspace should be created");
+        assert_eq!(user.ws_id, ws.id);
+        assert_eq!(ws.owner_id, user.id);
+
+        // the account has no usable password
+        let input = SigninUser::new("alice@example.com", "");
+        assert!(state.verify_user(&input).await?.is_none());
+
+        idp.sign_in_as("bob@unknown.org");
+        let ret = signin(&state, &idp).await;
+        assert!(ret.is_err());
+        assert!(state.find_user_by_email("bob@unknown.org").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_state_should_be_single_use() -> anyhow::Result<()> {
+        let idp = MockIdp::start().await?;
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let state = state.with_oidc(idp.config())?;
+
+        let (url, _) = state.oidc_login_url().await?;
+        let (code, oidc_state) = idp.authorize(&url).await?;
+        let input = OidcCallback {
+            code: Some(code),
+            state: oidc_state,
+            error: None,
+            error_description: None,
+        };
+        state.oidc_signin(&input).await?;
+        let ret = state.oidc_signin(&input).await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::policy::Action;
+use crate::{AppError, AppState, ChatFile};
+use chat_core::{User, UserProfile};
+use chrono::{DateTime, Local};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// fields which are not set stay as they are, an empty string clears them
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateProfile {
+    pub display_name: Option<String>,
+    pub title: Option<String>,
+    /// iana name, e.g. Europe/Berlin
+    pub timezone: Option<String>,
+    /// url of a file uploaded to the current workspace
+    pub avatar: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    /// only used together with a new status, the status never expires when it is not set
+    pub status_expires_at: Option<DateTime<Local>>,
+}
+
+// an expired status reads as no status
+const PROFILE_COLUMNS: &str = r#"
+    u.id, u.fullname, u.email, u.display_name, u.title, u.timezone, u.avatar,
+    CASE WHEN u.status_expires_at IS NULL OR u.status_expires_at > NOW() THEN u.status_text END AS status_text,
+    CASE WHEN u.status_expires_at IS NULL OR u.status_expires_at > NOW() THEN u.status_emoji END AS status_emoji,
+    CASE WHEN u.status_expires_at > NOW() THEN u.status_expires_at END AS status_expires_at
+"#;
+
+impl AppState {
+    /// profile of a member of the workspace of the user
+    pub async fn find_user_profile(&self, user: &User, id: i64) -> Result<UserProfile, AppError> {
+        if id != user.id {
+            self.authorize(user, Action::ReadDirectory).await?;
+        }
+        let profile: Option<UserProfile> = sqlx::query_as(&format!(
+            r#"
+            SELECT {PROFILE_COLUMNS}
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE u.id = $1 AND m.ws_id = $2 AND m.deactivated_at IS NULL
+            "#
+        ))
+        .bind(id)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        profile.ok_or_else(|| AppError::NotFound(format!("user id {id}")))
+    }
+
+    pub async fn update_profile(
+        &self,
+        user: &User,
+        input: &UpdateProfile,
+    ) -> Result<UserProfile, AppError> {
+        check_length("display name", &input.display_name, 64)?;
+        check_length("title", &input.title, 64)?;
+        check_length("status text", &input.status_text, 100)?;
+        check_length("status emoji", &input.status_emoji, 32)?;
+        if let Some(timezone) = input.timezone.as_deref().filter(|v| !v.is_empty()) {
+            validate_timezone(timezone)?;
+        }
+        if let Some(avatar) = input.avatar.as_deref().filter(|v| !v.is_empty()) {
+            let file = ChatFile::from_str(avatar)?;
+            if file.ws_id != user.ws_id as u64 || !file.path(&self.config.server.base_dir).exists()
+            {
+                return Err(AppError::UpdateProfileError(format!(
+                    "avatar {avatar} is not a file of the workspace"
+                )));
+            }
+        }
+        let new_status = input.status_text.is_some() || input.status_emoji.is_some();
+        sqlx::query(
+            r#"
+            UPDATE users SET
+                display_name = CASE WHEN $2::TEXT IS NULL THEN display_name ELSE NULLIF($2, '') END,
+                title = CASE WHEN $3::TEXT IS NULL THEN title ELSE NULLIF($3, '') END,
+                timezone = CASE WHEN $4::TEXT IS NULL THEN timezone ELSE NULLIF($4, '') END,
+                avatar = CASE WHEN $5::TEXT IS NULL THEN avatar ELSE NULLIF($5, '') END,
+                status_text = CASE WHEN $6::TEXT IS NULL THEN status_text ELSE NULLIF($6, '') END,
+                status_emoji = CASE WHEN $7::TEXT IS NULL THEN status_emoji ELSE NULLIF($7, '') END,
+                status_expires_at = CASE WHEN $8 THEN $9 ELSE status_expires_at END
+            WHERE id = $1
+            "#,
+        )
+        .bind(user.id)
+        .bind(&input.display_name)
+        .bind(&input.title)
+        .bind(&input.timezone)
+        .bind(&input.avatar)
+        .bind(&input.status_text)
+        .bind(&input.status_emoji)
+        .bind(new_status)
+        .bind(input.status_expires_at)
+        .execute(&self.pool)
+        .await?;
+        self.find_user_profile(user, user.id).await
+    }
+}
+
+pub(crate) fn validate_timezone(timezone: &str) -> Result<(), AppError> {
+    Tz::from_str(timezone)
+        .map(|_| ())
+        .map_err(|_| AppError::UpdateProfileError(format!("unknown timezone {timezone}")))
+}
+
+fn check_length(field: &str, value: &Option<String>, max: usize) -> Result<(), AppError> {
+    match value {
+        Some(v) if v.chars().count() > max => Err(AppError::UpdateProfileError(format!(
+            "{field} can have at most {max} characters"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn update_profile_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateProfile {
+            display_name: Some("tyr".to_string()),
+            title: Some("engineer".to_string()),
+            timezone: Some("Europe/Berlin".to_string()),
+            status_text: Some("on vacation".to_string()),
+            status_emoji: Some(":palm_tree:".to_string()),
+            ..Default::default()
+        };
+        let profile = state.update_profile(&user, &input).await?;
+        assert_eq!(profile.display_name.as_deref(), Some("tyr"));
+        assert_eq!(profile.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(profile.status_text.as_deref(), Some("on vacation"));
+
+        // other members see the profile, an empty string clears a field
+        let input = UpdateProfile {
+            title: Some("".to_string()),
+            ..Default::default()
+        };
+        state.update_profile(&user, &input).await?;
+        let other = state.find_user_by_id(2).await?.expect("user should exist");
+        let profile = state.find_user_profile(&other, user.id).await?;
+        assert_eq!(profile.title, None);
+        assert_eq!(profile.display_name.as_deref(), Some("tyr"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_status_should_be_hidden() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateProfile {
+            status_text: Some("in a meeting".to_string()),
+            status_expires_at: Some(Local::now() - Duration::minutes(1)),
+            ..Default::default()
+        };
+        let profile = state.update_profile(&user, &input).await?;
+        assert_eq!(profile.status_text, None);
+        assert_eq!(profile.status_expires_at, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_profile_should_fail() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateProfile {
+            timezone: Some("Mars/Olympus".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_profile(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::UpdateProfileError(_))));
+        let input = UpdateProfile {
+            avatar: Some("/files/1/aaa/bbb/ccc.png".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_profile(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::UpdateProfileError(_))));
+        Ok(())
+    }
+}
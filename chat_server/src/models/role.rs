@@ -0,0 +1,264 @@
+use super::chat::delete_chat_member;
+use crate::policy::{can_assign_role, can_manage, Action};
+use crate::{AppError, AppState};
+use chat_core::{User, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateRole {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct WorkspaceMember {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub deactivated_at: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct DeactivateUser {
+    /// also take the user out of the chats of the workspace
+    #[serde(default)]
+    pub remove_from_chats: bool,
+}
+
+impl AppState {
+    /// role of the user in the workspace of the token, deactivated members have none
+    pub async fn workspace_role(&self, user: &User) -> Result<WorkspaceRole, AppError> {
+        let role: Option<WorkspaceRole> = sqlx::query_scalar(
+            r#"
+            SELECT role FROM workspace_members
+            WHERE user_id = $1 AND ws_id = $2 AND deactivated_at IS NULL
+            "#,
+        )
+        .bind(user.id)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        role.ok_or_else(|| {
+            AppError::PermissionDenied("user is not a member of the workspace".to_string())
+        })
+    }
+
+    /// check the policy for the user, returns their role when the action is allowed
+    pub async fn authorize(&self, user: &User, action: Action) -> Result<WorkspaceRole, AppError> {
+        let role = self.workspace_role(user).await?;
+        action.check(role)?;
+        Ok(role)
+    }
+
+    pub async fn update_member_role(
+        &self,
+        user: &User,
+        member_id: i64,
+        role: WorkspaceRole,
+    ) -> Result<WorkspaceMember, AppError> {
+        let own_role = self.authorize(user, Action::ManageRoles).await?;
+        let current: Option<WorkspaceRole> = sqlx::query_scalar(
+            "SELECT role FROM workspace_members WHERE user_id = $1 AND ws_id = $2",
+        )
+        .bind(member_id)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(current) = current else {
+            return Err(AppError::NotFound(format!("user id {member_id}")));
+        };
+        if member_id == user.id || !can_assign_role(own_role, current, role) {
+            return Err(AppError::PermissionDenied(format!(
+                "a workspace {own_role} can not make a {current} {role}"
+            )));
+        }
+        let member = sqlx::query_as(
+            r#"
+            UPDATE workspace_members m SET role = $3
+            FROM users u
+            WHERE u.id = m.user_id AND m.user_id = $1 AND m.ws_id = $2
+            RETURNING u.id, m.ws_id, u.fullname, u.email, m.role, m.deactivated_at
+            "#,
+        )
+        .bind(member_id)
+        .bind(user.ws_id)
+        .bind(role)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    /// the member loses access to the workspace right away, their tokens for it are rejected
+    pub async fn deactivate_member(
+        &self,
+        user: &User,
+        member_id: i64,
+        input: &DeactivateUser,
+    ) -> Result<WorkspaceMember, AppError> {
+        self.ensure_can_manage(user, member_id).await?;
+        let mut tx = self.pool.begin().await?;
+        let member = sqlx::query_as(
+            r#"
+            UPDATE workspace_members m SET deactivated_at = COALESCE(m.deactivated_at, NOW())
+            FROM users u
+            WHERE u.id = m.user_id AND m.user_id = $1 AND m.ws_id = $2
+            RETURNING u.id, m.ws_id, u.fullname, u.email, m.role, m.deactivated_at
+            "#,
+        )
+        .bind(member_id)
+        .bind(user.ws_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if input.remove_from_chats {
+            // single chats keep both members, like leaving them one by one
+            let chat_ids: Vec<i64> = sqlx::query_scalar(
+                r#"
+                SELECT c.id FROM chats c
+                JOIN chat_members m ON m.chat_id = c.id
+                WHERE m.user_id = $1 AND c.ws_id = $2 AND c.type <> 'single'
+                "#,
+            )
+            .bind(member_id)
+            .bind(user.ws_id)
+            .fetch_all(&mut *tx)
+            .await?;
+            for chat_id in chat_ids {
+                delete_chat_member(&mut tx, chat_id, member_id).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(member)
+    }
+
+    pub async fn reactivate_member(
+        &self,
+        user: &User,
+        member_id: i64,
+    ) -> Result<WorkspaceMember, AppError> {
+        self.ensure_can_manage(user, member_id).await?;
+        let member = sqlx::query_as(
+            r#"
+            UPDATE workspace_members m SET deactivated_at = NULL
+            FROM users u
+            WHERE u.id = m.user_id AND m.user_id = $1 AND m.ws_id = $2
+            RETURNING u.id, m.ws_id, u.fullname, u.email, m.role, m.deactivated_at
+            "#,
+        )
+        .bind(member_id)
+        .bind(user.ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(member)
+    }
+
+    async fn ensure_can_manage(&self, user: &User, member_id: i64) -> Result<(), AppError> {
+        let own_role = self.authorize(user, Action::ManageMembers).await?;
+        let role: Option<WorkspaceRole> = sqlx::query_scalar(
+            "SELECT role FROM workspace_members WHERE user_id = $1 AND ws_id = $2",
+        )
+        .bind(member_id)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(role) = role else {
+            return Err(AppError::NotFound(format!("user id {member_id}")));
+        };
+        if member_id == user.id || !can_manage(own_role, role) {
+            return Err(AppError::PermissionDenied(format!(
+                "a workspace {own_role} can not manage a {role}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chat_core::middlewares::TokenVerify;
+
+    #[tokio::test]
+    async fn owner_should_manage_roles() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        assert_eq!(state.workspace_role(&owner).await?, WorkspaceRole::Owner);
+
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.authorize(&member, Action::ManageRoles).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let admin = state
+            .update_member_role(&owner, member.id, WorkspaceRole::Admin)
+            .await?;
+        assert_eq!(admin.role, WorkspaceRole::Admin);
+        let guest = state
+            .update_member_role(&member, 3, WorkspaceRole::Guest)
+            .await?;
+        assert_eq!(guest.role, WorkspaceRole::Guest);
+
+        // admins can not touch the owner or each other
+        let ret = state
+            .update_member_role(&member, owner.id, WorkspaceRole::Member)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ret = state
+            .update_member_role(&member, 4, WorkspaceRole::Admin)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deactivated_member_should_lose_access() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let token = state.ek.sign(member.clone())?;
+
+        let input = DeactivateUser {
+            remove_from_chats: true,
+        };
+        let ret = state.deactivate_member(&member, 3, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        // the member is the only owner of the general chat
+        sqlx::query(
+            r#"
+            UPDATE chat_members
+            SET role = CASE WHEN user_id = $1 THEN 'owner' ELSE 'member' END::chat_role
+            WHERE chat_id = 1
+            "#,
+        )
+        .bind(member.id)
+        .execute(&state.pool)
+        .await?;
+        let deactivated = state.deactivate_member(&owner, member.id, &input).await?;
+        assert!(deactivated.deactivated_at.is_some());
+
+        assert!(state.verify(&token).await.is_err());
+        let ret = state.issue_tokens(member.clone()).await;
+        assert!(matches!(ret, Err(AppError::UserDeactivated)));
+        let users = state.fetch_chat_user_all(owner.ws_id as _).await?;
+        assert!(users.iter().all(|u| u.id != member.id));
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert!(!chat.members.contains(&member.id));
+        let owners: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM chat_members WHERE chat_id = 1 AND role = 'owner'",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(owners, 1);
+        // the single chat keeps both members
+        let chat = state.get_chat_by_id(3).await?.expect("chat should exist");
+        assert!(chat.members.contains(&member.id));
+
+        let reactivated = state.reactivate_member(&owner, member.id).await?;
+        assert!(reactivated.deactivated_at.is_none());
+        state.issue_tokens(member).await?;
+        assert!(state.verify(&token).await.is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,1058 @@
+use super::chat::{fetch_chat, set_chat_members, CHAT_COLUMNS};
+use super::token::{generate_token, hash_token};
+use super::workspace::add_workspace_member;
+use crate::policy::Action;
+use crate::{AppError, AppState};
+use chat_core::{Chat, ChatType, User, WorkSpace, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor};
+use utoipa::ToSchema;
+
+/// scim tokens are told apart from jwt and api keys by this prefix
+pub const SCIM_TOKEN_PREFIX: &str = "scim_";
+const SCIM_TOKEN_DISPLAY_LEN: usize = 12;
+
+const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub(crate) const SCIM_ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct ScimToken {
+    pub id: i64,
+    pub ws_id: i64,
+    pub prefix: String,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub revoked_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+/// returned once on creation, the token itself can't be read again
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ScimTokenCreated {
+    #[serde(flatten)]
+    pub scim_token: ScimToken,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimName {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: String,
+    pub created: DateTime<Local>,
+    pub location: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<String>,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    pub display_name: String,
+    pub name: ScimName,
+    pub emails: Vec<ScimEmail>,
+    pub active: bool,
+    pub meta: ScimMeta,
+}
+/// a user as sent by the identity provider, the user name is the email address
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserInput {
+    pub user_name: String,
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub name: Option<ScimName>,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimMemberRef {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    pub schemas: Vec<String>,
+    pub id: String,
+    pub display_name: String,
+    pub members: Vec<ScimMemberRef>,
+    pub meta: ScimMeta,
+}
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupInput {
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<ScimMemberRef>,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScimPatch {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<ScimPatchOp>,
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScimPatchOp {
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListQuery {
+    pub filter: Option<String>,
+    /// 1-based, as defined by scim
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimListResponse<T> {
+    pub schemas: Vec<String>,
+    pub total_results: i64,
+    pub start_index: i64,
+    pub items_per_page: i64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+#[derive(Debug, FromRow)]
+struct ScimUserRow {
+    id: i64,
+    fullname: String,
+    email: String,
+    external_id: Option<String>,
+    active: bool,
+    created_at: DateTime<Local>,
+}
+
+const SCIM_USER_COLUMNS: &str = r#"
+    u.id, u.fullname, u.email, m.external_id, m.deactivated_at IS NULL AS active, m.created_at
+    FROM workspace_members m
+    JOIN users u ON u.id = m.user_id
+    WHERE m.ws_id = $1 AND NOT u.is_bot
+"#;
+
+impl AppState {
+    pub async fn create_scim_token(&self, owner: &User) -> Result<ScimTokenCreated, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let token = format!("{}{}", SCIM_TOKEN_PREFIX, generate_token());
+        let scim_token = sqlx::query_as(
+            r#"
+            INSERT INTO scim_tokens (ws_id, prefix, token_hash, created_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, ws_id, prefix, last_used_at, revoked_at, created_at
+            "#,
+        )
+        .bind(owner.ws_id)
+        .bind(&token[..SCIM_TOKEN_DISPLAY_LEN])
+        .bind(hash_token(&token))
+        .bind(owner.id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ScimTokenCreated { scim_token, token })
+    }
+
+    pub async fn list_scim_tokens(&self, owner: &User) -> Result<Vec<ScimToken>, AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, prefix, last_used_at, revoked_at, created_at
+            FROM scim_tokens
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(owner.ws_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tokens)
+    }
+
+    pub async fn revoke_scim_token(&self, owner: &User, id: i64) -> Result<(), AppError> {
+        self.authorize(owner, Action::ManageWorkspace).await?;
+        let ret = sqlx::query(
+            r#"
+            UPDATE scim_tokens SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND ws_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(owner.ws_id)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("scim token id {id}")));
+        }
+        Ok(())
+    }
+
+    /// resolve a scim token to the workspace it provisions
+    pub async fn verify_scim_token(&self, token: &str) -> Result<WorkSpace, AppError> {
+        let ws_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE scim_tokens SET last_used_at = NOW()
+            WHERE token_hash = $1 AND revoked_at IS NULL
+            RETURNING ws_id
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(ws_id) = ws_id else {
+            return Err(AppError::Unauthorized("invalid scim token".to_string()));
+        };
+        match self.find_workspace_by_id(ws_id as _).await? {
+            Some(ws) if ws.deleted_at.is_none() => Ok(ws),
+            _ => Err(AppError::WorkspaceDeleted(ws_id)),
+        }
+    }
+
+    pub async fn list_scim_users(
+        &self,
+        ws_id: i64,
+        query: &ScimListQuery,
+    ) -> Result<ScimListResponse<ScimUser>, AppError> {
+        let (column, value) = match parse_filter(query.filter.as_deref())? {
+            None => ("u.email", None),
+            Some(("userName", value)) => ("u.email", Some(value)),
+            Some(("externalId", value)) => ("m.external_id", Some(value)),
+            Some((attr, _)) => {
+                return Err(AppError::ScimError(format!(
+                    "filtering users by {attr} is not supported"
+                )))
+            }
+        };
+        let condition = format!("AND ($2::TEXT IS NULL OR {column} = $2)");
+        let (offset, limit) = page(query);
+        let total: i64 = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM (SELECT {SCIM_USER_COLUMNS} {condition}) t"
+        ))
+        .bind(ws_id)
+        .bind(value)
+        .fetch_one(&self.pool)
+        .await?;
+        let rows: Vec<ScimUserRow> = sqlx::query_as(&format!(
+            "SELECT {SCIM_USER_COLUMNS} {condition} ORDER BY u.id OFFSET $3 LIMIT $4"
+        ))
+        .bind(ws_id)
+        .bind(value)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(list_response(
+            offset,
+            total,
+            rows.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    pub async fn get_scim_user(&self, ws_id: i64, id: i64) -> Result<ScimUser, AppError> {
+        let row: Option<ScimUserRow> =
+            sqlx::query_as(&format!("SELECT {SCIM_USER_COLUMNS} AND u.id = $2"))
+                .bind(ws_id)
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(Into::into)
+            .ok_or_else(|| AppError::NotFound(format!("user id {id}")))
+    }
+
+    /// provision a new account, the address of an existing one is a conflict as its
+    /// profile is not the workspace's to manage
+    pub async fn create_scim_user(
+        &self,
+        ws_id: i64,
+        input: &ScimUserInput,
+    ) -> Result<ScimUser, AppError> {
+        let email = input.email()?;
+        if self.find_user_by_email(&email).await?.is_some() {
+            return Err(AppError::EmailAlreadyExists(email));
+        }
+        let mut tx = self.pool.begin().await?;
+        // an empty hash never matches, the user signs in through the identity provider
+        let user_id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (ws_id, email, fullname, password_hash, email_verified_at)
+            VALUES ($1, $2, $3, '', NOW())
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id)
+        .bind(&email)
+        .bind(input.fullname())
+        .fetch_one(&mut *tx)
+        .await?;
+        add_workspace_member(&mut *tx, ws_id, user_id, WorkspaceRole::Member).await?;
+        sqlx::query(
+            r#"
+            UPDATE workspace_members
+            SET external_id = $3, deactivated_at = CASE WHEN $4 THEN NULL ELSE NOW() END
+            WHERE ws_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .bind(&input.external_id)
+        .bind(input.active.unwrap_or(true))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.get_scim_user(ws_id, user_id).await
+    }
+
+    pub async fn patch_scim_user(
+        &self,
+        ws_id: i64,
+        id: i64,
+        patch: &ScimPatch,
+    ) -> Result<ScimUser, AppError> {
+        self.get_scim_user(ws_id, id).await?;
+        // the operations of a patch apply together or not at all
+        let mut tx = self.pool.begin().await?;
+        for op in &patch.operations {
+            if !op.is_replace() {
+                return Err(AppError::ScimError(format!(
+                    "{} is not supported for users",
+                    op.op
+                )));
+            }
+            for (path, value) in op.values()? {
+                match path.as_str() {
+                    "active" => set_scim_user_active(&mut *tx, ws_id, id, as_bool(&value)?).await?,
+                    "displayName" | "name.formatted" => {
+                        // the name is shared by every workspace of the account
+                        let elsewhere: bool = sqlx::query_scalar(
+                            r#"
+                            SELECT EXISTS(SELECT 1 FROM workspace_members
+                                          WHERE user_id = $1 AND ws_id <> $2)
+                            "#,
+                        )
+                        .bind(id)
+                        .bind(ws_id)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                        if elsewhere {
+                            return Err(AppError::ScimError(format!(
+                                "user id {id} belongs to other workspaces, their name is not managed here"
+                            )));
+                        }
+                        sqlx::query("UPDATE users SET fullname = $2 WHERE id = $1")
+                            .bind(id)
+                            .bind(as_str(&value)?)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                    "externalId" => {
+                        sqlx::query(
+                            "UPDATE workspace_members SET external_id = $3 WHERE ws_id = $1 AND user_id = $2",
+                        )
+                        .bind(ws_id)
+                        .bind(id)
+                        .bind(as_str(&value)?)
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    path => {
+                        return Err(AppError::ScimError(format!(
+                            "attribute {path} can not be changed"
+                        )))
+                    }
+                }
+            }
+        }
+        tx.commit().await?;
+        self.get_scim_user(ws_id, id).await
+    }
+
+    /// deprovision a user, the account and their messages stay but they leave the workspace
+    pub async fn delete_scim_user(&self, ws_id: i64, id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ret = sqlx::query("DELETE FROM workspace_members WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("user id {id}")));
+        }
+        sqlx::query(
+            r#"
+            DELETE FROM chat_members
+            WHERE user_id = $2 AND chat_id IN (SELECT id FROM chats WHERE ws_id = $1)
+            "#,
+        )
+        .bind(ws_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE refresh_tokens SET ws_id = NULL WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE users u
+            SET ws_id = COALESCE((
+                SELECT MIN(ws_id) FROM workspace_members
+                WHERE user_id = u.id AND deactivated_at IS NULL
+            ), 0)
+            WHERE id = $2 AND ws_id = $1
+            "#,
+        )
+        .bind(ws_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn list_scim_groups(
+        &self,
+        ws_id: i64,
+        query: &ScimListQuery,
+    ) -> Result<ScimListResponse<ScimGroup>, AppError> {
+        let name = match parse_filter(query.filter.as_deref())? {
+            None => None,
+            Some(("displayName", value)) => Some(value),
+            Some((attr, _)) => {
+                return Err(AppError::ScimError(format!(
+                    "filtering groups by {attr} is not supported"
+                )))
+            }
+        };
+        let (offset, limit) = page(query);
+        let total: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM chats
+            WHERE ws_id = $1 AND scim_group AND ($2::TEXT IS NULL OR name = $2)
+            "#,
+        )
+        .bind(ws_id)
+        .bind(name)
+        .fetch_one(&self.pool)
+        .await?;
+        let chats: Vec<Chat> = sqlx::query_as(&format!(
+            r#"
+            SELECT {CHAT_COLUMNS} FROM chats c
+            WHERE c.ws_id = $1 AND c.scim_group AND ($2::TEXT IS NULL OR c.name = $2)
+            ORDER BY c.id
+            OFFSET $3 LIMIT $4
+            "#
+        ))
+        .bind(ws_id)
+        .bind(name)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(list_response(
+            offset,
+            total,
+            chats.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    pub async fn get_scim_group(&self, ws_id: i64, id: i64) -> Result<ScimGroup, AppError> {
+        let chat: Option<Chat> = sqlx::query_as(&format!(
+            r#"
+            SELECT {CHAT_COLUMNS} FROM chats c
+            WHERE c.id = $1 AND c.ws_id = $2 AND c.scim_group
+            "#
+        ))
+        .bind(id)
+        .bind(ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        chat.map(Into::into)
+            .ok_or_else(|| AppError::NotFound(format!("group id {id}")))
+    }
+
+    /// a group is provisioned as a private channel of its members
+    pub async fn create_scim_group(
+        &self,
+        ws_id: i64,
+        input: &ScimGroupInput,
+    ) -> Result<ScimGroup, AppError> {
+        let name = group_name(&input.display_name)?;
+        let mut tx = self.pool.begin().await?;
+        let members = scim_group_members(&mut *tx, ws_id, &input.members).await?;
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO chats (ws_id, name, type, scim_group)
+            VALUES ($1, $2, $3, TRUE)
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id)
+        .bind(name)
+        .bind(ChatType::PrivateChannel)
+        .fetch_one(&mut *tx)
+        .await?;
+        set_chat_members(&mut tx, id, &members).await?;
+        let chat = fetch_chat(&mut *tx, id).await?;
+        tx.commit().await?;
+        Ok(chat.into())
+    }
+
+    pub async fn patch_scim_group(
+        &self,
+        ws_id: i64,
+        id: i64,
+        patch: &ScimPatch,
+    ) -> Result<ScimGroup, AppError> {
+        // the group stays locked until every operation is applied
+        let mut tx = self.pool.begin().await?;
+        let group: Option<Chat> = sqlx::query_as(&format!(
+            r#"
+            SELECT {CHAT_COLUMNS} FROM chats c
+            WHERE c.id = $1 AND c.ws_id = $2 AND c.scim_group
+            FOR UPDATE
+            "#
+        ))
+        .bind(id)
+        .bind(ws_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let group: ScimGroup = group
+            .map(Into::into)
+            .ok_or_else(|| AppError::NotFound(format!("group id {id}")))?;
+        let mut name = group.display_name.clone();
+        let mut members: Vec<i64> = group
+            .members
+            .iter()
+            .filter_map(|v| v.value.parse().ok())
+            .collect();
+        for op in &patch.operations {
+            match (op.op.to_lowercase().as_str(), op.path.as_deref()) {
+                ("add", Some("members")) => {
+                    for member in scim_group_members(&mut *tx, ws_id, &op.member_refs()?).await? {
+                        if !members.contains(&member) {
+                            members.push(member);
+                        }
+                    }
+                }
+                ("remove", Some("members")) if op.value.is_none() => members.clear(),
+                ("remove", Some("members")) => {
+                    let removed = parse_member_ids(&op.member_refs()?)?;
+                    members.retain(|v| !removed.contains(v));
+                }
+                ("remove", Some(path)) if path.starts_with("members[") => {
+                    let Some(("value", value)) = parse_filter(
+                        path.strip_prefix("members[")
+                            .and_then(|v| v.strip_suffix(']')),
+                    )?
+                    else {
+                        return Err(AppError::ScimError(format!("unsupported path {path}")));
+                    };
+                    let removed: i64 = value
+                        .parse()
+                        .map_err(|_| AppError::ScimError(format!("invalid member id {value}")))?;
+                    members.retain(|v| *v != removed);
+                }
+                ("replace", Some("members")) => {
+                    members = scim_group_members(&mut *tx, ws_id, &op.member_refs()?).await?;
+                }
+                ("replace", _) => {
+                    for (path, value) in op.values()? {
+                        match path.as_str() {
+                            "displayName" => name = group_name(as_str(&value)?)?.to_string(),
+                            "members" => {
+                                let refs: Vec<ScimMemberRef> = serde_json::from_value(value)
+                                    .map_err(|e| AppError::ScimError(e.to_string()))?;
+                                members = scim_group_members(&mut *tx, ws_id, &refs).await?;
+                            }
+                            path => {
+                                return Err(AppError::ScimError(format!(
+                                    "attribute {path} can not be changed"
+                                )))
+                            }
+                        }
+                    }
+                }
+                (op, path) => {
+                    return Err(AppError::ScimError(format!(
+                        "{op} of {} is not supported for groups",
+                        path.unwrap_or("the group")
+                    )))
+                }
+            }
+        }
+        if name != group.display_name {
+            sqlx::query("UPDATE chats SET name = $3 WHERE id = $1 AND ws_id = $2")
+                .bind(id)
+                .bind(ws_id)
+                .bind(&name)
+                .execute(&mut *tx)
+                .await?;
+        }
+        set_chat_members(&mut tx, id, &members).await?;
+        let chat = fetch_chat(&mut *tx, id).await?;
+        tx.commit().await?;
+        Ok(chat.into())
+    }
+
+    pub async fn delete_scim_group(&self, ws_id: i64, id: i64) -> Result<(), AppError> {
+        self.get_scim_group(ws_id, id).await?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM messages WHERE chat_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+impl ScimUserInput {
+    fn email(&self) -> Result<String, AppError> {
+        let email = self
+            .emails
+            .iter()
+            .find(|v| v.primary)
+            .or(self.emails.first())
+            .map(|v| v.value.as_str())
+            .unwrap_or(&self.user_name);
+        if !email.contains('@') {
+            return Err(AppError::ScimError(format!(
+                "{email} is not an email address"
+            )));
+        }
+        Ok(email.to_string())
+    }
+
+    fn fullname(&self) -> String {
+        let name = self.name.clone().unwrap_or_default();
+        let given = [name.given_name, name.family_name]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.display_name
+            .clone()
+            .or(name.formatted)
+            .or(Some(given).filter(|v| !v.is_empty()))
+            .unwrap_or_else(|| self.user_name.clone())
+    }
+}
+
+impl ScimPatchOp {
+    fn is_replace(&self) -> bool {
+        matches!(self.op.to_lowercase().as_str(), "replace" | "add")
+    }
+
+    /// the changed attributes, a patch without path carries them as an object
+    fn values(&self) -> Result<Vec<(String, Value)>, AppError> {
+        match (&self.path, &self.value) {
+            (Some(path), Some(value)) => Ok(vec![(path.clone(), value.clone())]),
+            (None, Some(Value::Object(values))) => {
+                Ok(values.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            }
+            _ => Err(AppError::ScimError(format!("{} needs a value", self.op))),
+        }
+    }
+
+    fn member_refs(&self) -> Result<Vec<ScimMemberRef>, AppError> {
+        let value = self.value.clone().unwrap_or(Value::Array(vec![]));
+        serde_json::from_value(value).map_err(|e| AppError::ScimError(e.to_string()))
+    }
+}
+
+impl From<ScimUserRow> for ScimUser {
+    fn from(row: ScimUserRow) -> Self {
+        Self {
+            schemas: vec![USER_SCHEMA.to_string()],
+            id: row.id.to_string(),
+            external_id: row.external_id,
+            user_name: row.email.clone(),
+            display_name: row.fullname.clone(),
+            name: ScimName {
+                formatted: Some(row.fullname),
+                ..Default::default()
+            },
+            emails: vec![ScimEmail {
+                value: row.email,
+                primary: true,
+            }],
+            active: row.active,
+            meta: ScimMeta {
+                resource_type: "User".to_string(),
+                created: row.created_at,
+                location: format!("/scim/v2/Users/{}", row.id),
+            },
+        }
+    }
+}
+
+impl From<Chat> for ScimGroup {
+    fn from(chat: Chat) -> Self {
+        Self {
+            schemas: vec![GROUP_SCHEMA.to_string()],
+            id: chat.id.to_string(),
+            display_name: chat.name.unwrap_or_default(),
+            members: chat
+                .members
+                .iter()
+                .map(|v| ScimMemberRef {
+                    value: v.to_string(),
+                    display: None,
+                })
+                .collect(),
+            meta: ScimMeta {
+                resource_type: "Group".to_string(),
+                created: chat.created_at,
+                location: format!("/scim/v2/Groups/{}", chat.id),
+            },
+        }
+    }
+}
+
+async fn set_scim_user_active(
+    executor: impl PgExecutor<'_>,
+    ws_id: i64,
+    user_id: i64,
+    active: bool,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE workspace_members
+        SET deactivated_at = CASE WHEN $3 THEN NULL ELSE COALESCE(deactivated_at, NOW()) END
+        WHERE ws_id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(ws_id)
+    .bind(user_id)
+    .bind(active)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// group members have to be active members of the workspace
+async fn scim_group_members(
+    executor: impl PgExecutor<'_>,
+    ws_id: i64,
+    refs: &[ScimMemberRef],
+) -> Result<Vec<i64>, AppError> {
+    let mut ids = parse_member_ids(refs)?;
+    ids.sort();
+    ids.dedup();
+    let found: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM workspace_members
+        WHERE ws_id = $1 AND deactivated_at IS NULL AND user_id = ANY($2)
+        "#,
+    )
+    .bind(ws_id)
+    .bind(&ids)
+    .fetch_one(executor)
+    .await?;
+    if found != ids.len() as i64 {
+        return Err(AppError::ScimError(
+            "some members do not exist in the workspace".to_string(),
+        ));
+    }
+    Ok(ids)
+}
+
+/// only `attribute eq "value"` filters are supported, which is what identity providers send
+fn parse_filter(filter: Option<&str>) -> Result<Option<(&str, &str)>, AppError> {
+    let Some(filter) = filter.map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    let mut parts = filter.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(attr), Some(op), Some(value)) if op.eq_ignore_ascii_case("eq") => {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Ok(Some((attr, value)))
+        }
+        _ => Err(AppError::ScimError(format!("unsupported filter {filter}"))),
+    }
+}
+
+fn page(query: &ScimListQuery) -> (i64, i64) {
+    let offset = query.start_index.unwrap_or(1).max(1) - 1;
+    let limit = query.count.unwrap_or(MAX_PAGE_SIZE).clamp(0, MAX_PAGE_SIZE);
+    (offset, limit)
+}
+
+fn list_response<T>(offset: i64, total: i64, resources: Vec<T>) -> ScimListResponse<T> {
+    ScimListResponse {
+        schemas: vec![LIST_SCHEMA.to_string()],
+        total_results: total,
+        start_index: offset + 1,
+        items_per_page: resources.len() as i64,
+        resources,
+    }
+}
+
+fn group_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(AppError::ScimError(
+            "group name must be 1 to 64 characters".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+fn parse_member_ids(refs: &[ScimMemberRef]) -> Result<Vec<i64>, AppError> {
+    refs.iter()
+        .map(|v| {
+            v.value
+                .parse()
+                .map_err(|_| AppError::ScimError(format!("invalid member id {}", v.value)))
+        })
+        .collect()
+}
+
+fn as_str(value: &Value) -> Result<&str, AppError> {
+    value
+        .as_str()
+        .ok_or_else(|| AppError::ScimError(format!("{value} is not a string")))
+}
+
+/// some identity providers send booleans as strings
+fn as_bool(value: &Value) -> Result<bool, AppError> {
+    match value {
+        Value::Bool(v) => Ok(*v),
+        Value::String(v) if v.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(v) if v.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(AppError::ScimError(format!("{value} is not a boolean"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+
+    async fn scim_workspace(state: &AppState) -> anyhow::Result<WorkSpace> {
+        let owner = state.workspace_owner_for_test().await?;
+        let created = state.create_scim_token(&owner).await?;
+        assert!(created.token.starts_with(SCIM_TOKEN_PREFIX));
+        Ok(state.verify_scim_token(&created.token).await?)
+    }
+
+    fn patch(value: Value) -> ScimPatch {
+        serde_json::from_value(value).expect("patch should parse")
+    }
+
+    #[test]
+    fn parse_filter_should_work() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_filter(Some(r#"userName eq "alice@acme.org""#))?,
+            Some(("userName", "alice@acme.org"))
+        );
+        assert_eq!(parse_filter(None)?, None);
+        assert!(parse_filter(Some(r#"userName sw "alice""#)).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scim_token_should_be_revocable() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let ret = state.create_scim_token(&member).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let created = state.create_scim_token(&owner).await?;
+        let ws = state.verify_scim_token(&created.token).await?;
+        assert_eq!(ws.id, owner.ws_id);
+        state
+            .revoke_scim_token(&owner, created.scim_token.id)
+            .await?;
+        assert!(state.verify_scim_token(&created.token).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scim_user_should_be_provisioned() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ws = scim_workspace(&state).await?;
+        let input: ScimUserInput = serde_json::from_value(serde_json::json!({
+            "userName": "alice@acme.org",
+            "externalId": "00u1",
+            "name": { "givenName": "Alice", "familyName": "Smith" },
+            "active": true
+        }))?;
+        let user = state.create_scim_user(ws.id, &input).await?;
+        assert_eq!(user.display_name, "Alice Smith");
+        assert_eq!(user.external_id.as_deref(), Some("00u1"));
+        let ret = state.create_scim_user(ws.id, &input).await;
+        assert!(matches!(ret, Err(AppError::EmailAlreadyExists(_))));
+        // accounts of other workspaces are not taken over
+        let input = CreateUser::new("foo2", "bob@foo.org", "bob", "Hunter42");
+        state.create_user(&input).await?;
+        let input: ScimUserInput =
+            serde_json::from_value(serde_json::json!({ "userName": "bob@foo.org" }))?;
+        let ret = state.create_scim_user(ws.id, &input).await;
+        assert!(matches!(ret, Err(AppError::EmailAlreadyExists(_))));
+
+        let query = ScimListQuery {
+            filter: Some(r#"userName eq "alice@acme.org""#.to_string()),
+            ..Default::default()
+        };
+        let list = state.list_scim_users(ws.id, &query).await?;
+        assert_eq!(list.total_results, 1);
+        assert_eq!(list.resources[0].id, user.id);
+
+        let id: i64 = user.id.parse()?;
+        // a patch which fails partway leaves the user as it was
+        let ret = state
+            .patch_scim_user(
+                ws.id,
+                id,
+                &patch(serde_json::json!({
+                    "Operations": [
+                        { "op": "replace", "path": "active", "value": false },
+                        { "op": "replace", "path": "nickName", "value": "ali" }
+                    ]
+                })),
+            )
+            .await;
+        assert!(matches!(ret, Err(AppError::ScimError(_))));
+        assert!(state.get_scim_user(ws.id, id).await?.active);
+
+        let user = state
+            .patch_scim_user(
+                ws.id,
+                id,
+                &patch(serde_json::json!({
+                    "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+                    "Operations": [{ "op": "Replace", "value": { "active": "False" } }]
+                })),
+            )
+            .await?;
+        assert!(!user.active);
+        assert!(!state.is_workspace_member(ws.id, id).await?);
+
+        state.delete_scim_user(ws.id, id).await?;
+        assert!(state.get_scim_user(ws.id, id).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scim_should_only_rename_users_of_the_workspace() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ws = scim_workspace(&state).await?;
+        let rename = patch(serde_json::json!({
+            "Operations": [{ "op": "replace", "path": "displayName", "value": "Boy" }]
+        }));
+        let user = state.patch_scim_user(ws.id, 2, &rename).await?;
+        assert_eq!(user.display_name, "Boy");
+
+        sqlx::query("INSERT INTO workspace_members (ws_id, user_id) VALUES (2, 3)")
+            .execute(&state.pool)
+            .await?;
+        let ret = state.patch_scim_user(ws.id, 3, &rename).await;
+        assert!(matches!(ret, Err(AppError::ScimError(_))));
+        assert_eq!(
+            state.get_scim_user(ws.id, 3).await?.display_name,
+            "Dawd chen"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scim_group_should_map_to_private_channel() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ws = scim_workspace(&state).await?;
+        let input: ScimGroupInput = serde_json::from_value(serde_json::json!({
+            "displayName": "engineering",
+            "members": [{ "value": "1" }, { "value": "2" }]
+        }))?;
+        let group = state.create_scim_group(ws.id, &input).await?;
+        let id: i64 = group.id.parse()?;
+        let chat = state
+            .get_chat_by_id(id as _)
+            .await?
+            .expect("chat should exist");
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+        assert_eq!(chat.members, vec![1, 2]);
+
+        let group = state
+            .patch_scim_group(
+                ws.id,
+                id,
+                &patch(serde_json::json!({
+                    "Operations": [
+                        { "op": "add", "path": "members", "value": [{ "value": "3" }] },
+                        { "op": "remove", "path": "members[value eq \"1\"]" },
+                        { "op": "replace", "value": { "displayName": "platform" } }
+                    ]
+                })),
+            )
+            .await?;
+        assert_eq!(group.display_name, "platform");
+        let members: Vec<&str> = group.members.iter().map(|v| v.value.as_str()).collect();
+        assert_eq!(members, vec!["2", "3"]);
+
+        let ret = state
+            .patch_scim_group(
+                ws.id,
+                id,
+                &patch(serde_json::json!({
+                    "Operations": [
+                        { "op": "replace", "path": "displayName", "value": "ops" },
+                        { "op": "add", "path": "members", "value": [{ "value": "999" }] }
+                    ]
+                })),
+            )
+            .await;
+        assert!(matches!(ret, Err(AppError::ScimError(_))));
+        assert_eq!(
+            state.get_scim_group(ws.id, id).await?.display_name,
+            "platform"
+        );
+
+        let query = ScimListQuery::default();
+        assert_eq!(
+            state.list_scim_groups(ws.id, &query).await?.total_results,
+            1
+        );
+        state.delete_scim_group(ws.id, id).await?;
+        assert!(state.get_chat_by_id(id as _).await?.is_none());
+        Ok(())
+    }
+}
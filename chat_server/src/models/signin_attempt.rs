@@ -0,0 +1,180 @@
+use super::token::hash_token;
+use crate::{AppError, AppState};
+use serde_json::json;
+use tracing::warn;
+
+pub const SIGNIN_LOCKOUT_ACTION: &str = "signin_lockout";
+
+impl AppState {
+    /// seconds until the email or the ip may try to sign in again, `None` if neither is locked
+    pub async fn signin_lockout(
+        &self,
+        email: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<i64>, AppError> {
+        let keys = attempt_keys(email, ip);
+        let retry_after: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT MAX(CEIL(EXTRACT(EPOCH FROM locked_until - NOW())))::BIGINT
+            FROM signin_attempts
+            WHERE key = ANY($1) AND locked_until > NOW()
+            "#,
+        )
+        .bind(&keys)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(retry_after)
+    }
+
+    /// count a failed signin for the email and the ip, locks them once the limit is reached
+    pub async fn record_signin_failure(
+        &self,
+        email: &str,
+        ip: Option<&str>,
+    ) -> Result<(), AppError> {
+        let throttle = &self.config.auth.signin_throttle;
+        for key in attempt_keys(email, ip) {
+            let failures: i32 = sqlx::query_scalar(
+                r#"
+                INSERT INTO signin_attempts (key, failures, last_failure_at)
+                VALUES ($1, 1, NOW())
+                ON CONFLICT (key) DO UPDATE
+                SET failures = CASE
+                        WHEN signin_attempts.last_failure_at < NOW() - $2 * INTERVAL '1 second'
+                         AND COALESCE(signin_attempts.locked_until, '-infinity') < NOW() - $2 * INTERVAL '1 second'
+                        THEN 1
+                        ELSE signin_attempts.failures + 1
+                    END,
+                    last_failure_at = NOW()
+                RETURNING failures
+                "#,
+            )
+            .bind(&key)
+            .bind(throttle.failure_window as f64)
+            .fetch_one(&self.pool)
+            .await?;
+
+            let max_failures = if key.starts_with("ip:") {
+                throttle.max_ip_failures
+            } else {
+                throttle.max_email_failures
+            };
+            if failures < max_failures {
+                continue;
+            }
+            // double the lockout with every failure past the limit
+            let exponent = (failures - max_failures).min(30) as u32;
+            let lockout = throttle
+                .base_lockout
+                .saturating_mul(1 << exponent)
+                .min(throttle.max_lockout);
+            sqlx::query(
+                r#"
+                UPDATE signin_attempts
+                SET locked_until = NOW() + $2 * INTERVAL '1 second'
+                WHERE key = $1
+                "#,
+            )
+            .bind(&key)
+            .bind(lockout as f64)
+            .execute(&self.pool)
+            .await?;
+
+            warn!(
+                "{} locked for {}s after {} failed signins",
+                key, lockout, failures
+            );
+            let user_id: Option<i64> =
+                sqlx::query_scalar("SELECT id FROM users WHERE LOWER(email) = LOWER($1)")
+                    .bind(email.trim())
+                    .fetch_optional(&self.pool)
+                    .await?;
+            let detail = json!({
+                "key": key,
+                "failures": failures,
+                "lockout": lockout,
+            });
+            self.write_audit_log(user_id, SIGNIN_LOCKOUT_ACTION, ip, detail)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// forget the failures of the email after a successful signin
+    pub async fn clear_signin_failures(&self, email: &str) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM signin_attempts WHERE key = $1")
+            .bind(email_key(email))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// the address is hashed, the key keeps a fixed length whatever a client sends
+fn email_key(email: &str) -> String {
+    format!("email:{}", hash_token(&email.trim().to_lowercase()))
+}
+
+fn attempt_keys(email: &str, ip: Option<&str>) -> Vec<String> {
+    let mut keys = vec![email_key(email)];
+    if let Some(ip) = ip {
+        keys.push(format!("ip:{ip}"));
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn signin_failures_should_lock_email() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let max = state.config.auth.signin_throttle.max_email_failures;
+        for _ in 0..max - 1 {
+            state.record_signin_failure("tchen1@acme.org", None).await?;
+        }
+        assert_eq!(state.signin_lockout("tchen1@acme.org", None).await?, None);
+
+        state.record_signin_failure("TChen1@acme.org", None).await?;
+        let retry_after = state.signin_lockout("tchen1@acme.org", None).await?;
+        let base = state.config.auth.signin_throttle.base_lockout;
+        assert!(retry_after.is_some_and(|v| v > 0 && v <= base));
+
+        // another failure doubles the lockout
+        state.record_signin_failure("tchen1@acme.org", None).await?;
+        let retry_after = state.signin_lockout("tchen1@acme.org", None).await?;
+        assert!(retry_after.is_some_and(|v| v > base));
+
+        let logs = state.list_audit_logs(SIGNIN_LOCKOUT_ACTION).await?;
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].user_id, Some(1));
+        assert_eq!(logs[0].detail["key"], email_key("tchen1@acme.org"));
+
+        state.clear_signin_failures("tchen1@acme.org").await?;
+        assert_eq!(state.signin_lockout("tchen1@acme.org", None).await?, None);
+
+        // any address fits into the key
+        let email = format!("{}@acme.org", "a".repeat(200));
+        state.record_signin_failure(&email, None).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_failures_should_lock_ip() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let max = state.config.auth.signin_throttle.max_ip_failures;
+        let ip = Some("10.0.0.1");
+        for i in 0..max {
+            state
+                .record_signin_failure(&format!("user{i}@acme.org"), ip)
+                .await?;
+        }
+        assert!(state.signin_lockout("tchen1@acme.org", ip).await?.is_some());
+        assert!(state
+            .signin_lockout("tchen1@acme.org", Some("10.0.0.2"))
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{AppError, AppState};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chat_core::utils::{UserClaims, JWT_DURATION};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgExecutor};
+
+/// refresh tokens live for 30 days, every use rotates them
+pub const REFRESH_TOKEN_DURATION: i64 = 60 * 60 * 24 * 30;
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRow {
+    id: i64,
+    user_id: i64,
+    family_id: String,
+    ws_id: Option<i64>,
+    expired: bool,
+    used: bool,
+    revoked: bool,
+}
+
+/// result of a refresh token rotation, the family id doubles as session id
+#[derive(Debug)]
+pub struct RotatedToken {
+    pub user_id: i64,
+    pub family_id: String,
+    /// workspace selected by the session, not set for sessions older than workspace switching
+    pub ws_id: Option<i64>,
+    pub token: String,
+}
+
+impl AppState {
+    /// create a refresh token for the user, returns the raw token which is only known by the client
+    pub async fn create_refresh_token(
+        &self,
+        user_id: i64,
+        ws_id: i64,
+        family_id: &str,
+    ) -> Result<String, AppError> {
+        insert_refresh_token(&self.pool, user_id, Some(ws_id), family_id).await
+    }
+
+    /// exchange a refresh token for a new one of the same family.
+    /// presenting a token that was already exchanged revokes the whole family
+    pub async fn rotate_refresh_token(&self, token: &str) -> Result<RotatedToken, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row: Option<RefreshTokenRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, family_id, ws_id,
+                   expires_at < NOW() AS expired,
+                   used_at IS NOT NULL AS used,
+                   revoked_at IS NOT NULL AS revoked
+            FROM refresh_tokens
+            WHERE token_hash = $1
+            FOR UPDATE
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(row) = row else {
+            return Err(AppError::Unauthorized("invalid refresh token".to_string()));
+        };
+        if row.used {
+            sqlx::query(
+                r#"
+                UPDATE refresh_tokens
+                SET revoked_at = NOW()
+                WHERE family_id = $1 AND revoked_at IS NULL
+                "#,
+            )
+            .bind(&row.family_id)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            return Err(AppError::Unauthorized(
+                "refresh token reuse detected".to_string(),
+            ));
+        }
+        if row.revoked {
+            return Err(AppError::Unauthorized("refresh token revoked".to_string()));
+        }
+        if row.expired {
+            return Err(AppError::Unauthorized("refresh token expired".to_string()));
+        }
+        sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE id = $1")
+            .bind(row.id)
+            .execute(&mut *tx)
+            .await?;
+        let token = insert_refresh_token(&mut *tx, row.user_id, row.ws_id, &row.family_id).await?;
+        tx.commit().await?;
+        Ok(RotatedToken {
+            user_id: row.user_id,
+            family_id: row.family_id,
+            ws_id: row.ws_id,
+            token,
+        })
+    }
+
+    pub async fn token_version(&self, user_id: i64) -> Result<i64, AppError> {
+        let version = sqlx::query_scalar("SELECT token_version FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        version.ok_or_else(|| AppError::NotFound(format!("user id {user_id}")))
+    }
+
+    /// revoke the access token and the session it belongs to. tokens of the session
+    /// signed after the one used here expire later, the row outlives all of them
+    pub async fn revoke_session(&self, claims: &UserClaims) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO revoked_tokens (user_id, jti, sid, expires_at)
+            VALUES ($1, $2, $3, GREATEST(to_timestamp($4), NOW() + make_interval(secs => $5)))
+            "#,
+        )
+        .bind(claims.user.id)
+        .bind(&claims.jti)
+        .bind(&claims.sid)
+        .bind(claims.expires_at as f64)
+        .bind(JWT_DURATION as f64)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(sid) = &claims.sid {
+            sqlx::query(
+                r#"
+                UPDATE refresh_tokens
+                SET revoked_at = NOW()
+                WHERE family_id = $1 AND revoked_at IS NULL
+                "#,
+            )
+            .bind(sid)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// log out every session of the user: bump the token generation and revoke all refresh tokens
+    pub async fn revoke_all_sessions(&self, user_id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// store a new token of the family, returns the raw token
+async fn insert_refresh_token(
+    executor: impl PgExecutor<'_>,
+    user_id: i64,
+    ws_id: Option<i64>,
+    family_id: &str,
+) -> Result<String, AppError> {
+    let token = generate_token();
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (user_id, ws_id, family_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, NOW() + $5 * INTERVAL '1 second')
+        "#,
+    )
+    .bind(user_id)
+    .bind(ws_id)
+    .bind(family_id)
+    .bind(hash_token(&token))
+    .bind(REFRESH_TOKEN_DURATION as f64)
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+/// random 256 bit token, hex encoded
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_and_hash_token_should_work() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+
+    #[tokio::test]
+    async fn rotate_refresh_token_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, 1, "family").await?;
+        let rotated = state.rotate_refresh_token(&token).await?;
+        assert_eq!(rotated.user_id, 1);
+        assert_eq!(rotated.family_id, "family");
+        assert_ne!(token, rotated.token);
+        let rotated = state.rotate_refresh_token(&rotated.token).await?;
+        assert_eq!(rotated.user_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reused_refresh_token_should_revoke_family() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state.create_refresh_token(1, 1, "family").await?;
+        let token2 = state.rotate_refresh_token(&token).await?.token;
+        // replaying the first token revokes the whole family
+        let err = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unauthorized: refresh token reuse detected"
+        );
+        let err = state.rotate_refresh_token(&token2).await.unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized: refresh token revoked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_session_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.create_refresh_token(1, 1, "session").await?;
+        let access = state
+            .ek
+            .sign(UserClaims::new(user, Some("session".to_string()), 0))?;
+        let claims = state.dk.verify(&access)?;
+        assert!(!chat_core::utils::is_token_revoked(&state.pool, &claims).await?);
+
+        // logging out with a token about to expire still revokes later tokens of the session
+        let mut expiring = claims.clone();
+        expiring.expires_at = 0;
+        state.revoke_session(&expiring).await?;
+        sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < NOW()")
+            .execute(&state.pool)
+            .await?;
+        assert!(chat_core::utils::is_token_revoked(&state.pool, &claims).await?);
+        let err = state.rotate_refresh_token(&token).await.unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized: refresh token revoked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_all_sessions_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.create_refresh_token(1, 1, "session").await?;
+        let claims = state.dk.verify(&state.ek.sign(user.clone())?)?;
+        assert!(!chat_core::utils::is_token_revoked(&state.pool, &claims).await?);
+
+        state.revoke_all_sessions(1).await?;
+        assert_eq!(state.token_version(1).await?, 1);
+        assert!(chat_core::utils::is_token_revoked(&state.pool, &claims).await?);
+        assert!(state.rotate_refresh_token(&token).await.is_err());
+        // tokens of the new generation are accepted again
+        let claims = state
+            .dk
+            .verify(&state.ek.sign(UserClaims::new(user, None, 1))?)?;
+        assert!(!chat_core::utils::is_token_revoked(&state.pool, &claims).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_refresh_token_should_fail() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let err = state.rotate_refresh_token("invalid").await.unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized: invalid refresh token");
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use std::mem;
 
+use super::invite::redeem;
+use super::user_token::set_pending_email;
+use super::workspace::add_workspace_member;
 use crate::{AppError, AppState};
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
 
-use chat_core::{ChatUser, User};
+use chat_core::{ChatUser, User, WorkspaceRole};
 use jwt_simple::prelude::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -13,30 +16,51 @@ use utoipa::ToSchema;
 pub struct CreateUser {
     pub fullname: String,
     pub email: String,
+    /// name of the new workspace, ignored when an invite is given
+    #[serde(default)]
     pub workspace: String,
     pub password: String,
+    /// invite code, the user joins the workspace of the invite
+    #[serde(default)]
+    pub invite: Option<String>,
 }
 #[derive(Debug, Clone, ToSchema, Deserialize, Serialize)]
 pub struct SigninUser {
     pub email: String,
     pub password: String,
+    /// invite code, the user moves to the workspace of the invite
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+#[derive(Debug, Clone, Default, ToSchema, Deserialize, Serialize)]
+pub struct UpdateUser {
+    pub fullname: Option<String>,
+    /// the new address only takes effect after it has been verified
+    pub email: Option<String>,
+}
+#[derive(Debug, Clone, ToSchema, Deserialize, Serialize)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
 }
 impl AppState {
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
-        let user =
-            sqlx::query_as("SELECT id,ws_id,fullname,email,created_at FROM users WHERE email = $1")
-                .bind(email)
-                .fetch_optional(&self.pool)
-                .await?;
+        let user = sqlx::query_as(
+            "SELECT id,ws_id,fullname,email,is_bot,created_at FROM users WHERE email = $1",
+        )
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
         Ok(user)
     }
     #[allow(unused)]
     pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
-        let user =
-            sqlx::query_as("SELECT id,ws_id,fullname,email,created_at FROM users WHERE id = $1")
-                .bind(id)
-                .fetch_optional(&self.pool)
-                .await?;
+        let user = sqlx::query_as(
+            "SELECT id,ws_id,fullname,email,is_bot,created_at FROM users WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
         Ok(user)
     }
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
@@ -45,12 +69,30 @@ impl AppState {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
-            None => self.create_workspace(&input.workspace, 0).await?,
-            Some(ws) => ws,
-        };
         let password_hash = hash_password(&input.password)?;
-
+        let mut tx = self.pool.begin().await?;
+        let (ws, role) = match &input.invite {
+            // the invite is only used up together with the insert of the user
+            Some(code) => {
+                let (ws_id, role) = redeem(&mut *tx, code, &input.email).await?;
+                let ws = self
+                    .find_workspace_by_id(ws_id as _)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("workspace id {ws_id}")))?;
+                (ws, role)
+            }
+            None => match self.find_workspace_by_name(&input.workspace).await? {
+                None => (
+                    self.create_workspace(&input.workspace, 0).await?,
+                    WorkspaceRole::Member,
+                ),
+                // the built-in "none" workspace of the initial migration is open to everyone
+                Some(ws) if ws.id == 0 || self.config.auth.allow_join_by_name => {
+                    (ws, WorkspaceRole::Member)
+                }
+                Some(ws) => return Err(AppError::WorkspaceAlreadyExists(ws.name)),
+            },
+        };
         let user: User = sqlx::query_as(
             r#"
         INSERT INTO users (ws_id,email,fullname,password_hash) VALUES ($1,$2,$3,$4)
@@ -61,8 +103,10 @@ impl AppState {
         .bind(&input.email)
         .bind(&input.fullname)
         .bind(password_hash)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        add_workspace_member(&mut *tx, ws.id, user.id, role).await?;
+        tx.commit().await?;
         if ws.owner_id == 0 {
             self.update_workspace_owner(ws, user.id as _).await?;
         }
@@ -79,7 +123,7 @@ impl AppState {
         //     .await?;
 
         let user: Option<User> = sqlx::query_as(
-            "SELECT id,ws_id,fullname,email,password_hash,created_at FROM users WHERE email = $1",
+            "SELECT id,ws_id,fullname,email,password_hash,created_at FROM users WHERE email = $1 AND NOT is_bot",
         )
         .bind(&input.email)
         .fetch_optional(&self.pool)
@@ -99,14 +143,94 @@ impl AppState {
         }
     }
 
-    pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
+    pub async fn update_user_fullname(
+        &self,
+        user_id: i64,
+        fullname: &str,
+    ) -> Result<User, AppError> {
+        let user = sqlx::query_as(
+            r#"
+            UPDATE users SET fullname = $2 WHERE id = $1
+            RETURNING id, ws_id, fullname, email, created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(fullname)
+        .fetch_optional(&self.pool)
+        .await?;
+        user.ok_or_else(|| AppError::NotFound(format!("user id {user_id}")))
+    }
+
+    /// check every field before any is written, the new address is only mailed once committed
+    pub async fn update_me(&self, user: &User, input: &UpdateUser) -> Result<User, AppError> {
+        let email = input.email.as_deref().filter(|v| *v != user.email);
+        if let Some(email) = email {
+            self.ensure_email_available(email).await?;
+        }
+        let mut tx = self.pool.begin().await?;
+        let updated: Option<User> = sqlx::query_as(
+            r#"
+            UPDATE users SET fullname = COALESCE($2, fullname) WHERE id = $1
+            RETURNING id, ws_id, fullname, email, created_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(input.fullname.as_deref())
+        .fetch_optional(&mut *tx)
+        .await?;
+        let updated = updated.ok_or_else(|| AppError::NotFound(format!("user id {}", user.id)))?;
+        let token = match email {
+            Some(email) => Some(set_pending_email(&mut tx, user.id, email).await?),
+            None => None,
+        };
+        tx.commit().await?;
+        if let (Some(email), Some(token)) = (email, token) {
+            self.send_email_change(&updated, email, &token).await?;
+        }
+        Ok(updated)
+    }
+
+    /// replace the password after checking the current one, every session of the user is logged out
+    pub async fn change_password(
+        &self,
+        user_id: i64,
+        input: &ChangePassword,
+    ) -> Result<(), AppError> {
+        let password_hash: Option<Option<String>> =
+            sqlx::query_scalar("SELECT password_hash FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(password_hash) = password_hash else {
+            return Err(AppError::NotFound(format!("user id {user_id}")));
+        };
+        if !verify_password(&input.current_password, &password_hash.unwrap_or_default())? {
+            return Err(AppError::InvalidPassword);
+        }
+        let password_hash = hash_password(&input.new_password)?;
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&self.pool)
+            .await?;
+        self.revoke_all_sessions(user_id).await
+    }
+
+    /// users of the workspace among `ids`
+    pub async fn fetch_chat_user_by_ids(
+        &self,
+        ws_id: u64,
+        ids: &[i64],
+    ) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-        SELECT id, fullname, email
-        FROM users
-        WHERE id = ANY($1)
+        SELECT u.id, u.fullname, u.email, u.is_bot, u.display_name, u.avatar
+        FROM users u
+        JOIN workspace_members m ON m.user_id = u.id
+        WHERE m.ws_id = $1 AND m.deactivated_at IS NULL AND u.id = ANY($2)
         "#,
         )
+        .bind(ws_id as i64)
         .bind(ids)
         .fetch_all(&self.pool)
         .await
@@ -117,9 +241,10 @@ impl AppState {
     pub async fn fetch_chat_user_all(&self, ws_is: u64) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
-            SELECT id ,fullname, email
-            FROM users
-            WHERE ws_id  = $1
+            SELECT u.id, u.fullname, u.email, u.is_bot, u.display_name, u.avatar
+            FROM users u
+            JOIN workspace_members m ON m.user_id = u.id
+            WHERE m.ws_id = $1 AND m.deactivated_at IS NULL
             "#,
         )
         .bind(ws_is as i64)
@@ -129,7 +254,7 @@ impl AppState {
     }
 }
 // impl ChatUser {}
-fn hash_password(password: &str) -> Result<String, AppError> {
+pub(crate) fn hash_password(password: &str) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
     let password_hash = argon2
@@ -138,6 +263,10 @@ fn hash_password(password: &str) -> Result<String, AppError> {
     Ok(password_hash)
 }
 fn verify_password(password: &str, password_hash_string: &str) -> Result<bool, AppError> {
+    // bots and single sign-on users have no password
+    if password_hash_string.is_empty() {
+        return Ok(false);
+    }
     let argon2 = Argon2::default();
     let password_hash = PasswordHash::new(password_hash_string)?;
     let is_valid = argon2
@@ -153,6 +282,7 @@ impl CreateUser {
             workspace: workspace.to_string(),
             fullname: fullname.to_string(),
             password: password.to_string(),
+            invite: None,
         }
     }
 }
@@ -162,6 +292,7 @@ impl SigninUser {
         Self {
             email: email.to_string(),
             password: password.to_string(),
+            invite: None,
         }
     }
 }
@@ -221,6 +352,54 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn update_user_fullname_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.update_user_fullname(1, "Tyr").await?;
+        assert_eq!(user.fullname, "Tyr");
+        assert_eq!(user.email, "tchen1@acme.org");
+        assert!(state.update_user_fullname(100, "Tyr").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_me_should_not_apply_partially() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = UpdateUser {
+            fullname: Some("Tyr".to_string()),
+            email: Some("tchen2@acme.org".to_string()),
+        };
+        let ret = state.update_me(&user, &input).await;
+        assert!(matches!(ret, Err(AppError::EmailAlreadyExists(_))));
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.fullname, "Tyr chen");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChangePassword {
+            current_password: "wrong".to_string(),
+            new_password: "hunter42".to_string(),
+        };
+        let ret = state.change_password(1, &input).await;
+        assert!(matches!(ret, Err(AppError::InvalidPassword)));
+
+        let input = ChangePassword {
+            current_password: "123456".to_string(),
+            new_password: "hunter42".to_string(),
+        };
+        state.change_password(1, &input).await?;
+        assert_eq!(state.token_version(1).await?, 1);
+        let user = state
+            .verify_user(&SigninUser::new("tchen1@acme.org", "hunter42"))
+            .await?;
+        assert!(user.is_some());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn find_user_by_id_should_test() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
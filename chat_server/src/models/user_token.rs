@@ -0,0 +1,394 @@
+use super::token::{generate_token, hash_token};
+use super::user::hash_password;
+use crate::mail::Mail;
+use crate::{AppError, AppState};
+use chat_core::User;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// email verification links are valid for 2 days
+const EMAIL_VERIFICATION_DURATION: i64 = 60 * 60 * 48;
+/// password reset links are valid for 30 minutes
+const PASSWORD_RESET_DURATION: i64 = 60 * 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "user_token_kind", rename_all = "snake_case")]
+pub enum UserTokenKind {
+    EmailVerification,
+    PasswordReset,
+    EmailChange,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ForgotPassword {
+    pub email: String,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+impl AppState {
+    /// create a single-use token, older unused tokens of the same kind are invalidated
+    pub async fn create_user_token(
+        &self,
+        user_id: i64,
+        kind: UserTokenKind,
+    ) -> Result<String, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let token = insert_user_token(&mut tx, user_id, kind).await?;
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    /// mark the token as used, returns the user it was issued for
+    pub async fn consume_user_token(
+        &self,
+        token: &str,
+        kind: UserTokenKind,
+    ) -> Result<i64, AppError> {
+        let user_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE user_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND kind = $2 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(kind)
+        .fetch_optional(&self.pool)
+        .await?;
+        user_id.ok_or_else(|| AppError::InvalidToken("token is invalid or expired".to_string()))
+    }
+
+    pub async fn send_email_verification(&self, user: &User) -> Result<(), AppError> {
+        let token = self
+            .create_user_token(user.id, UserTokenKind::EmailVerification)
+            .await?;
+        let link = format!("{}/verify-email?token={}", self.config.mail.base_url, token);
+        let mail = Mail::new(
+            &user.email,
+            "Verify your email address",
+            format!(
+                "Hi {},\n\nplease verify your email address by opening the link below:\n\n{}\n",
+                user.fullname, link
+            ),
+        );
+        self.mailer.send(mail).await
+    }
+
+    /// remember the new address and send a verification link to it
+    pub async fn request_email_change(&self, user: &User, email: &str) -> Result<(), AppError> {
+        self.ensure_email_available(email).await?;
+        let mut tx = self.pool.begin().await?;
+        let token = set_pending_email(&mut tx, user.id, email).await?;
+        tx.commit().await?;
+        self.send_email_change(user, email, &token).await
+    }
+
+    pub(crate) async fn ensure_email_available(&self, email: &str) -> Result<(), AppError> {
+        if self.find_user_by_email(email).await?.is_some() {
+            return Err(AppError::EmailAlreadyExists(email.to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn send_email_change(
+        &self,
+        user: &User,
+        email: &str,
+        token: &str,
+    ) -> Result<(), AppError> {
+        let link = format!("{}/verify-email?token={}", self.config.mail.base_url, token);
+        let mail = Mail::new(
+            email,
+            "Confirm your new email address",
+            format!(
+                "Hi {},\n\nopen the link below to use this address for your account:\n\n{}\n",
+                user.fullname, link
+            ),
+        );
+        self.mailer.send(mail).await
+    }
+
+    /// handles the links of both the signup verification and the email change mails
+    pub async fn verify_email(&self, token: &str) -> Result<(), AppError> {
+        let user_id = match self
+            .consume_user_token(token, UserTokenKind::EmailVerification)
+            .await
+        {
+            Ok(user_id) => user_id,
+            Err(AppError::InvalidToken(_)) => {
+                let user_id = self
+                    .consume_user_token(token, UserTokenKind::EmailChange)
+                    .await?;
+                return self.apply_email_change(user_id).await;
+            }
+            Err(e) => return Err(e),
+        };
+        sqlx::query("UPDATE users SET email_verified_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn apply_email_change(&self, user_id: i64) -> Result<(), AppError> {
+        let pending: Option<Option<String>> =
+            sqlx::query_scalar("SELECT pending_email FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(email) = pending.flatten() else {
+            return Err(AppError::InvalidToken(
+                "no pending email change".to_string(),
+            ));
+        };
+        // the address may have been taken since the change was requested
+        if self.find_user_by_email(&email).await?.is_some() {
+            return Err(AppError::EmailAlreadyExists(email));
+        }
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET email = pending_email, pending_email = NULL, email_verified_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn is_email_verified(&self, user_id: i64) -> Result<bool, AppError> {
+        let verified: Option<bool> =
+            sqlx::query_scalar("SELECT email_verified_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(verified.unwrap_or_default())
+    }
+
+    /// send a reset link if the address belongs to a user, unknown addresses are ignored silently
+    pub async fn request_password_reset(&self, email: &str) -> Result<(), AppError> {
+        let Some(user) = self.find_user_by_email(email).await? else {
+            info!("password reset requested for unknown email {}", email);
+            return Ok(());
+        };
+        let token = self
+            .create_user_token(user.id, UserTokenKind::PasswordReset)
+            .await?;
+        let link = format!(
+            "{}/reset-password?token={}",
+            self.config.mail.base_url, token
+        );
+        let mail = Mail::new(
+            &user.email,
+            "Reset your password",
+            format!(
+                "Hi {},\n\nopen the link below to choose a new password, it expires in 30 minutes:\n\n{}\n\nIf you did not ask for it, you can ignore this mail.\n",
+                user.fullname, link
+            ),
+        );
+        self.mailer.send(mail).await
+    }
+
+    /// set a new password and log out every session of the user
+    pub async fn reset_password(&self, input: &ResetPassword) -> Result<(), AppError> {
+        let user_id = self
+            .consume_user_token(&input.token, UserTokenKind::PasswordReset)
+            .await?;
+        let password_hash = hash_password(&input.password)?;
+        // receiving the reset mail proves the address is real
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET password_hash = $2, email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .execute(&self.pool)
+        .await?;
+        self.revoke_all_sessions(user_id).await
+    }
+}
+
+async fn insert_user_token(
+    conn: &mut PgConnection,
+    user_id: i64,
+    kind: UserTokenKind,
+) -> Result<String, AppError> {
+    let duration = match kind {
+        UserTokenKind::EmailVerification | UserTokenKind::EmailChange => {
+            EMAIL_VERIFICATION_DURATION
+        }
+        UserTokenKind::PasswordReset => PASSWORD_RESET_DURATION,
+    };
+    let token = generate_token();
+    sqlx::query(
+        r#"
+        UPDATE user_tokens
+        SET used_at = NOW()
+        WHERE user_id = $1 AND kind = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(kind)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO user_tokens (user_id, kind, token_hash, expires_at)
+        VALUES ($1, $2, $3, NOW() + $4 * INTERVAL '1 second')
+        "#,
+    )
+    .bind(user_id)
+    .bind(kind)
+    .bind(hash_token(&token))
+    .bind(duration as f64)
+    .execute(&mut *conn)
+    .await?;
+    Ok(token)
+}
+
+/// the address is only used once the returned token is verified
+pub(crate) async fn set_pending_email(
+    conn: &mut PgConnection,
+    user_id: i64,
+    email: &str,
+) -> Result<String, AppError> {
+    sqlx::query("UPDATE users SET pending_email = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(email)
+        .execute(&mut *conn)
+        .await?;
+    insert_user_token(conn, user_id, UserTokenKind::EmailChange).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SigninUser;
+
+    #[tokio::test]
+    async fn user_token_should_be_single_use() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token = state
+            .create_user_token(1, UserTokenKind::PasswordReset)
+            .await?;
+        // a token of another kind is rejected
+        assert!(state
+            .consume_user_token(&token, UserTokenKind::EmailVerification)
+            .await
+            .is_err());
+        let user_id = state
+            .consume_user_token(&token, UserTokenKind::PasswordReset)
+            .await?;
+        assert_eq!(user_id, 1);
+        let err = state
+            .consume_user_token(&token, UserTokenKind::PasswordReset)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid token: token is invalid or expired"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_user_token_should_invalidate_old_one() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let token1 = state
+            .create_user_token(1, UserTokenKind::PasswordReset)
+            .await?;
+        let token2 = state
+            .create_user_token(1, UserTokenKind::PasswordReset)
+            .await?;
+        assert!(state
+            .consume_user_token(&token1, UserTokenKind::PasswordReset)
+            .await
+            .is_err());
+        assert!(state
+            .consume_user_token(&token2, UserTokenKind::PasswordReset)
+            .await
+            .is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn password_reset_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.request_password_reset("tchen1@acme.org").await?;
+        state.request_password_reset("nobody@acme.org").await?;
+        let outbox = state.outbox().expect("outbox mailer");
+        assert_eq!(outbox.mails().len(), 1);
+        let token = outbox.last_token_to("tchen1@acme.org").expect("mail sent");
+        let input = ResetPassword {
+            token,
+            password: "new-password".to_string(),
+        };
+        state.reset_password(&input).await?;
+        assert_eq!(state.token_version(1).await?, 1);
+
+        let user = state
+            .verify_user(&SigninUser::new("tchen1@acme.org", "new-password"))
+            .await?;
+        assert!(user.is_some());
+        let user = state
+            .verify_user(&SigninUser::new("tchen1@acme.org", "123456"))
+            .await?;
+        assert!(user.is_none());
+        // the link can only be used once
+        assert!(state.reset_password(&input).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_verification_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert!(!state.is_email_verified(1).await?);
+        state.send_email_verification(&user).await?;
+        let token = state
+            .outbox()
+            .and_then(|v| v.last_token_to(&user.email))
+            .expect("mail sent");
+        state.verify_email(&token).await?;
+        assert!(state.is_email_verified(1).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn email_change_should_need_verification() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let ret = state.request_email_change(&user, "tchen2@acme.org").await;
+        assert!(matches!(ret, Err(AppError::EmailAlreadyExists(_))));
+
+        state.request_email_change(&user, "tyr@acme.org").await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.email, "tchen1@acme.org");
+
+        let token = state
+            .outbox()
+            .and_then(|v| v.last_token_to("tyr@acme.org"))
+            .expect("mail sent");
+        state.verify_email(&token).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.email, "tyr@acme.org");
+        assert!(state.is_email_verified(1).await?);
+        Ok(())
+    }
+}
@@ -1,12 +1,40 @@
+use super::profile::validate_timezone;
+use crate::policy::Action;
 use crate::{AppError, AppState};
-use chat_core::WorkSpace;
+use chat_core::{User, WorkSpace, WorkspaceRole};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgExecutor};
+use std::io::ErrorKind;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// rename the workspace or hand it to another member, only the owner can do the latter
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateWorkspace {
+    pub name: Option<String>,
+    pub owner_id: Option<i64>,
+    /// iana name, e.g. Asia/Shanghai
+    pub timezone: Option<String>,
+}
+
+/// a workspace the user belongs to
+#[derive(Debug, Clone, ToSchema, FromRow, Serialize, Deserialize)]
+pub struct UserWorkspace {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    pub role: WorkspaceRole,
+    pub joined_at: DateTime<Local>,
+    pub deleted_at: Option<DateTime<Local>>,
+}
 
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<WorkSpace, AppError> {
         let ws = sqlx::query_as(
             r#"
         INSERT INTO workspaces (name,owner_id) VALUES ($1,$2)
-        RETURNING id , name,owner_id,created_at
+        RETURNING id , name,owner_id,created_at,deleted_at,timezone
         "#,
         )
         .bind(name)
@@ -15,29 +43,21 @@ impl AppState {
         .await?;
         Ok(ws)
     }
+    /// the previous owner stays on as an admin
     pub async fn update_workspace_owner(
         &self,
         work_space: WorkSpace,
         owner_id: u64,
     ) -> Result<WorkSpace, AppError> {
-        let ws = sqlx::query_as(
-            r#"
-        UPDATE  workspaces
-        SET owner_id = $1
-        WHERE id = $2 and  (SELECT ws_id FROM users WHERE id = $1) = $2
-        RETURNING id , name,owner_id,created_at
-        "#,
-        )
-        .bind(owner_id as i64)
-        .bind(work_space.id)
-        .fetch_one(&self.pool)
-        .await?;
+        let mut tx = self.pool.begin().await?;
+        let ws = set_workspace_owner(&mut tx, work_space.id, owner_id as _).await?;
+        tx.commit().await?;
         Ok(ws)
     }
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<WorkSpace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id,name ,owner_id,created_at
+        SELECT id,name ,owner_id,created_at,deleted_at,timezone
         FROM workspaces
         WHERE name = $1
         "#,
@@ -51,7 +71,7 @@ impl AppState {
     pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<WorkSpace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id,name ,owner_id,created_at
+        SELECT id,name ,owner_id,created_at,deleted_at,timezone
         FROM workspaces
         WHERE id = $1
         "#,
@@ -61,23 +81,356 @@ impl AppState {
         .await?;
         Ok(ws)
     }
+    pub async fn list_user_workspaces(&self, user_id: i64) -> Result<Vec<UserWorkspace>, AppError> {
+        let workspaces = sqlx::query_as(
+            r#"
+        SELECT w.id, w.name, w.owner_id, m.role, m.created_at AS joined_at, w.deleted_at
+        FROM workspace_members m
+        JOIN workspaces w ON w.id = m.ws_id
+        WHERE m.user_id = $1 AND m.deactivated_at IS NULL
+        ORDER BY w.id
+        "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(workspaces)
+    }
+    /// whether the user is an active member of the workspace
+    pub async fn is_workspace_member(&self, ws_id: i64, user_id: i64) -> Result<bool, AppError> {
+        let member: Option<i32> = sqlx::query_scalar(
+            r#"
+        SELECT 1 FROM workspace_members
+        WHERE ws_id = $1 AND user_id = $2 AND deactivated_at IS NULL
+        "#,
+        )
+        .bind(ws_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(member.is_some())
+    }
+    /// the workspace of the token, for any of its members
+    pub async fn current_workspace(&self, user: &User) -> Result<WorkSpace, AppError> {
+        self.workspace_role(user).await?;
+        self.find_workspace_by_id(user.ws_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id {}", user.ws_id)))
+    }
+    pub async fn update_workspace(
+        &self,
+        user: &User,
+        input: &UpdateWorkspace,
+    ) -> Result<WorkSpace, AppError> {
+        let name = input.name.as_deref().map(str::trim);
+        // authorize and validate every field before any of them is written
+        if name.is_some() || input.timezone.is_some() {
+            self.authorize(user, Action::ManageWorkspace).await?;
+        }
+        if input.owner_id.is_some() {
+            self.authorize(user, Action::TransferOwnership).await?;
+        }
+        if let Some(name) = name {
+            self.check_workspace_name(user.ws_id, name).await?;
+        }
+        if let Some(timezone) = &input.timezone {
+            validate_timezone(timezone).map_err(|_| {
+                AppError::UpdateWorkspaceError(format!("unknown timezone {timezone}"))
+            })?;
+        }
+        if let Some(owner_id) = input.owner_id {
+            match self.find_user_by_id(owner_id).await? {
+                Some(owner)
+                    if !owner.is_bot && self.is_workspace_member(user.ws_id, owner.id).await? => {}
+                _ => return Err(AppError::NotFound(format!("user id {owner_id}"))),
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if let Some(name) = name {
+            sqlx::query("UPDATE workspaces SET name = $2 WHERE id = $1")
+                .bind(user.ws_id)
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if let Some(timezone) = &input.timezone {
+            sqlx::query("UPDATE workspaces SET timezone = $2 WHERE id = $1")
+                .bind(user.ws_id)
+                .bind(timezone)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if let Some(owner_id) = input.owner_id {
+            set_workspace_owner(&mut tx, user.ws_id, owner_id).await?;
+        }
+        tx.commit().await?;
+        self.current_workspace(user).await
+    }
+    /// whether the workspace may take the name
+    async fn check_workspace_name(&self, ws_id: i64, name: &str) -> Result<(), AppError> {
+        // same limits as the workspaces table
+        if name.is_empty() || name.chars().count() > 32 {
+            return Err(AppError::UpdateWorkspaceError(
+                "name must have 1 to 32 characters".to_string(),
+            ));
+        }
+        match self.find_workspace_by_name(name).await? {
+            Some(ws) if ws.id != ws_id => Err(AppError::WorkspaceNameTaken(name.to_string())),
+            _ => Ok(()),
+        }
+    }
+    /// soft delete, the workspace can be restored until the grace period is over
+    pub async fn delete_workspace(&self, user: &User) -> Result<WorkSpace, AppError> {
+        self.authorize(user, Action::DeleteWorkspace).await?;
+        if user.ws_id == 0 {
+            return Err(AppError::PermissionDenied(
+                "the built-in workspace can not be deleted".to_string(),
+            ));
+        }
+        let ws = sqlx::query_as(
+            r#"
+        UPDATE workspaces SET deleted_at = COALESCE(deleted_at, NOW())
+        WHERE id = $1
+        RETURNING id, name, owner_id, created_at, deleted_at, timezone
+        "#,
+        )
+        .bind(user.ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ws)
+    }
+    pub async fn restore_workspace(&self, user: &User) -> Result<WorkSpace, AppError> {
+        self.authorize(user, Action::DeleteWorkspace).await?;
+        let ws = sqlx::query_as(
+            r#"
+        UPDATE workspaces SET deleted_at = NULL
+        WHERE id = $1
+        RETURNING id, name, owner_id, created_at, deleted_at, timezone
+        "#,
+        )
+        .bind(user.ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(ws)
+    }
+    pub async fn is_workspace_deleted(&self, ws_id: i64) -> Result<bool, AppError> {
+        let deleted: Option<bool> =
+            sqlx::query_scalar("SELECT deleted_at IS NOT NULL FROM workspaces WHERE id = $1")
+                .bind(ws_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(deleted.unwrap_or(true))
+    }
+    /// purge the workspaces whose grace period is over, returns their ids
+    pub async fn purge_deleted_workspaces(&self) -> Result<Vec<i64>, AppError> {
+        let ids: Vec<i64> = sqlx::query_scalar(
+            r#"
+        SELECT id FROM workspaces
+        WHERE deleted_at < NOW() - $1 * INTERVAL '1 day'
+        "#,
+        )
+        .bind(self.config.server.workspace_grace_days as f64)
+        .fetch_all(&self.pool)
+        .await?;
+        for id in &ids {
+            self.purge_workspace(*id).await?;
+        }
+        Ok(ids)
+    }
+    /// remove the workspace with its chats, messages, invites, scim tokens and files.
+    /// users stay, they land in another of their workspaces at signin
+    async fn purge_workspace(&self, ws_id: i64) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM messages WHERE chat_id IN (SELECT id FROM chats WHERE ws_id = $1)",
+        )
+        .bind(ws_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM chats WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM workspace_invites WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM scim_tokens WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        // bots only ever belong to one workspace
+        sqlx::query(
+            r#"
+        UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+        WHERE user_id IN (SELECT id FROM users WHERE ws_id = $1 AND is_bot)
+        "#,
+        )
+        .bind(ws_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM workspace_members WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE refresh_tokens SET ws_id = NULL WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+        UPDATE users u
+        SET ws_id = COALESCE((
+            SELECT MIN(ws_id) FROM workspace_members
+            WHERE user_id = u.id AND deactivated_at IS NULL
+        ), 0)
+        WHERE ws_id = $1
+        "#,
+        )
+        .bind(ws_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let dir = self.config.server.base_dir.join(ws_id.to_string());
+        match tokio::fs::remove_dir_all(&dir).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        info!("purged workspace {ws_id}");
+        Ok(())
+    }
+    /// point the session at another workspace of the user, it is also where their next signin lands
+    pub async fn select_workspace(
+        &self,
+        user_id: i64,
+        sid: &str,
+        ws_id: i64,
+    ) -> Result<(), AppError> {
+        if !self.is_workspace_member(ws_id, user_id).await? {
+            return Err(AppError::NotFound(format!("workspace id {ws_id}")));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+        UPDATE refresh_tokens SET ws_id = $3
+        WHERE user_id = $1 AND family_id = $2 AND used_at IS NULL AND revoked_at IS NULL
+        "#,
+        )
+        .bind(user_id)
+        .bind(sid)
+        .bind(ws_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE users SET ws_id = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    /// the workspace a signin lands in, the default one unless the user was deactivated there
+    pub async fn active_workspace(&self, user: &User) -> Result<i64, AppError> {
+        if self.is_workspace_member(user.ws_id, user.id).await? {
+            return Ok(user.ws_id);
+        }
+        let (active, total): (Option<i64>, i64) = sqlx::query_as(
+            r#"
+        SELECT MIN(ws_id) FILTER (WHERE deactivated_at IS NULL), COUNT(*)
+        FROM workspace_members
+        WHERE user_id = $1
+        "#,
+        )
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await?;
+        match active {
+            Some(ws_id) => Ok(ws_id),
+            None if total > 0 => Err(AppError::UserDeactivated),
+            // users left without any workspace keep signing in to theirs
+            None => Ok(user.ws_id),
+        }
+    }
+}
+
+/// existing memberships keep their role
+pub(crate) async fn add_workspace_member(
+    executor: impl PgExecutor<'_>,
+    ws_id: i64,
+    user_id: i64,
+    role: WorkspaceRole,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO workspace_members (ws_id, user_id, role) VALUES ($1, $2, $3)
+        ON CONFLICT (ws_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(ws_id)
+    .bind(user_id)
+    .bind(role)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// hand the workspace over to an active member, the previous owner becomes an admin
+async fn set_workspace_owner(
+    conn: &mut PgConnection,
+    ws_id: i64,
+    owner_id: i64,
+) -> Result<WorkSpace, AppError> {
+    let ws: WorkSpace = sqlx::query_as(
+        r#"
+        UPDATE  workspaces
+        SET owner_id = $1
+        WHERE id = $2
+          AND EXISTS (
+            SELECT 1 FROM workspace_members
+            WHERE ws_id = $2 AND user_id = $1 AND deactivated_at IS NULL
+          )
+        RETURNING id , name,owner_id,created_at,deleted_at,timezone
+        "#,
+    )
+    .bind(owner_id)
+    .bind(ws_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE workspace_members
+        SET role = CASE WHEN user_id = $1 THEN 'owner' ELSE 'admin' END::workspace_role
+        WHERE ws_id = $2 AND (user_id = $1 OR role = 'owner')
+        "#,
+    )
+    .bind(owner_id)
+    .bind(ws.id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(ws)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::models::CreateUser;
-    use crate::AppState;
     #[tokio::test]
     async fn workspace_should_creat_and_set_owner() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let ws = state.create_workspace("test", 0).await?;
-
-        let input = CreateUser::new(&ws.name, "qazwsx2228@163.com", "zhang", "Hunter42");
+        let input = CreateUser::new("test", "qazwsx2228@163.com", "zhang", "Hunter42");
 
         let user = state.create_user(&input).await?;
-        assert_eq!(ws.name, "test");
+        let ws = state
+            .find_workspace_by_name("test")
+            .await?
+            .expect("workspace should exist");
         assert_eq!(user.ws_id, ws.id);
-        let ws = state.update_workspace_owner(ws, user.id as _).await?;
         assert_eq!(ws.owner_id, user.id);
         Ok(())
     }
@@ -104,4 +457,127 @@ mod tests {
         // assert_eq!(users.clone().split_off(2),users);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn workspace_should_rename_and_transfer() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let input = UpdateWorkspace {
+            name: Some("foo".to_string()),
+            ..Default::default()
+        };
+        let ret = state.update_workspace(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::WorkspaceNameTaken(_))));
+        let input = UpdateWorkspace {
+            name: Some(" acme2 ".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(state.update_workspace(&owner, &input).await?.name, "acme2");
+        let input = UpdateWorkspace {
+            timezone: Some("Asia/Shanghai".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            state.update_workspace(&owner, &input).await?.timezone,
+            "Asia/Shanghai"
+        );
+
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let input = UpdateWorkspace {
+            owner_id: Some(member.id),
+            ..Default::default()
+        };
+        let ret = state.update_workspace(&member, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let ws = state.update_workspace(&owner, &input).await?;
+        assert_eq!(ws.owner_id, member.id);
+        assert_eq!(state.workspace_role(&owner).await?, WorkspaceRole::Admin);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_update_should_apply_all_fields_or_none() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        // an admin may rename, but not hand over the workspace
+        let admin = state.find_user_by_id(2).await?.expect("user should exist");
+        sqlx::query(
+            "UPDATE workspace_members SET role = 'admin' WHERE ws_id = $1 AND user_id = $2",
+        )
+        .bind(admin.ws_id)
+        .bind(admin.id)
+        .execute(&state.pool)
+        .await?;
+        let input = UpdateWorkspace {
+            name: Some("acme2".to_string()),
+            timezone: Some("Asia/Shanghai".to_string()),
+            owner_id: Some(admin.id),
+        };
+        let ret = state.update_workspace(&admin, &input).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        // an invalid new owner leaves the name and the timezone as they were
+        let input = UpdateWorkspace {
+            owner_id: Some(10000),
+            ..input
+        };
+        let ret = state.update_workspace(&owner, &input).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        let ws = state.current_workspace(&owner).await?;
+        assert_eq!(ws.name, "acme");
+        assert_ne!(ws.timezone, "Asia/Shanghai");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_workspace_should_be_purged_after_grace_period() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state.workspace_owner_for_test().await?;
+        let ws = state.delete_workspace(&owner).await?;
+        assert!(ws.deleted_at.is_some());
+        assert!(state.is_workspace_deleted(ws.id).await?);
+        // still within the grace period
+        assert!(state.purge_deleted_workspaces().await?.is_empty());
+        state.restore_workspace(&owner).await?;
+        assert!(!state.is_workspace_deleted(ws.id).await?);
+
+        let foo = state
+            .find_workspace_by_name("foo")
+            .await?
+            .expect("workspace should exist");
+        add_workspace_member(&state.pool, foo.id, 2, WorkspaceRole::Member).await?;
+        state.delete_workspace(&owner).await?;
+        sqlx::query("UPDATE workspaces SET deleted_at = NOW() - INTERVAL '31 days' WHERE id = $1")
+            .bind(ws.id)
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.purge_deleted_workspaces().await?, vec![ws.id]);
+        assert!(state.find_workspace_by_id(ws.id as _).await?.is_none());
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        assert_eq!(user.ws_id, foo.id);
+        let user = state.find_user_by_id(3).await?.expect("user should exist");
+        assert_eq!(user.ws_id, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_should_select_any_of_their_workspaces() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let foo = state
+            .find_workspace_by_name("foo")
+            .await?
+            .expect("workspace should exist");
+        assert!(state.select_workspace(1, "session", foo.id).await.is_err());
+
+        add_workspace_member(&state.pool, foo.id, 1, WorkspaceRole::Guest).await?;
+        let workspaces = state.list_user_workspaces(1).await?;
+        assert_eq!(workspaces.len(), 2);
+        assert_eq!(workspaces[1].name, "foo");
+        assert_eq!(workspaces[1].role, WorkspaceRole::Guest);
+
+        state.select_workspace(1, "session", foo.id).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        assert_eq!(user.ws_id, foo.id);
+        Ok(())
+    }
 }
@@ -0,0 +1,535 @@
+use crate::config::OidcConfig;
+use crate::AppError;
+use anyhow::Context;
+use jwt_simple::prelude::*;
+use jwt_simple::reexports::ct_codecs::{Base64UrlSafeNoPadding, Decoder, Encoder};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tracing::warn;
+
+const OIDC_SCOPES: &str = "openid email profile";
+/// clock skew accepted between us and the provider
+const ID_TOKEN_TOLERANCE: u64 = 60;
+
+/// the part of the provider metadata used by the authorization code flow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// verified identity from an id token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IdTokenClaims {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProviderJwks {
+    keys: Vec<ProviderJwk>,
+}
+
+/// RSA and P-256 keys of the provider, other key types are ignored
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ProviderJwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default, rename = "use")]
+    usage: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+/// relying party of the OpenID Connect authorization code flow with PKCE
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: Mutex<Option<ProviderMetadata>>,
+}
+
+impl OidcClient {
+    pub fn try_new(config: OidcConfig) -> anyhow::Result<Self> {
+        Url::parse(&config.issuer).context("invalid oidc issuer url")?;
+        Url::parse(&config.redirect_uri).context("invalid oidc redirect uri")?;
+        let http = reqwest::Client::builder()
+            // never follow the provider somewhere else with our client secret
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .context("build oidc http client failed")?;
+        Ok(Self {
+            config,
+            http,
+            metadata: Mutex::new(None),
+        })
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// the provider metadata, discovered once and cached afterwards
+    pub async fn metadata(&self) -> Result<ProviderMetadata, AppError> {
+        if let Some(metadata) = self.metadata.lock().unwrap().as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer != self.config.issuer {
+            return Err(AppError::OidcError(format!(
+                "provider issuer {} does not match {}",
+                metadata.issuer, self.config.issuer
+            )));
+        }
+        *self.metadata.lock().unwrap() = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// url of the provider's authorization endpoint to send the browser to
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| AppError::OidcError(format!("invalid authorization endpoint: {e}")))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", OIDC_SCOPES)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &pkce_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// redeem the authorization code and verify the returned id token
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<Identity, AppError> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let res = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            warn!("oidc token endpoint returned {}: {}", status, body);
+            return Err(AppError::OidcError(format!(
+                "token endpoint returned {status}"
+            )));
+        }
+        let token: TokenResponse = res.json().await.map_err(provider_error)?;
+        let id_token = token
+            .id_token
+            .ok_or_else(|| AppError::OidcError("token response has no id_token".to_string()))?;
+        let jwks: ProviderJwks = self.get_json(&metadata.jwks_uri).await?;
+        self.verify_id_token(&id_token, &jwks, nonce)
+    }
+
+    fn verify_id_token(
+        &self,
+        id_token: &str,
+        jwks: &ProviderJwks,
+        nonce: &str,
+    ) -> Result<Identity, AppError> {
+        let metadata = Token::decode_metadata(id_token).map_err(invalid_id_token)?;
+        let options = VerificationOptions {
+            allowed_issuers: Some(HashSet::from([self.config.issuer.clone()])),
+            allowed_audiences: Some(HashSet::from([self.config.client_id.clone()])),
+            time_tolerance: Some(Duration::from_secs(ID_TOKEN_TOLERANCE)),
+            ..Default::default()
+        };
+        let claims: JWTClaims<IdTokenClaims> = match metadata.algorithm() {
+            "RS256" => {
+                let jwk = find_jwk(jwks, metadata.key_id(), "RSA")?;
+                let key = RS256PublicKey::from_components(
+                    &decode_jwk_field(&jwk.n)?,
+                    &decode_jwk_field(&jwk.e)?,
+                )
+                .map_err(invalid_id_token)?;
+                key.verify_token(id_token, Some(options))
+            }
+            "ES256" => {
+                let jwk = find_jwk(jwks, metadata.key_id(), "EC")?;
+                if jwk.crv.as_deref() != Some("P-256") {
+                    return Err(AppError::OidcError("unsupported EC curve".to_string()));
+                }
+                let mut point = vec![0x04];
+                point.extend(decode_jwk_field(&jwk.x)?);
+                point.extend(decode_jwk_field(&jwk.y)?);
+                let key = ES256PublicKey::from_bytes(&point).map_err(invalid_id_token)?;
+                key.verify_token(id_token, Some(options))
+            }
+            alg => {
+                return Err(AppError::OidcError(format!(
+                    "unsupported id token algorithm {alg}"
+                )))
+            }
+        }
+        .map_err(invalid_id_token)?;
+
+        // jwt-simple only compares the nonce when the token carries one
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::OidcError("id token nonce mismatch".to_string()));
+        }
+        let subject = claims
+            .subject
+            .ok_or_else(|| AppError::OidcError("id token has no subject".to_string()))?;
+        let email = claims
+            .custom
+            .email
+            .ok_or_else(|| AppError::OidcError("id token has no email".to_string()))?;
+        Ok(Identity {
+            issuer: self.config.issuer.clone(),
+            subject,
+            email,
+            email_verified: claims.custom.email_verified.unwrap_or_default(),
+            name: claims.custom.name,
+        })
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        let res = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(provider_error)?;
+        res.json().await.map_err(provider_error)
+    }
+}
+
+/// S256 code challenge of RFC 7636
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    Base64UrlSafeNoPadding::encode_to_string(Sha256::digest(code_verifier.as_bytes()))
+        .expect("encode code challenge should work")
+}
+
+fn find_jwk<'a>(
+    jwks: &'a ProviderJwks,
+    kid: Option<&str>,
+    kty: &str,
+) -> Result<&'a ProviderJwk, AppError> {
+    jwks.keys
+        .iter()
+        .filter(|jwk| jwk.kty == kty && jwk.usage.as_deref().unwrap_or("sig") == "sig")
+        .find(|jwk| kid.is_none() || jwk.kid.as_deref() == kid)
+        .ok_or_else(|| AppError::OidcError("no provider key for the id token".to_string()))
+}
+
+fn decode_jwk_field(value: &Option<String>) -> Result<Vec<u8>, AppError> {
+    let value = value
+        .as_deref()
+        .ok_or_else(|| AppError::OidcError("incomplete provider key".to_string()))?;
+    Base64UrlSafeNoPadding::decode_to_vec(value, None)
+        .map_err(|_| AppError::OidcError("invalid provider key encoding".to_string()))
+}
+
+fn invalid_id_token(e: jwt_simple::Error) -> AppError {
+    AppError::OidcError(format!("invalid id token: {e}"))
+}
+
+fn provider_error(e: reqwest::Error) -> AppError {
+    AppError::OidcError(format!("request to the provider failed: {e}"))
+}
+
+/// an in-process identity provider for the tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::config::OidcDomainRule;
+    use axum::extract::{Form, Query, State};
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Redirect};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    pub(crate) const MOCK_CLIENT_ID: &str = "chat";
+
+    #[derive(Clone)]
+    pub(crate) struct MockIdp {
+        inner: Arc<MockIdpInner>,
+    }
+
+    struct MockIdpInner {
+        issuer: String,
+        key: ES256KeyPair,
+        /// email of the account signed in at the provider
+        email: Mutex<String>,
+        grants: Mutex<HashMap<String, Grant>>,
+    }
+
+    struct Grant {
+        redirect_uri: String,
+        code_challenge: String,
+        nonce: String,
+        email: String,
+    }
+
+    impl MockIdp {
+        pub(crate) async fn start() -> anyhow::Result<Self> {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let issuer = format!("http://{}", listener.local_addr()?);
+            let idp = Self {
+                inner: Arc::new(MockIdpInner {
+                    issuer,
+                    key: ES256KeyPair::generate().with_key_id("mock"),
+                    email: Mutex::new("tchen1@acme.org".to_string()),
+                    grants: Mutex::new(HashMap::new()),
+                }),
+            };
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/authorize", get(authorize))
+                .route("/token", post(token))
+                .route("/jwks", get(jwks))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Ok(idp)
+        }
+
+        pub(crate) fn config(&self) -> OidcConfig {
+            OidcConfig {
+                issuer: self.inner.issuer.clone(),
+                client_id: MOCK_CLIENT_ID.to_string(),
+                client_secret: Some("secret".to_string()),
+                redirect_uri: "http://localhost:6688/api/oidc/callback".to_string(),
+                domains: vec![OidcDomainRule {
+                    domain: "example.com".to_string(),
+                    workspace: "example".to_string(),
+                }],
+            }
+        }
+
+        pub(crate) fn sign_in_as(&self, email: &str) {
+            *self.inner.email.lock().unwrap() = email.to_string();
+        }
+
+        /// open the login url like a browser would, returns the code and the state of the callback
+        pub(crate) async fn authorize(&self, login_url: &str) -> anyhow::Result<(String, String)> {
+            let client = reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?;
+            let res = client.get(login_url).send().await?;
+            let location = res
+                .headers()
+                .get("location")
+                .context("authorize should redirect")?
+                .to_str()?;
+            let url = Url::parse(location)?;
+            let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            Ok((query["code"].clone(), query["state"].clone()))
+        }
+    }
+
+    async fn discovery(State(idp): State<MockIdp>) -> impl IntoResponse {
+        let issuer = &idp.inner.issuer;
+        Json(ProviderMetadata {
+            issuer: issuer.clone(),
+            authorization_endpoint: format!("{issuer}/authorize"),
+            token_endpoint: format!("{issuer}/token"),
+            jwks_uri: format!("{issuer}/jwks"),
+        })
+    }
+
+    async fn authorize(
+        State(idp): State<MockIdp>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        let code = uuid::Uuid::now_v7().to_string();
+        let grant = Grant {
+            redirect_uri: query["redirect_uri"].clone(),
+            code_challenge: query["code_challenge"].clone(),
+            nonce: query["nonce"].clone(),
+            email: idp.inner.email.lock().unwrap().clone(),
+        };
+        let location = format!(
+            "{}?code={}&state={}",
+            grant.redirect_uri, code, query["state"]
+        );
+        idp.inner.grants.lock().unwrap().insert(code, grant);
+        Redirect::to(&location)
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        let grant = idp
+            .inner
+            .grants
+            .lock()
+            .unwrap()
+            .remove(&form["code"])
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if form["client_id"] != MOCK_CLIENT_ID
+            || form["redirect_uri"] != grant.redirect_uri
+            || pkce_challenge(&form["code_verifier"]) != grant.code_challenge
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let custom = IdTokenClaims {
+            email: Some(grant.email.clone()),
+            email_verified: Some(true),
+            name: Some("Mock User".to_string()),
+        };
+        let claims = Claims::with_custom_claims(custom, Duration::from_mins(5))
+            .with_issuer(&idp.inner.issuer)
+            .with_audience(MOCK_CLIENT_ID)
+            .with_subject(format!("mock|{}", grant.email))
+            .with_nonce(grant.nonce);
+        let id_token = idp
+            .inner
+            .key
+            .sign(claims)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok(Json(serde_json::json!({
+            "access_token": "mock",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+
+    async fn jwks(State(idp): State<MockIdp>) -> impl IntoResponse {
+        let point = idp
+            .inner
+            .key
+            .public_key()
+            .public_key()
+            .to_bytes_uncompressed();
+        let encode = |v: &[u8]| Base64UrlSafeNoPadding::encode_to_string(v).unwrap();
+        Json(ProviderJwks {
+            keys: vec![ProviderJwk {
+                kty: "EC".to_string(),
+                kid: Some("mock".to_string()),
+                usage: Some("sig".to_string()),
+                crv: Some("P-256".to_string()),
+                x: Some(encode(&point[1..33])),
+                y: Some(encode(&point[33..65])),
+                ..Default::default()
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockIdp;
+    use super::*;
+
+    #[test]
+    fn pkce_challenge_should_match_rfc7636() {
+        let challenge = pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+        assert_eq!(challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[test]
+    fn workspace_for_should_match_domain() {
+        let mut config = OidcConfig {
+            issuer: "http://localhost".to_string(),
+            client_id: "chat".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost/api/oidc/callback".to_string(),
+            domains: vec![crate::config::OidcDomainRule {
+                domain: "acme.org".to_string(),
+                workspace: "acme".to_string(),
+            }],
+        };
+        assert_eq!(config.workspace_for("tyr@ACME.org"), Some("acme"));
+        assert_eq!(config.workspace_for("tyr@example.com"), None);
+        config.domains.push(crate::config::OidcDomainRule {
+            domain: "*".to_string(),
+            workspace: "guests".to_string(),
+        });
+        assert_eq!(config.workspace_for("tyr@example.com"), Some("guests"));
+    }
+
+    #[tokio::test]
+    async fn id_token_should_be_verified() -> anyhow::Result<()> {
+        let idp = MockIdp::start().await?;
+        let client = OidcClient::try_new(idp.config())?;
+        let verifier = "verifier".repeat(6);
+        let url = client
+            .authorization_url("state", "nonce", &verifier)
+            .await?;
+        assert!(url.contains("code_challenge_method=S256"));
+
+        let (code, state) = idp.authorize(&url).await?;
+        assert_eq!(state, "state");
+        let identity = client.exchange_code(&code, &verifier, "nonce").await?;
+        assert_eq!(identity.email, "tchen1@acme.org");
+        assert!(identity.email_verified);
+
+        // the code is single use, and bound to the verifier and the nonce
+        assert!(client
+            .exchange_code(&code, &verifier, "nonce")
+            .await
+            .is_err());
+        let (code, _) = idp.authorize(&url).await?;
+        assert!(client.exchange_code(&code, "wrong", "nonce").await.is_err());
+        let (code, _) = idp.authorize(&url).await?;
+        let ret = client.exchange_code(&code, &verifier, "other").await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+        Ok(())
+    }
+}
@@ -1,6 +1,18 @@
-use crate::{AppState, CreateChat, CreateMessage, CreateUser, ErrOutput, ListMessages, SigninUser};
+use crate::{
+    AddChatMembers, ApiKey, ApiKeyCreated, AppState, ChangePassword, Channel, ChannelPage,
+    ChatMember, CreateApiKey, CreateBot, CreateChat, CreateInvite, CreateMessage, CreateUser,
+    DeactivateUser, DirectoryPage, DirectoryUser, ErrOutput, ForgotPassword, Invite, InviteCreated,
+    ListChats, ListMessages, MfaCode, MfaEnrollment, MuteMember, OidcCallback, RecoveryCodes,
+    ResetPassword, ScimToken, ScimTokenCreated, SigninUser, UpdateChat, UpdateChatRole,
+    UpdateChatSettings, UpdateProfile, UpdateRole, UpdateUser, UpdateWorkspace, UserWorkspace,
+    VerifyEmail, WorkspaceMember,
+};
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, WorkSpace};
+use chat_core::utils::{Jwk, Jwks, Scope};
+use chat_core::{
+    Chat, ChatRole, ChatType, ChatUser, Message, NotificationLevel, PostingPolicy, User,
+    UserProfile, WorkSpace, WorkspaceRole,
+};
 use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 use utoipa_rapidoc::RapiDoc;
@@ -17,12 +29,70 @@ pub(crate) trait OpenApiRouter {
     paths(
         signup_handler,
         signin_handler,
+        signin_mfa_handler,
+        refresh_handler,
+        logout_handler,
+        logout_all_handler,
+        create_scoped_token_handler,
+        forgot_password_handler,
+        reset_password_handler,
+        verify_email_handler,
+        resend_verification_handler,
+        update_me_handler,
+        change_password_handler,
+        enroll_mfa_handler,
+        confirm_mfa_handler,
+        disable_mfa_handler,
+        list_bot_handler,
+        create_bot_handler,
+        list_api_key_handler,
+        create_api_key_handler,
+        revoke_api_key_handler,
+        list_invite_handler,
+        create_invite_handler,
+        revoke_invite_handler,
+        list_chat_users_handler,
+        get_user_handler,
+        update_profile_handler,
+        update_role_handler,
+        deactivate_user_handler,
+        reactivate_user_handler,
+        list_scim_token_handler,
+        create_scim_token_handler,
+        revoke_scim_token_handler,
+        list_workspaces_handler,
+        switch_workspace_handler,
+        get_workspace_handler,
+        update_workspace_handler,
+        delete_workspace_handler,
+        restore_workspace_handler,
+        oidc_login_handler,
+        oidc_callback_handler,
+        jwks_handler,
         list_chat_handler,
         create_chat_handler,
         get_chat_handler,
         update_chat_handler,
         delete_chat_handler,
+        add_chat_members_handler,
+        remove_chat_member_handler,
+        leave_chat_handler,
+        update_chat_settings_handler,
+        list_chat_members_handler,
+        set_chat_role_handler,
+        mute_chat_member_handler,
+        unmute_chat_member_handler,
+        lock_chat_handler,
+        unlock_chat_handler,
+        archive_chat_handler,
+        unarchive_chat_handler,
+        restore_chat_handler,
+        list_channels_handler,
+        join_channel_handler,
+        leave_channel_handler,
+        preview_channel_handler,
         send_message_handler,
+        delete_message_handler,
         list_message_handler
     ),
     components(
@@ -36,9 +106,58 @@ pub(crate) trait OpenApiRouter {
             SigninUser,
             CreateUser,
             CreateChat,
+            UpdateChat,
+            AddChatMembers,
+            ChatRole,
+            ChatMember,
+            UpdateChatRole,
+            MuteMember,
+            UpdateChatSettings,
+            PostingPolicy,
+            NotificationLevel,
+            ListChats,
+            Channel,
+            ChannelPage,
             CreateMessage,
             ListMessages,
             AuthOutput,
+            RefreshInput,
+            ForgotPassword,
+            ResetPassword,
+            VerifyEmail,
+            UpdateUser,
+            UpdateProfile,
+            UserProfile,
+            DirectoryUser,
+            DirectoryPage,
+            ChangePassword,
+            MfaPendingOutput,
+            MfaSigninInput,
+            MfaEnrollment,
+            MfaCode,
+            RecoveryCodes,
+            CreateBot,
+            CreateApiKey,
+            ApiKey,
+            ApiKeyCreated,
+            Scope,
+            CreateInvite,
+            Invite,
+            InviteCreated,
+            WorkspaceRole,
+            UpdateRole,
+            WorkspaceMember,
+            DeactivateUser,
+            ScimToken,
+            ScimTokenCreated,
+            UserWorkspace,
+            UpdateWorkspace,
+            WorkspaceTokenOutput,
+            OidcCallback,
+            CreateScopedToken,
+            ScopedTokenOutput,
+            Jwks,
+            Jwk,
             ErrOutput
         )
     ),
@@ -0,0 +1,191 @@
+use crate::AppError;
+use chat_core::{ChatRole, WorkspaceRole};
+use std::fmt;
+
+/// operations gated by the role of the user in their workspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// single, group and private chats
+    CreateChat,
+    /// create a public channel, or turn a chat into one
+    CreatePublicChannel,
+    UpdateChat,
+    DeleteChat,
+    /// act as the owner of every chat of the workspace
+    ModerateChats,
+    /// list every user of the workspace
+    ReadDirectory,
+    /// find, preview and join public channels
+    BrowseChannels,
+    /// bots, api keys and invites
+    ManageWorkspace,
+    /// change the role of other users
+    ManageRoles,
+    /// deactivate and reactivate other users
+    ManageMembers,
+    TransferOwnership,
+    /// delete or restore the workspace
+    DeleteWorkspace,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CreateChat => "create chats",
+            Self::CreatePublicChannel => "create public channels",
+            Self::UpdateChat => "update chats",
+            Self::DeleteChat => "delete chats",
+            Self::ModerateChats => "moderate chats",
+            Self::ReadDirectory => "read the member directory",
+            Self::BrowseChannels => "browse public channels",
+            Self::ManageWorkspace => "manage the workspace",
+            Self::ManageRoles => "manage roles",
+            Self::ManageMembers => "manage members",
+            Self::TransferOwnership => "transfer the ownership",
+            Self::DeleteWorkspace => "delete the workspace",
+        }
+    }
+
+    pub fn allowed_for(&self, role: WorkspaceRole) -> bool {
+        use WorkspaceRole::*;
+        match self {
+            Self::CreateChat | Self::UpdateChat | Self::ReadDirectory | Self::BrowseChannels => {
+                role != Guest
+            }
+            Self::CreatePublicChannel
+            | Self::DeleteChat
+            | Self::ModerateChats
+            | Self::ManageWorkspace
+            | Self::ManageRoles
+            | Self::ManageMembers => matches!(role, Owner | Admin),
+            Self::TransferOwnership | Self::DeleteWorkspace => role == Owner,
+        }
+    }
+
+    pub fn check(&self, role: WorkspaceRole) -> Result<(), AppError> {
+        if self.allowed_for(role) {
+            return Ok(());
+        }
+        Err(AppError::PermissionDenied(format!(
+            "a workspace {role} can not {self}"
+        )))
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// operations gated by the role of the user in a chat, see `AppState::authorize_chat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatAction {
+    /// read the chat and its messages, or leave it
+    Read,
+    /// muted members do not post, locked chats and announcement channels need a moderator
+    SendMessage,
+    /// needs a moderator once members may not invite
+    AddMembers,
+    /// rename the chat, change its settings, archive it
+    UpdateChat,
+    /// remove or mute somebody else
+    ModerateMembers,
+    /// delete messages of somebody else
+    DeleteMessages,
+    LockChat,
+    /// give members another role in the chat
+    ManageRoles,
+    DeleteChat,
+}
+
+impl ChatAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read the chat",
+            Self::SendMessage => "send messages",
+            Self::AddMembers => "add members",
+            Self::UpdateChat => "update the chat",
+            Self::ModerateMembers => "remove or mute members",
+            Self::DeleteMessages => "delete messages of others",
+            Self::LockChat => "lock the chat",
+            Self::ManageRoles => "change roles in the chat",
+            Self::DeleteChat => "delete the chat",
+        }
+    }
+
+    /// the lowest role allowed, whatever state the chat is in
+    pub fn min_role(&self) -> ChatRole {
+        match self {
+            Self::Read | Self::SendMessage | Self::AddMembers => ChatRole::Member,
+            Self::UpdateChat | Self::ModerateMembers | Self::DeleteMessages | Self::LockChat => {
+                ChatRole::Moderator
+            }
+            Self::ManageRoles | Self::DeleteChat => ChatRole::Owner,
+        }
+    }
+}
+
+impl fmt::Display for ChatAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// whether `role` may remove or mute a member with the `other` role in a chat,
+/// moderators only act on members
+pub fn can_moderate(role: ChatRole, other: ChatRole) -> bool {
+    role == ChatRole::Owner || role.rank() > other.rank()
+}
+
+/// whether `role` may act on a user with the `other` role, admins only manage members and guests
+pub fn can_manage(role: WorkspaceRole, other: WorkspaceRole) -> bool {
+    use WorkspaceRole::*;
+    match (role, other) {
+        (_, Owner) => false,
+        (Owner, _) => true,
+        (Admin, Member | Guest) => true,
+        _ => false,
+    }
+}
+
+/// whether `role` may give `target` to a user who currently has `current`,
+/// the owner role only changes hands through an ownership transfer
+pub fn can_assign_role(role: WorkspaceRole, current: WorkspaceRole, target: WorkspaceRole) -> bool {
+    can_manage(role, current) && can_manage(role, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use WorkspaceRole::*;
+
+    #[test]
+    fn guests_should_not_read_directory() {
+        assert!(Action::ReadDirectory.allowed_for(Member));
+        assert!(!Action::ReadDirectory.allowed_for(Guest));
+        assert!(!Action::BrowseChannels.allowed_for(Guest));
+        assert!(!Action::DeleteChat.allowed_for(Member));
+        assert!(Action::DeleteChat.allowed_for(Admin));
+        assert!(!Action::ModerateChats.allowed_for(Member));
+        assert_eq!(ChatAction::DeleteMessages.min_role(), ChatRole::Moderator);
+        assert_eq!(ChatAction::DeleteChat.min_role(), ChatRole::Owner);
+        assert!(Action::CreatePublicChannel.allowed_for(Owner));
+        assert!(!Action::DeleteWorkspace.allowed_for(Admin));
+    }
+
+    #[test]
+    fn admins_should_not_promote_admins() {
+        assert!(can_assign_role(Owner, Member, Admin));
+        assert!(can_assign_role(Admin, Member, Guest));
+        assert!(!can_assign_role(Admin, Member, Admin));
+        assert!(!can_assign_role(Admin, Admin, Member));
+        assert!(!can_assign_role(Owner, Owner, Admin));
+        assert!(!can_assign_role(Member, Guest, Member));
+        assert!(can_manage(Owner, Admin));
+        assert!(!can_manage(Admin, Owner));
+        assert!(can_moderate(ChatRole::Owner, ChatRole::Owner));
+        assert!(can_moderate(ChatRole::Moderator, ChatRole::Member));
+        assert!(!can_moderate(ChatRole::Moderator, ChatRole::Moderator));
+    }
+}
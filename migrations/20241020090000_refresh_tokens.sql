@@ -0,0 +1,20 @@
+-- refresh tokens are opaque, only the sha256 hash is stored
+-- every rotation keeps the family_id so a reused token can revoke the whole chain
+CREATE TABLE IF NOT EXISTS refresh_tokens
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT      NOT NULL REFERENCES users (id),
+    family_id  VARCHAR(36) NOT NULL,
+    token_hash CHAR(64)    NOT NULL,
+    expires_at timestamptz NOT NULL,
+    -- set when the token has been exchanged for a new one
+    used_at    timestamptz,
+    revoked_at timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS refresh_tokens_hash_index ON refresh_tokens (token_hash);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_index ON refresh_tokens (family_id);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_index ON refresh_tokens (user_id);
@@ -0,0 +1,57 @@
+-- token generation, bumping it logs out every session of the user
+ALTER TABLE users
+    ADD COLUMN token_version BIGINT NOT NULL DEFAULT 0;
+
+-- revoked access tokens (jti) and sessions (sid), kept until the token expires
+CREATE TABLE IF NOT EXISTS revoked_tokens
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT      NOT NULL REFERENCES users (id),
+    jti        VARCHAR(36) NOT NULL,
+    sid        VARCHAR(36),
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS revoked_tokens_jti_index ON revoked_tokens (jti);
+
+CREATE INDEX IF NOT EXISTS revoked_tokens_sid_index ON revoked_tokens (sid);
+
+-- if a session is revoked, notify with the session data
+CREATE OR REPLACE FUNCTION revoke_token()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'revoke_token: %', NEW;
+        PERFORM
+            pg_notify('token_revoked', json_build_object(
+                    'user_id', NEW.user_id,
+                    'jti', NEW.jti,
+                    'sid', NEW.sid
+                                       )::TEXT);
+    ELSIF TG_OP = 'UPDATE' AND OLD.token_version <> NEW.token_version THEN
+        RAISE NOTICE 'revoke_all_tokens: %', NEW.id;
+        PERFORM
+            pg_notify('token_revoked', json_build_object(
+                    'user_id', NEW.id,
+                    'jti', NULL,
+                    'sid', NULL
+                                       )::TEXT);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER revoke_token_trigger
+    AFTER INSERT
+    ON revoked_tokens
+    FOR EACH ROW
+EXECUTE FUNCTION revoke_token();
+
+CREATE TRIGGER revoke_all_tokens_trigger
+    AFTER UPDATE OF token_version
+    ON users
+    FOR EACH ROW
+EXECUTE FUNCTION revoke_token();
@@ -0,0 +1,24 @@
+ALTER TABLE users
+    ADD COLUMN email_verified_at timestamptz;
+
+-- users created before email verification existed are trusted
+UPDATE users
+SET email_verified_at = created_at;
+
+CREATE TYPE user_token_kind AS ENUM ('email_verification', 'password_reset');
+
+-- single-use tokens sent by mail, only the sha256 hash is stored
+CREATE TABLE IF NOT EXISTS user_tokens
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT          NOT NULL REFERENCES users (id),
+    kind       user_token_kind NOT NULL,
+    token_hash CHAR(64)        NOT NULL,
+    expires_at timestamptz     NOT NULL,
+    used_at    timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_tokens_hash_index ON user_tokens (token_hash);
+
+CREATE INDEX IF NOT EXISTS user_tokens_user_id_index ON user_tokens (user_id, kind);
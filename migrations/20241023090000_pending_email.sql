@@ -0,0 +1,5 @@
+-- a changed email address only replaces the current one after it has been verified
+ALTER TABLE users
+    ADD COLUMN pending_email VARCHAR(64);
+
+ALTER TYPE user_token_kind ADD VALUE IF NOT EXISTS 'email_change';
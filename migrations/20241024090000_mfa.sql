@@ -0,0 +1,22 @@
+-- totp second factor, the secret is kept until the enrollment is confirmed or replaced
+CREATE TABLE IF NOT EXISTS user_mfa
+(
+    user_id        BIGINT PRIMARY KEY REFERENCES users (id),
+    secret         VARCHAR(64) NOT NULL,
+    -- last accepted time step, a code can't be replayed
+    last_used_step BIGINT      NOT NULL DEFAULT 0,
+    enabled_at     timestamptz,
+    created_at     timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- one-time recovery codes, only the sha256 hash is stored
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT   NOT NULL REFERENCES users (id),
+    code_hash  CHAR(64) NOT NULL,
+    used_at    timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS mfa_recovery_codes_user_id_index ON mfa_recovery_codes (user_id);
@@ -0,0 +1,21 @@
+-- failed signin attempts, keyed by `email:<address>` and `ip:<address>`
+CREATE TABLE IF NOT EXISTS signin_attempts
+(
+    key             VARCHAR(128) PRIMARY KEY,
+    failures        INT         NOT NULL DEFAULT 0,
+    locked_until    timestamptz,
+    last_failure_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS audit_logs
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT,
+    action     VARCHAR(64) NOT NULL,
+    ip         VARCHAR(64),
+    detail     JSONB       NOT NULL DEFAULT '{}',
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_logs_user_id_index ON audit_logs (user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS audit_logs_action_index ON audit_logs (action, created_at DESC);
@@ -0,0 +1,43 @@
+-- bot accounts belong to a workspace and act through api keys only
+ALTER TABLE users
+    ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE messages
+    ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- long-lived keys, only the sha256 hash is stored
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id           BIGSERIAL PRIMARY KEY,
+    user_id      BIGINT      NOT NULL REFERENCES users (id),
+    name         VARCHAR(64) NOT NULL,
+    -- first characters of the key, shown to tell keys apart
+    prefix       VARCHAR(16) NOT NULL,
+    key_hash     CHAR(64)    NOT NULL,
+    scopes       TEXT[]      NOT NULL,
+    created_by   BIGINT      NOT NULL REFERENCES users (id),
+    expires_at   timestamptz,
+    last_used_at timestamptz,
+    revoked_at   timestamptz,
+    created_at   timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_hash_index ON api_keys (key_hash);
+CREATE INDEX IF NOT EXISTS api_keys_user_id_index ON api_keys (user_id);
+
+-- mark messages sent by bots, before the notify trigger reads the row
+CREATE OR REPLACE FUNCTION mark_bot_message()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    NEW.is_bot := COALESCE((SELECT is_bot FROM users WHERE id = NEW.sender_id), FALSE);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER mark_bot_message_trigger
+    BEFORE INSERT
+    ON messages
+    FOR EACH ROW
+EXECUTE FUNCTION mark_bot_message();
@@ -0,0 +1,22 @@
+-- accounts at an external identity provider linked to a local user
+CREATE TABLE IF NOT EXISTS user_identities
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT       NOT NULL REFERENCES users (id),
+    issuer     VARCHAR(255) NOT NULL,
+    subject    VARCHAR(255) NOT NULL,
+    email      VARCHAR(64)  NOT NULL,
+    created_at timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON user_identities (user_id);
+
+-- pending authorization requests, keyed by the sha256 hash of the state parameter
+CREATE TABLE IF NOT EXISTS oidc_logins
+(
+    state_hash    CHAR(64) PRIMARY KEY,
+    nonce         VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    created_at    timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
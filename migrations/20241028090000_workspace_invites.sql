@@ -0,0 +1,19 @@
+-- invitations into a workspace, only the sha256 hash of the code is stored
+CREATE TABLE IF NOT EXISTS workspace_invites
+(
+    id         BIGSERIAL PRIMARY KEY,
+    ws_id      BIGINT      NOT NULL REFERENCES workspaces (id),
+    code_hash  CHAR(64)    NOT NULL,
+    -- only this address may redeem the invite, NULL for invite links
+    email      VARCHAR(64),
+    -- NULL for unlimited uses
+    max_uses   INT,
+    uses       INT         NOT NULL DEFAULT 0,
+    created_by BIGINT      NOT NULL REFERENCES users (id),
+    expires_at timestamptz NOT NULL,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS workspace_invites_code_hash_idx ON workspace_invites (code_hash);
+CREATE INDEX IF NOT EXISTS workspace_invites_ws_id_idx ON workspace_invites (ws_id);
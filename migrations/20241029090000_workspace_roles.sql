@@ -0,0 +1,14 @@
+-- role of a user in their workspace
+CREATE TYPE workspace_role AS ENUM ('owner', 'admin', 'member', 'guest');
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS role workspace_role NOT NULL DEFAULT 'member';
+
+UPDATE users u
+SET role = 'owner'
+FROM workspaces w
+WHERE w.owner_id = u.id AND w.id = u.ws_id;
+
+-- the role given to the users joining through the invite
+ALTER TABLE workspace_invites
+    ADD COLUMN IF NOT EXISTS role workspace_role NOT NULL DEFAULT 'member';
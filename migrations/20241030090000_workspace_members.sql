@@ -0,0 +1,24 @@
+-- users can belong to several workspaces, users.ws_id is the workspace they land in at signin
+CREATE TABLE IF NOT EXISTS workspace_members
+(
+    ws_id      BIGINT         NOT NULL REFERENCES workspaces (id),
+    user_id    BIGINT         NOT NULL REFERENCES users (id),
+    role       workspace_role NOT NULL DEFAULT 'member',
+    created_at timestamptz    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ws_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS workspace_members_user_id_idx ON workspace_members (user_id);
+
+INSERT INTO workspace_members (ws_id, user_id, role)
+SELECT ws_id, id, role
+FROM users
+ON CONFLICT DO NOTHING;
+
+-- the role lives on the membership now
+ALTER TABLE users
+    DROP COLUMN IF EXISTS role;
+
+-- the workspace selected by the session of the refresh token family
+ALTER TABLE refresh_tokens
+    ADD COLUMN IF NOT EXISTS ws_id BIGINT REFERENCES workspaces (id);
@@ -0,0 +1,3 @@
+-- soft delete, the workspace is purged together with its chats and files after a grace period
+ALTER TABLE workspaces
+    ADD COLUMN IF NOT EXISTS deleted_at timestamptz;
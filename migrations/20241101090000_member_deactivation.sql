@@ -0,0 +1,31 @@
+-- deactivated members keep their row and their messages, but lose access to the workspace
+ALTER TABLE workspace_members
+    ADD COLUMN IF NOT EXISTS deactivated_at timestamptz;
+
+-- tell the open clients of the workspace, and the member themselves, about the change
+CREATE OR REPLACE FUNCTION member_status_changed()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF (OLD.deactivated_at IS NULL) <> (NEW.deactivated_at IS NULL) THEN
+        RAISE NOTICE 'member_status_changed: %', NEW;
+        PERFORM
+            pg_notify('member_status_changed', json_build_object(
+                    'ws_id', NEW.ws_id,
+                    'user_id', NEW.user_id,
+                    'active', NEW.deactivated_at IS NULL,
+                    'members', (SELECT array_agg(user_id)
+                                FROM workspace_members
+                                WHERE ws_id = NEW.ws_id AND deactivated_at IS NULL)
+                                               )::TEXT);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER member_status_changed_trigger
+    AFTER UPDATE OF deactivated_at
+    ON workspace_members
+    FOR EACH ROW
+EXECUTE FUNCTION member_status_changed();
@@ -0,0 +1,24 @@
+-- tokens of the identity provider, each one provisions a single workspace
+CREATE TABLE IF NOT EXISTS scim_tokens
+(
+    id           BIGSERIAL PRIMARY KEY,
+    ws_id        BIGINT      NOT NULL REFERENCES workspaces (id),
+    -- first characters of the token, shown to tell tokens apart
+    prefix       VARCHAR(16) NOT NULL,
+    token_hash   CHAR(64)    NOT NULL,
+    created_by   BIGINT      NOT NULL REFERENCES users (id),
+    last_used_at timestamptz,
+    revoked_at   timestamptz,
+    created_at   timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS scim_tokens_hash_index ON scim_tokens (token_hash);
+CREATE INDEX IF NOT EXISTS scim_tokens_ws_id_index ON scim_tokens (ws_id);
+
+-- id of the user in the identity provider
+ALTER TABLE workspace_members
+    ADD COLUMN IF NOT EXISTS external_id VARCHAR(255);
+
+-- private channels which are managed as scim groups
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS scim_group BOOLEAN NOT NULL DEFAULT FALSE;
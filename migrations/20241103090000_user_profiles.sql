@@ -0,0 +1,53 @@
+-- profile of a user, shown to the members of their workspaces
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS display_name      VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS title             VARCHAR(64),
+    -- iana name, e.g. Europe/Berlin
+    ADD COLUMN IF NOT EXISTS timezone          VARCHAR(64),
+    -- url of a file uploaded to the workspace of the user
+    ADD COLUMN IF NOT EXISTS avatar            VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS status_text       VARCHAR(100),
+    ADD COLUMN IF NOT EXISTS status_emoji      VARCHAR(32),
+    -- the status is hidden once it expired
+    ADD COLUMN IF NOT EXISTS status_expires_at timestamptz;
+
+-- default timezone of the members, used to show times of the workspace
+ALTER TABLE workspaces
+    ADD COLUMN IF NOT EXISTS timezone VARCHAR(64) NOT NULL DEFAULT 'UTC';
+
+-- tell everyone who shares a workspace with the user
+CREATE OR REPLACE FUNCTION profile_updated()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE NOTICE 'profile_updated: %', NEW.id;
+    PERFORM
+        pg_notify('profile_updated', json_build_object(
+                'profile', json_build_object(
+                        'id', NEW.id,
+                        'fullname', NEW.fullname,
+                        'display_name', NEW.display_name,
+                        'title', NEW.title,
+                        'timezone', NEW.timezone,
+                        'avatar', NEW.avatar,
+                        'status_text', NEW.status_text,
+                        'status_emoji', NEW.status_emoji,
+                        'status_expires_at', NEW.status_expires_at
+                           ),
+                'members', (SELECT array_agg(DISTINCT m.user_id)
+                            FROM workspace_members m
+                            WHERE m.deactivated_at IS NULL
+                              AND m.ws_id IN (SELECT ws_id
+                                              FROM workspace_members
+                                              WHERE user_id = NEW.id AND deactivated_at IS NULL))
+                                     )::TEXT);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER profile_updated_trigger
+    AFTER UPDATE OF fullname, display_name, title, timezone, avatar, status_text, status_emoji, status_expires_at
+    ON users
+    FOR EACH ROW
+EXECUTE FUNCTION profile_updated();
@@ -0,0 +1,6 @@
+-- fuzzy search of the member directory
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS users_fullname_trgm_index ON users USING gin (fullname gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_display_name_trgm_index ON users USING gin (display_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_email_trgm_index ON users USING gin (email gin_trgm_ops);
@@ -0,0 +1,167 @@
+-- membership of a chat, replaces the members array of chats
+CREATE TYPE chat_role AS ENUM ('owner', 'moderator', 'member');
+
+CREATE TABLE IF NOT EXISTS chat_members
+(
+    chat_id              BIGINT      NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    user_id              BIGINT      NOT NULL REFERENCES users (id),
+    role                 chat_role   NOT NULL DEFAULT 'member',
+    joined_at            timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- last message the member has seen, null when they never read the chat
+    last_read_message_id BIGINT REFERENCES messages (id) ON DELETE SET NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- the chats of a user
+CREATE INDEX IF NOT EXISTS chat_members_user_id_idx ON chat_members (user_id);
+
+INSERT INTO chat_members (chat_id, user_id, joined_at)
+SELECT DISTINCT c.id, m.user_id, c.created_at
+FROM chats c,
+     unnest(c.members) AS m(user_id)
+WHERE m.user_id IN (SELECT id FROM users)
+ON CONFLICT DO NOTHING;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+ALTER TABLE chats
+    DROP COLUMN members;
+
+-- the payload keeps the shape of a chat, members are read from chat_members
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    TARGET_ID bigint;
+    MEMBERS bigint[];
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        TARGET_ID := OLD.id;
+    ELSE
+        TARGET_ID := NEW.id;
+    END IF;
+    RAISE NOTICE 'add_to_chat: % %', TG_OP, TARGET_ID;
+    MEMBERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = TARGET_ID ORDER BY user_id);
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', TG_OP,
+                'old', to_jsonb(OLD) || jsonb_build_object('members', MEMBERS),
+                'new', to_jsonb(NEW) || jsonb_build_object('members', MEMBERS)
+                                 )::TEXT);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- a new chat is announced on commit, once its members were added
+CREATE CONSTRAINT TRIGGER add_to_chat_trigger
+    AFTER INSERT
+    ON chats
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER update_chat_trigger
+    AFTER UPDATE
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+-- before the members are removed by the cascade
+CREATE TRIGGER delete_chat_trigger
+    BEFORE DELETE
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+-- members joined or left, notify like an update of the chat.
+-- chats created in the same transaction are announced by add_to_chat_trigger,
+-- deleted chats by delete_chat_trigger
+CREATE OR REPLACE FUNCTION chat_members_changed()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHAT RECORD;
+    OLD_MEMBERS bigint[];
+BEGIN
+    FOR CHAT IN
+        SELECT c.*,
+               ARRAY(SELECT user_id FROM chat_members WHERE chat_id = c.id ORDER BY user_id) AS members,
+               ARRAY(SELECT user_id FROM changed WHERE chat_id = c.id) AS changed_members
+        FROM chats c
+        WHERE c.id IN (SELECT chat_id FROM changed)
+          AND c.created_at < NOW()
+        LOOP
+            RAISE NOTICE 'chat_members_changed: % %', TG_OP, CHAT.id;
+            IF TG_OP = 'INSERT' THEN
+                OLD_MEMBERS := ARRAY(SELECT unnest(CHAT.members)
+                                     EXCEPT
+                                     SELECT unnest(CHAT.changed_members)
+                                     ORDER BY 1);
+            ELSE
+                OLD_MEMBERS := ARRAY(SELECT unnest(CHAT.members || CHAT.changed_members) ORDER BY 1);
+            END IF;
+            PERFORM
+                pg_notify('chat_updated', json_build_object(
+                        'op', 'UPDATE',
+                        'old', json_build_object(
+                                'id', CHAT.id,
+                                'ws_id', CHAT.ws_id,
+                                'name', CHAT.name,
+                                'type', CHAT.type,
+                                'members', OLD_MEMBERS,
+                                'created_at', CHAT.created_at
+                               ),
+                        'new', json_build_object(
+                                'id', CHAT.id,
+                                'ws_id', CHAT.ws_id,
+                                'name', CHAT.name,
+                                'type', CHAT.type,
+                                'members', CHAT.members,
+                                'created_at', CHAT.created_at
+                               )
+                                         )::TEXT);
+        END LOOP;
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_members_added_trigger
+    AFTER INSERT
+    ON chat_members
+    REFERENCING NEW TABLE AS changed
+    FOR EACH STATEMENT
+EXECUTE FUNCTION chat_members_changed();
+
+CREATE TRIGGER chat_members_removed_trigger
+    AFTER DELETE
+    ON chat_members
+    REFERENCING OLD TABLE AS changed
+    FOR EACH STATEMENT
+EXECUTE FUNCTION chat_members_changed();
+
+-- if new message added, notify the members of the chat
+CREATE OR REPLACE FUNCTION add_to_message()
+    RETURNS TRIGGER
+AS
+$$
+DECLARE
+    USERS bigint[];
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        RAISE NOTICE 'add_to_message: %', NEW;
+        USERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = NEW.chat_id);
+        PERFORM
+            pg_notify('chat_message_created', json_build_object(
+                    'message', NEW,
+                    'members', USERS
+                                              )::TEXT);
+    END IF;
+    RETURN NEW;
+end;
+$$
+    LANGUAGE plpgsql;
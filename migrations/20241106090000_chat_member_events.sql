@@ -0,0 +1,36 @@
+-- members joined or left a chat, tell the members and the users who were added or removed.
+-- chats created in the same transaction are announced by add_to_chat_trigger,
+-- deleted chats by delete_chat_trigger
+CREATE OR REPLACE FUNCTION chat_members_changed()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHAT RECORD;
+BEGIN
+    FOR CHAT IN
+        SELECT c.*,
+               ARRAY(SELECT user_id FROM chat_members WHERE chat_id = c.id ORDER BY user_id) AS members,
+               ARRAY(SELECT user_id FROM changed WHERE chat_id = c.id ORDER BY user_id) AS changed_members
+        FROM chats c
+        WHERE c.id IN (SELECT chat_id FROM changed)
+          AND c.created_at < NOW()
+        LOOP
+            RAISE NOTICE 'chat_members_changed: % %', TG_OP, CHAT.id;
+            PERFORM
+                pg_notify('chat_members_changed', json_build_object(
+                        'op', TG_OP,
+                        'chat', json_build_object(
+                                'id', CHAT.id,
+                                'ws_id', CHAT.ws_id,
+                                'name', CHAT.name,
+                                'type', CHAT.type,
+                                'members', CHAT.members,
+                                'created_at', CHAT.created_at
+                                ),
+                        'user_ids', CHAT.changed_members
+                                                  )::TEXT);
+        END LOOP;
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
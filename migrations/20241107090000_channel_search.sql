@@ -0,0 +1,3 @@
+-- fuzzy search of public channels
+CREATE INDEX IF NOT EXISTS chats_name_trgm_index ON chats USING gin (name gin_trgm_ops)
+    WHERE type = 'public_channel';
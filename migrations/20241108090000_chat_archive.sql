@@ -0,0 +1,49 @@
+-- archived chats are read-only and hidden from the default chat list,
+-- deleted chats can be restored by an admin until they are purged
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS archived_at timestamptz,
+    ADD COLUMN IF NOT EXISTS deleted_at  timestamptz;
+
+CREATE INDEX IF NOT EXISTS chats_deleted_at_index ON chats (deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- the purge removes the messages together with the chat
+ALTER TABLE messages
+    DROP CONSTRAINT IF EXISTS messages_chat_id_fkey,
+    ADD CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats (id) ON DELETE CASCADE;
+
+-- archive and soft delete get their own operation, so clients can tell them from other updates
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    TARGET_ID bigint;
+    MEMBERS bigint[];
+    OPERATION text := TG_OP;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        TARGET_ID := OLD.id;
+    ELSE
+        TARGET_ID := NEW.id;
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        IF (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.deleted_at IS NULL THEN 'RESTORE' ELSE 'SOFT_DELETE' END;
+        ELSIF (OLD.archived_at IS NULL) <> (NEW.archived_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.archived_at IS NULL THEN 'UNARCHIVE' ELSE 'ARCHIVE' END;
+        END IF;
+    END IF;
+    RAISE NOTICE 'add_to_chat: % %', OPERATION, TARGET_ID;
+    MEMBERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = TARGET_ID ORDER BY user_id);
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', OPERATION,
+                'old', to_jsonb(OLD) || jsonb_build_object('members', MEMBERS),
+                'new', to_jsonb(NEW) || jsonb_build_object('members', MEMBERS)
+                                 )::TEXT);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
@@ -0,0 +1,53 @@
+-- sorted member ids of a chat without a name, e.g. '1,2'.
+-- there is one such chat per member set, creating it again returns the existing one
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS member_key TEXT;
+
+-- the oldest chat keeps the key when the same members already have several
+UPDATE chats c
+SET member_key = k.member_key
+FROM (SELECT DISTINCT ON (ws_id, member_key) id, member_key
+      FROM (SELECT c.id,
+                   c.ws_id,
+                   array_to_string(ARRAY(SELECT user_id
+                                         FROM chat_members
+                                         WHERE chat_id = c.id
+                                         ORDER BY user_id), ',') AS member_key
+            FROM chats c
+            WHERE c.name IS NULL
+              AND c.deleted_at IS NULL) t
+      ORDER BY ws_id, member_key, id) k
+WHERE c.id = k.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_member_key_index ON chats (ws_id, member_key)
+    WHERE member_key IS NOT NULL AND deleted_at IS NULL;
+
+-- a chat which lost members no longer stands for a member set
+CREATE OR REPLACE FUNCTION clear_member_key()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    UPDATE chats
+    SET member_key = NULL
+    WHERE member_key IS NOT NULL
+      AND id IN (SELECT chat_id FROM changed);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER clear_member_key_trigger
+    AFTER DELETE
+    ON chat_members
+    REFERENCING OLD TABLE AS changed
+    FOR EACH STATEMENT
+EXECUTE FUNCTION clear_member_key();
+
+-- the key is bookkeeping, clients are not told about it
+DROP TRIGGER IF EXISTS update_chat_trigger ON chats;
+
+CREATE TRIGGER update_chat_trigger
+    AFTER UPDATE OF name, type, archived_at, deleted_at
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
@@ -0,0 +1,89 @@
+-- who may post in a chat, announcement channels are for admins only
+CREATE TYPE chat_posting_policy AS ENUM ('everyone', 'admins');
+-- what members are notified about unless they chose otherwise
+CREATE TYPE notification_level AS ENUM ('all', 'mentions', 'none');
+
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS topic              VARCHAR(250),
+    ADD COLUMN IF NOT EXISTS description        VARCHAR(1000),
+    ADD COLUMN IF NOT EXISTS posting_policy     chat_posting_policy NOT NULL DEFAULT 'everyone',
+    ADD COLUMN IF NOT EXISTS members_can_invite BOOLEAN             NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS notification_level notification_level  NOT NULL DEFAULT 'all';
+
+-- changed settings get their own operation
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    TARGET_ID bigint;
+    MEMBERS bigint[];
+    OPERATION text := TG_OP;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        TARGET_ID := OLD.id;
+    ELSE
+        TARGET_ID := NEW.id;
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        IF (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.deleted_at IS NULL THEN 'RESTORE' ELSE 'SOFT_DELETE' END;
+        ELSIF (OLD.archived_at IS NULL) <> (NEW.archived_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.archived_at IS NULL THEN 'UNARCHIVE' ELSE 'ARCHIVE' END;
+        ELSIF (OLD.topic, OLD.description, OLD.posting_policy, OLD.members_can_invite, OLD.notification_level)
+            IS DISTINCT FROM
+              (NEW.topic, NEW.description, NEW.posting_policy, NEW.members_can_invite, NEW.notification_level) THEN
+            OPERATION := 'SETTINGS';
+        END IF;
+    END IF;
+    RAISE NOTICE 'add_to_chat: % %', OPERATION, TARGET_ID;
+    MEMBERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = TARGET_ID ORDER BY user_id);
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', OPERATION,
+                'old', to_jsonb(OLD) || jsonb_build_object('members', MEMBERS),
+                'new', to_jsonb(NEW) || jsonb_build_object('members', MEMBERS)
+                                 )::TEXT);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+-- the chat of a membership change carries every column, settings included
+CREATE OR REPLACE FUNCTION chat_members_changed()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHAT RECORD;
+BEGIN
+    FOR CHAT IN
+        SELECT c.*,
+               ARRAY(SELECT user_id FROM chat_members WHERE chat_id = c.id ORDER BY user_id) AS members,
+               ARRAY(SELECT user_id FROM changed WHERE chat_id = c.id ORDER BY user_id) AS changed_members
+        FROM chats c
+        WHERE c.id IN (SELECT chat_id FROM changed)
+          AND c.created_at < NOW()
+        LOOP
+            RAISE NOTICE 'chat_members_changed: % %', TG_OP, CHAT.id;
+            PERFORM
+                pg_notify('chat_members_changed', json_build_object(
+                        'op', TG_OP,
+                        'chat', to_jsonb(CHAT) - 'changed_members',
+                        'user_ids', CHAT.changed_members
+                                                  )::TEXT);
+        END LOOP;
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS update_chat_trigger ON chats;
+
+CREATE TRIGGER update_chat_trigger
+    AFTER UPDATE OF name, type, archived_at, deleted_at, topic, description, posting_policy,
+        members_can_invite, notification_level
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
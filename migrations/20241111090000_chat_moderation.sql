@@ -0,0 +1,127 @@
+-- muted members still read the chat, they post again once the time passed
+ALTER TABLE chat_members
+    ADD COLUMN IF NOT EXISTS muted_until timestamptz;
+
+-- locked chats only take messages from moderators and owners
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS locked_at timestamptz;
+
+-- the longest standing member owns the chat, both members of a single chat are equal
+UPDATE chat_members m
+SET role = 'owner'
+FROM (SELECT DISTINCT ON (m.chat_id) m.chat_id, m.user_id
+      FROM chat_members m
+               JOIN chats c ON c.id = m.chat_id
+      WHERE c.type <> 'single'
+      ORDER BY m.chat_id, m.joined_at, m.user_id) o
+WHERE m.chat_id = o.chat_id
+  AND m.user_id = o.user_id
+  AND NOT EXISTS(SELECT 1 FROM chat_members x WHERE x.chat_id = m.chat_id AND x.role = 'owner');
+
+-- locking the chat gets its own operation
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    TARGET_ID bigint;
+    MEMBERS bigint[];
+    OPERATION text := TG_OP;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        TARGET_ID := OLD.id;
+    ELSE
+        TARGET_ID := NEW.id;
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        IF (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.deleted_at IS NULL THEN 'RESTORE' ELSE 'SOFT_DELETE' END;
+        ELSIF (OLD.archived_at IS NULL) <> (NEW.archived_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.archived_at IS NULL THEN 'UNARCHIVE' ELSE 'ARCHIVE' END;
+        ELSIF (OLD.locked_at IS NULL) <> (NEW.locked_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.locked_at IS NULL THEN 'UNLOCK' ELSE 'LOCK' END;
+        ELSIF (OLD.topic, OLD.description, OLD.posting_policy, OLD.members_can_invite, OLD.notification_level)
+            IS DISTINCT FROM
+              (NEW.topic, NEW.description, NEW.posting_policy, NEW.members_can_invite, NEW.notification_level) THEN
+            OPERATION := 'SETTINGS';
+        END IF;
+    END IF;
+    RAISE NOTICE 'add_to_chat: % %', OPERATION, TARGET_ID;
+    MEMBERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = TARGET_ID ORDER BY user_id);
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', OPERATION,
+                'old', to_jsonb(OLD) || jsonb_build_object('members', MEMBERS),
+                'new', to_jsonb(NEW) || jsonb_build_object('members', MEMBERS)
+                                 )::TEXT);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS update_chat_trigger ON chats;
+
+CREATE TRIGGER update_chat_trigger
+    AFTER UPDATE OF name, type, archived_at, deleted_at, locked_at, topic, description, posting_policy,
+        members_can_invite, notification_level
+    ON chats
+    FOR EACH ROW
+EXECUTE FUNCTION add_to_chat();
+
+-- a member got another role or was muted, the members of the chat are told.
+-- roles given while the chat is created are announced with the chat
+CREATE OR REPLACE FUNCTION chat_member_updated()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF EXISTS(SELECT 1 FROM chats WHERE id = NEW.chat_id AND created_at >= NOW()) THEN
+        RETURN NULL;
+    END IF;
+    RAISE NOTICE 'chat_member_updated: % %', NEW.chat_id, NEW.user_id;
+    PERFORM
+        pg_notify('chat_member_updated', json_build_object(
+                'op', CASE WHEN OLD.role IS DISTINCT FROM NEW.role THEN 'ROLE' ELSE 'MUTE' END,
+                'chat_id', NEW.chat_id,
+                'user_id', NEW.user_id,
+                'role', NEW.role,
+                'muted_until', NEW.muted_until,
+                'members', ARRAY(SELECT user_id FROM chat_members WHERE chat_id = NEW.chat_id ORDER BY user_id)
+                                         )::TEXT);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_member_updated_trigger
+    AFTER UPDATE OF role, muted_until
+    ON chat_members
+    FOR EACH ROW
+    WHEN (OLD.role IS DISTINCT FROM NEW.role OR OLD.muted_until IS DISTINCT FROM NEW.muted_until)
+EXECUTE FUNCTION chat_member_updated();
+
+-- a message was deleted, messages of purged chats go silently
+CREATE OR REPLACE FUNCTION message_deleted()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF NOT EXISTS(SELECT 1 FROM chats WHERE id = OLD.chat_id AND deleted_at IS NULL) THEN
+        RETURN NULL;
+    END IF;
+    RAISE NOTICE 'message_deleted: %', OLD.id;
+    PERFORM
+        pg_notify('chat_message_deleted', json_build_object(
+                'message', OLD,
+                'members', ARRAY(SELECT user_id FROM chat_members WHERE chat_id = OLD.chat_id)
+                                          )::TEXT);
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE TRIGGER message_deleted_trigger
+    AFTER DELETE
+    ON messages
+    FOR EACH ROW
+EXECUTE FUNCTION message_deleted();
@@ -0,0 +1,18 @@
+-- a notification carries at most 8000 bytes, the notify server looks up who is told
+CREATE OR REPLACE FUNCTION member_status_changed()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF (OLD.deactivated_at IS NULL) <> (NEW.deactivated_at IS NULL) THEN
+        RAISE NOTICE 'member_status_changed: %', NEW;
+        PERFORM
+            pg_notify('member_status_changed', json_build_object(
+                    'ws_id', NEW.ws_id,
+                    'user_id', NEW.user_id,
+                    'active', NEW.deactivated_at IS NULL
+                                               )::TEXT);
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
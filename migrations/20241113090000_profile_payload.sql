@@ -0,0 +1,11 @@
+-- a notification carries at most 8000 bytes, the notify server reads the profile and who is told
+CREATE OR REPLACE FUNCTION profile_updated()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE NOTICE 'profile_updated: %', NEW.id;
+    PERFORM pg_notify('profile_updated', json_build_object('id', NEW.id)::TEXT);
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
@@ -0,0 +1,92 @@
+-- the chat of a notification has the columns the api returns, bookkeeping like member_key stays out
+CREATE OR REPLACE FUNCTION chat_payload(c chats, members bigint[])
+    RETURNS jsonb AS
+$$
+SELECT jsonb_build_object(
+               'id', c.id,
+               'ws_id', c.ws_id,
+               'name', c.name,
+               'type', c.type,
+               'created_at', c.created_at,
+               'archived_at', c.archived_at,
+               'deleted_at', c.deleted_at,
+               'locked_at', c.locked_at,
+               'topic', c.topic,
+               'description', c.description,
+               'posting_policy', c.posting_policy,
+               'members_can_invite', c.members_can_invite,
+               'notification_level', c.notification_level,
+               'members', members
+       );
+$$
+    LANGUAGE sql
+    STRICT;
+
+CREATE OR REPLACE FUNCTION add_to_chat()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    TARGET_ID bigint;
+    MEMBERS bigint[];
+    OPERATION text := TG_OP;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        TARGET_ID := OLD.id;
+    ELSE
+        TARGET_ID := NEW.id;
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        IF (OLD.deleted_at IS NULL) <> (NEW.deleted_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.deleted_at IS NULL THEN 'RESTORE' ELSE 'SOFT_DELETE' END;
+        ELSIF (OLD.archived_at IS NULL) <> (NEW.archived_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.archived_at IS NULL THEN 'UNARCHIVE' ELSE 'ARCHIVE' END;
+        ELSIF (OLD.locked_at IS NULL) <> (NEW.locked_at IS NULL) THEN
+            OPERATION := CASE WHEN NEW.locked_at IS NULL THEN 'UNLOCK' ELSE 'LOCK' END;
+        ELSIF (OLD.topic, OLD.description, OLD.posting_policy, OLD.members_can_invite, OLD.notification_level)
+            IS DISTINCT FROM
+              (NEW.topic, NEW.description, NEW.posting_policy, NEW.members_can_invite, NEW.notification_level) THEN
+            OPERATION := 'SETTINGS';
+        END IF;
+    END IF;
+    RAISE NOTICE 'add_to_chat: % %', OPERATION, TARGET_ID;
+    MEMBERS := ARRAY(SELECT user_id FROM chat_members WHERE chat_id = TARGET_ID ORDER BY user_id);
+    PERFORM
+        pg_notify('chat_updated', json_build_object(
+                'op', OPERATION,
+                'old', chat_payload(OLD, MEMBERS),
+                'new', chat_payload(NEW, MEMBERS)
+                                 )::TEXT);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$
+    LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION chat_members_changed()
+    RETURNS TRIGGER AS
+$$
+DECLARE
+    CHANGE RECORD;
+BEGIN
+    FOR CHANGE IN
+        SELECT c AS chat,
+               ARRAY(SELECT user_id FROM chat_members WHERE chat_id = c.id ORDER BY user_id) AS members,
+               ARRAY(SELECT user_id FROM changed WHERE chat_id = c.id ORDER BY user_id) AS changed_members
+        FROM chats c
+        WHERE c.id IN (SELECT chat_id FROM changed)
+          AND c.created_at < NOW()
+        LOOP
+            RAISE NOTICE 'chat_members_changed: % %', TG_OP, (CHANGE.chat).id;
+            PERFORM
+                pg_notify('chat_members_changed', json_build_object(
+                        'op', TG_OP,
+                        'chat', chat_payload(CHANGE.chat, CHANGE.members),
+                        'user_ids', CHANGE.changed_members
+                                                  )::TEXT);
+        END LOOP;
+    RETURN NULL;
+END;
+$$
+    LANGUAGE plpgsql;
@@ -3,6 +3,7 @@ use chat_core::utils::notify_server_path;
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs::File;
+use std::path::PathBuf;
 use tracing::info;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,7 +24,25 @@ pub struct ServerConfig {
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthConfig {
-    pub pk: String,
+    /// a single verification key, kept for configs written before key rotation
+    #[serde(default)]
+    pub pk: Option<String>,
+    /// key id of `pk`, defaults to the thumbprint of the public key
+    #[serde(default)]
+    pub kid: Option<String>,
+    /// local JWKS document
+    #[serde(default)]
+    pub jwks_file: Option<PathBuf>,
+    /// JWKS document published by chat_server, e.g. http://localhost:6688/.well-known/jwks.json
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    /// how often the JWKS document is fetched again, in seconds
+    #[serde(default = "default_jwks_refresh_interval")]
+    pub jwks_refresh_interval: u64,
+}
+
+fn default_jwks_refresh_interval() -> u64 {
+    300
 }
 
 impl AppConfig {
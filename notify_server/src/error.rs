@@ -13,6 +13,10 @@ pub enum AppError {
     JWTError(#[from] jwt_simple::Error),
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("sql error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("token has been revoked")]
+    TokenRevoked,
 }
 impl ErrOutput {
     pub(crate) fn new(error: impl Into<String>) -> Self {
@@ -26,6 +30,8 @@ impl IntoResponse for AppError {
         let state = match &self {
             Self::JWTError(_) => StatusCode::FORBIDDEN,
             Self::IoError(_) => StatusCode::BAD_REQUEST,
+            Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TokenRevoked => StatusCode::UNAUTHORIZED,
         };
         (state, Json(ErrOutput::new(self.to_string()))).into_response()
     }
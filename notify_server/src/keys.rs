@@ -0,0 +1,67 @@
+use crate::config::AuthConfig;
+use crate::AppState;
+use anyhow::{bail, Context};
+use chat_core::utils::{DecodingKey, Jwks};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// build the verification keyring from every configured source
+pub async fn load_decoding_key(config: &AuthConfig) -> anyhow::Result<DecodingKey> {
+    let mut keys: Option<DecodingKey> = None;
+    let mut add = |dk: DecodingKey| match keys.as_mut() {
+        Some(keys) => keys.merge(dk),
+        None => keys = Some(dk),
+    };
+    if let Some(pk) = &config.pk {
+        let dk = match &config.kid {
+            Some(kid) => DecodingKey::load_with_kid(pk, kid),
+            None => DecodingKey::load(pk),
+        }
+        .context("Failed to load auth pk")?;
+        add(dk);
+    }
+    if let Some(path) = &config.jwks_file {
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read jwks file {:?}", path))?;
+        let jwks: Jwks = serde_json::from_str(&data)?;
+        add(DecodingKey::from_jwks(&jwks)?);
+    }
+    if let Some(url) = &config.jwks_url {
+        add(fetch_jwks(url).await?);
+    }
+    match keys {
+        Some(keys) => Ok(keys),
+        None => bail!("auth config needs one of pk, jwks_file or jwks_url"),
+    }
+}
+
+async fn fetch_jwks(url: &str) -> anyhow::Result<DecodingKey> {
+    let jwks: Jwks = reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .with_context(|| format!("Failed to fetch jwks from {}", url))?;
+    DecodingKey::from_jwks(&jwks)
+}
+
+/// reload the keyring periodically so keys added by a rotation are picked up
+pub fn spawn_jwks_refresh(state: AppState) {
+    if state.config.auth.jwks_url.is_none() && state.config.auth.jwks_file.is_none() {
+        return;
+    }
+    let interval = Duration::from_secs(state.config.auth.jwks_refresh_interval.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match load_decoding_key(&state.config.auth).await {
+                Ok(dk) => {
+                    info!("Reloaded verification keys: {:?}", dk.kids());
+                    *state.dk.write().expect("dk lock poisoned") = dk;
+                }
+                Err(e) => warn!("Failed to reload verification keys: {}", e),
+            }
+        }
+    });
+}
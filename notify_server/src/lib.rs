@@ -1,5 +1,6 @@
 mod config;
 mod error;
+mod keys;
 mod notif;
 mod sse;
 
@@ -10,16 +11,20 @@ use axum::Router;
 use std::fmt;
 use std::fmt::Formatter;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use dashmap::DashMap;
 
 pub use crate::config::AppConfig;
-use chat_core::middlewares::{verify_token, TokenVerify};
-use chat_core::utils::DecodingKey;
-use chat_core::User;
+use anyhow::Context;
+use chat_core::middlewares::{verify_token, RequireScopeLayer, TokenVerify};
+use chat_core::utils::{is_token_revoked, DecodingKey, Scope, UserClaims};
 pub use error::AppError;
-pub use notif::{setup_pg_listener, AppEvent};
+use keys::{load_decoding_key, spawn_jwks_refresh};
+pub use notif::{
+    setup_pg_listener, AppEvent, ChatMemberUpdate, MemberChange, MemberStatus, SessionRevoked,
+};
+use sqlx::PgPool;
 use sse::sse_handler;
 use tokio::sync::broadcast;
 pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
@@ -27,18 +32,23 @@ pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
 pub struct AppState(Arc<AppStateInner>);
 #[allow(unused)]
 pub struct AppStateInner {
-    pub dk: DecodingKey,
+    pub dk: RwLock<DecodingKey>,
     pub users: UserMap,
     pub config: AppConfig,
+    pub pool: PgPool,
 }
 
 const INDEX_HTML: &str = include_str!("../index.html");
 
 pub async fn get_router(config: AppConfig) -> anyhow::Result<(Router, AppState)> {
-    let state = AppState::new(config);
+    let state = AppState::try_new(config).await?;
     setup_pg_listener(state.clone()).await.unwrap();
+    spawn_jwks_refresh(state.clone());
     let app = Router::new()
-        .route("/events", get(sse_handler))
+        .route(
+            "/events",
+            get(sse_handler).route_layer(RequireScopeLayer::new(Scope::EventsRead)),
+        )
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .route("/", get(index_handler))
         .with_state(state.clone());
@@ -55,13 +65,17 @@ impl TokenVerify for AppState {
     /// 定义验证过程中可能产生的错误类型为 AppError。
     type Error = AppError;
 
-    /// 验证给定的 token，并返回对应的 User 或者错误。
+    /// 验证给定的 token，并返回对应的 UserClaims 或者错误。
     ///
     /// 使用 self.dk (可能是某个验证服务或数据结构) 来验证 token。
-    /// 如果 token 有效，此函数将返回与该 token 关联的 User 对象。
-    /// 如果 token 验证失败，将返回一个 AppError 错误。
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
-        Ok(self.dk.verify(token)?)
+    /// 如果 token 有效且没有被注销，此函数将返回与该 token 关联的 UserClaims。
+    /// 如果 token 验证失败或已被注销，将返回一个 AppError 错误。
+    async fn verify(&self, token: &str) -> Result<UserClaims, Self::Error> {
+        let claims = self.dk.read().expect("dk lock poisoned").verify(token)?;
+        if is_token_revoked(&self.pool, &claims).await? {
+            return Err(AppError::TokenRevoked);
+        }
+        Ok(claims)
     }
 }
 impl Deref for AppState {
@@ -71,10 +85,18 @@ impl Deref for AppState {
     }
 }
 impl AppState {
-    fn new(config: AppConfig) -> AppState {
-        let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load auth pk");
+    async fn try_new(config: AppConfig) -> anyhow::Result<AppState> {
+        let dk = RwLock::new(load_decoding_key(&config.auth).await?);
+        let pool = PgPool::connect(&config.server.db_url)
+            .await
+            .context("connect to db failed")?;
         let users = Arc::new(DashMap::new());
-        AppState(Arc::new(AppStateInner { dk, users, config }))
+        Ok(AppState(Arc::new(AppStateInner {
+            dk,
+            users,
+            config,
+            pool,
+        })))
     }
 }
 impl fmt::Debug for AppStateInner {
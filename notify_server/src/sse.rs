@@ -5,7 +5,7 @@ use axum::{
     Extension,
 };
 // use axum_extra::{headers, TypedHeader};
-use chat_core::User;
+use chat_core::utils::UserClaims;
 use futures::Stream;
 use std::{convert::Infallible, time::Duration};
 use tokio::sync::broadcast;
@@ -18,18 +18,18 @@ const CHANNEL_CAPACITY: usize = 100;
 /// 处理服务器发送事件 (SSE) 的请求
 ///
 /// # 参数
-/// - `Extension(user)`：从请求中提取的用户信息
+/// - `Extension(claims)`：从请求中提取的 token 信息
 /// - `State(state)`：应用状态，包含用户和广播通道的信息
 /// # 返回
-/// 返回一个SSE响应，包含一个生成AppEvent事件的流
+/// 返回一个SSE响应，包含一个生成AppEvent事件的流, 会话被注销时流会被关闭
 pub(crate) async fn sse_handler(
-    Extension(user): Extension<User>,
+    Extension(claims): Extension<UserClaims>,
     State(state): State<AppState>,
     // TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     // info!("`{}` connected", user_agent.as_str());
     // 获取用户ID并转换为u64类型
-    let user_id = user.id as u64;
+    let user_id = claims.user.id as u64;
     // 获取用户状态
     let user = &state.users;
     // 根据用户ID获取广播接收器
@@ -43,31 +43,30 @@ pub(crate) async fn sse_handler(
     };
     // 记录用户订阅事件
     info!("User {} subscribed ", user_id);
-    // 创建广播流并进行过滤和映射处理
-    let mut stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        // 根据事件类型设置事件名称
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-            AppEvent::UpdateChatName(_) => "UpdateChatName",
-        };
-        // 序列化事件数据
-        let v = serde_json::to_string(&v).expect("Failed to serialize event");
-        // 创建并返回事件
-        Ok(Event::default().data(v).event(name))
-    });
+    // 创建广播流并进行过滤
+    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok());
     // 创建一个持续生成事件的流
     let stream = async_stream::stream! {
          let _guard = Guard {
             state,
             user_id
         };
+        // 在 guard 之后声明, 保证 guard 释放时接收端已经被释放
+        let mut stream = stream;
         loop {
             match stream.next().await{
                 Some(app)=>{
-                    yield app;
+                    // 当前会话被注销或用户被停用后, 发送最后一个事件并关闭流
+                    let revoked = match app.as_ref() {
+                        AppEvent::SessionRevoked(v) => v.matches(&claims),
+                        AppEvent::UserDeactivated(v) => v.matches(&claims),
+                        _ => false,
+                    };
+                    yield Ok(to_sse_event(&app));
+                    if revoked {
+                        info!("User {} session revoked", user_id);
+                        break;
+                    }
                 },
                 _=>{
                     info!("管开了");
@@ -85,6 +84,37 @@ pub(crate) async fn sse_handler(
     )
 }
 
+fn to_sse_event(v: &AppEvent) -> Event {
+    // 根据事件类型设置事件名称
+    let name = match v {
+        AppEvent::NewChat(_) => "NewChat",
+        AppEvent::AddToChat(_) => "AddToChat",
+        AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+        AppEvent::NewMessage(_) => "NewMessage",
+        AppEvent::UpdateChatName(_) => "UpdateChatName",
+        AppEvent::SessionRevoked(_) => "SessionRevoked",
+        AppEvent::UserDeactivated(_) => "UserDeactivated",
+        AppEvent::UserReactivated(_) => "UserReactivated",
+        AppEvent::ProfileUpdated(_) => "ProfileUpdated",
+        AppEvent::MemberAdded(_) => "MemberAdded",
+        AppEvent::MemberRemoved(_) => "MemberRemoved",
+        AppEvent::ChatArchived(_) => "ChatArchived",
+        AppEvent::ChatUnarchived(_) => "ChatUnarchived",
+        AppEvent::ChatDeleted(_) => "ChatDeleted",
+        AppEvent::ChatRestored(_) => "ChatRestored",
+        AppEvent::ChatSettingsUpdated(_) => "ChatSettingsUpdated",
+        AppEvent::ChatLocked(_) => "ChatLocked",
+        AppEvent::ChatUnlocked(_) => "ChatUnlocked",
+        AppEvent::MemberRoleChanged(_) => "MemberRoleChanged",
+        AppEvent::MemberMuted(_) => "MemberMuted",
+        AppEvent::MessageDeleted(_) => "MessageDeleted",
+    };
+    // 序列化事件数据
+    let v = serde_json::to_string(v).expect("Failed to serialize event");
+    // 创建并返回事件
+    Event::default().data(v).event(name)
+}
+
 /// 用于在用户断开连接时清理资源的结构体
 #[derive(Debug)]
 struct Guard {
@@ -94,12 +124,16 @@ struct Guard {
     user_id: u64,
 }
 
-/// 当Guard实例被丢弃时，自动移除用户ID对应的广播通道
+/// 当Guard实例被丢弃时，如果没有其他连接, 自动移除用户ID对应的广播通道
 impl Drop for Guard {
     fn drop(&mut self) {
         info!("{:?}", self);
-        // 删除用户ID对应的广播通道
-        if let Some(removed_value) = self.state.users.remove(&self.user_id) {
+        // 删除用户ID对应的广播通道, 同一个用户的其他会话仍然在使用时保留
+        if let Some(removed_value) = self
+            .state
+            .users
+            .remove_if(&self.user_id, |_, tx| tx.receiver_count() == 0)
+        {
             info!("成功删除了键\"key2\"，对应的值为: {:?}", removed_value);
         } else {
             info!("键\"key2\"不存在，无法删除");